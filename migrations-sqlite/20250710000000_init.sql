@@ -0,0 +1,50 @@
+-- SQLite用スキーマ(migrations と同じ構成を1つにまとめたもの)
+CREATE TABLE todos
+(
+    id INTEGER PRIMARY KEY,
+    user_id TEXT NOT NULL DEFAULT 'default',
+    text TEXT NOT NULL,
+    completed BOOLEAN NOT NULL DEFAULT false,
+    parent_id INTEGER REFERENCES todos (id) ON DELETE CASCADE,
+    due_date TEXT,
+    priority INTEGER
+);
+
+CREATE INDEX todos_user_id_idx ON todos (user_id);
+CREATE INDEX todos_parent_id_idx ON todos (parent_id);
+CREATE INDEX todos_due_date_idx ON todos (due_date);
+
+CREATE TABLE labels
+(
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE todo_labels
+(
+    id INTEGER PRIMARY KEY,
+    todo_id INTEGER NOT NULL REFERENCES todos (id) DEFERRABLE INITIALLY DEFERRED,
+    label_id INTEGER NOT NULL REFERENCES labels (id) DEFERRABLE INITIALLY DEFERRED
+);
+
+-- 作成日時・削除日時はUNIX時間(秒)
+CREATE TABLE idempotency_keys
+(
+    user_id TEXT NOT NULL DEFAULT 'default',
+    key TEXT NOT NULL,
+    todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    PRIMARY KEY (user_id, key)
+);
+
+CREATE TABLE deleted_todos
+(
+    id INTEGER PRIMARY KEY,
+    user_id TEXT NOT NULL DEFAULT 'default',
+    text TEXT NOT NULL,
+    completed BOOLEAN NOT NULL,
+    parent_id INTEGER,
+    due_date TEXT,
+    priority INTEGER,
+    deleted_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
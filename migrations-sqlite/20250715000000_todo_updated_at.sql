@@ -0,0 +1,5 @@
+-- TODOの最終更新日時(ADD COLUMN では現在日時を既定値にできないので後から埋める)
+ALTER TABLE todos ADD COLUMN updated_at TEXT NOT NULL DEFAULT '1970-01-01T00:00:00Z';
+UPDATE todos SET updated_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now');
+ALTER TABLE deleted_todos ADD COLUMN updated_at TEXT NOT NULL DEFAULT '1970-01-01T00:00:00Z';
+UPDATE deleted_todos SET updated_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now');
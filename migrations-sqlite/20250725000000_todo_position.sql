@@ -0,0 +1,3 @@
+-- 兄弟TODOの中での並び順(既存データは0のままID順に並ぶ)
+ALTER TABLE todos ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE deleted_todos ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
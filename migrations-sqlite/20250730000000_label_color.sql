@@ -0,0 +1,2 @@
+-- ラベルの色(#rrggbb、既定はグレー)
+ALTER TABLE labels ADD COLUMN color TEXT NOT NULL DEFAULT '#808080';
@@ -0,0 +1,3 @@
+-- 同じTODOに同じラベルを重複して付けない
+DELETE FROM todo_labels WHERE id NOT IN (SELECT min(id) FROM todo_labels GROUP BY todo_id, label_id);
+CREATE UNIQUE INDEX todo_labels_todo_id_label_id_idx ON todo_labels (todo_id, label_id);
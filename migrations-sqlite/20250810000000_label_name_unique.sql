@@ -0,0 +1,21 @@
+-- ラベル名は大文字小文字を区別しない(既に大文字小文字違いで重複していれば最小IDのラベルにまとめる)
+CREATE TEMPORARY TABLE label_merge AS
+SELECT labels.id AS old_id, keep.id AS new_id
+FROM labels
+JOIN (SELECT lower(name) AS lower_name, min(id) AS id FROM labels GROUP BY lower(name)) AS keep
+    ON lower(labels.name) = keep.lower_name
+WHERE labels.id <> keep.id;
+
+INSERT INTO todo_labels (todo_id, label_id)
+SELECT DISTINCT todo_labels.todo_id, label_merge.new_id
+FROM todo_labels
+JOIN label_merge ON todo_labels.label_id = label_merge.old_id
+WHERE NOT EXISTS (
+    SELECT 1 FROM todo_labels AS existing
+    WHERE existing.todo_id = todo_labels.todo_id AND existing.label_id = label_merge.new_id
+);
+DELETE FROM todo_labels WHERE label_id IN (SELECT old_id FROM label_merge);
+DELETE FROM labels WHERE id IN (SELECT old_id FROM label_merge);
+DROP TABLE label_merge;
+
+CREATE UNIQUE INDEX labels_lower_name_unique_idx ON labels (lower(name));
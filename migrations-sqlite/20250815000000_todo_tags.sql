@@ -0,0 +1,3 @@
+-- TODOのタグ(配列のJSON文字列、未設定は空配列)
+ALTER TABLE todos ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE deleted_todos ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,5 @@
+-- TODOの完了日時(未完了はNULL。既存の完了済みTODOは最終更新日時で埋める)
+ALTER TABLE todos ADD COLUMN completed_at TEXT;
+UPDATE todos SET completed_at = updated_at WHERE completed;
+ALTER TABLE deleted_todos ADD COLUMN completed_at TEXT;
+UPDATE deleted_todos SET completed_at = updated_at WHERE completed;
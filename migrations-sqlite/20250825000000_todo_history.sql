@@ -0,0 +1,14 @@
+-- TODOの変更履歴(TODOを削除しても残すので外部キーは付けない)
+CREATE TABLE todo_history
+(
+    id INTEGER PRIMARY KEY,
+    todo_id INTEGER NOT NULL,
+    user_id TEXT NOT NULL,
+    change_type TEXT NOT NULL,
+    old_text TEXT,
+    new_text TEXT,
+    completed BOOLEAN NOT NULL,
+    changed_at TEXT NOT NULL
+);
+
+CREATE INDEX todo_history_todo_id_idx ON todo_history (todo_id);
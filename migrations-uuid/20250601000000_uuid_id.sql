@@ -0,0 +1,76 @@
+-- TODOの主キーをUUIDへ移行する(uuid-id機能でビルドする場合のみ適用する)
+-- sqlx migrate run --source migrations-uuid
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+-- 冪等キー・削除済みTODOは一時的なデータなので作り直す
+DROP TABLE IF EXISTS idempotency_keys;
+DROP TABLE IF EXISTS deleted_todos;
+-- 変更履歴は削除済みTODOの分を付け替えられないので作り直す
+DROP TABLE IF EXISTS todo_history;
+
+ALTER TABLE todos ADD COLUMN uuid UUID NOT NULL DEFAULT gen_random_uuid();
+
+-- 関連テーブルの参照先を付け替える
+ALTER TABLE todo_labels ADD COLUMN todo_uuid UUID;
+UPDATE todo_labels SET todo_uuid = todos.uuid FROM todos WHERE todo_labels.todo_id = todos.id;
+ALTER TABLE todo_labels DROP COLUMN todo_id;
+ALTER TABLE todo_labels RENAME COLUMN todo_uuid TO todo_id;
+ALTER TABLE todo_labels ALTER COLUMN todo_id SET NOT NULL;
+
+-- 親子関係の参照先を付け替える
+ALTER TABLE todos ADD COLUMN parent_uuid UUID;
+UPDATE todos SET parent_uuid = parent.uuid FROM todos parent WHERE todos.parent_id = parent.id;
+ALTER TABLE todos DROP COLUMN parent_id;
+ALTER TABLE todos RENAME COLUMN parent_uuid TO parent_id;
+
+-- 主キーを差し替える
+ALTER TABLE todos DROP COLUMN id;
+ALTER TABLE todos RENAME COLUMN uuid TO id;
+ALTER TABLE todos ALTER COLUMN id DROP DEFAULT;
+ALTER TABLE todos ADD PRIMARY KEY (id);
+ALTER TABLE todo_labels
+    ADD FOREIGN KEY (todo_id) REFERENCES todos (id) DEFERRABLE INITIALLY DEFERRED;
+ALTER TABLE todos
+    ADD FOREIGN KEY (parent_id) REFERENCES todos (id) ON DELETE CASCADE;
+
+CREATE TABLE idempotency_keys
+(
+    user_id TEXT NOT NULL DEFAULT 'default',
+    key TEXT NOT NULL,
+    todo_id UUID NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, key)
+);
+
+CREATE TABLE deleted_todos
+(
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL DEFAULT 'default',
+    text TEXT NOT NULL,
+    completed BOOLEAN NOT NULL,
+    parent_id UUID,
+    due_date TIMESTAMPTZ,
+    priority INTEGER,
+    recurrence TEXT,
+    position INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    completed_at TIMESTAMPTZ,
+    deleted_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE todo_history
+(
+    id BIGSERIAL PRIMARY KEY,
+    todo_id UUID NOT NULL,
+    user_id TEXT NOT NULL,
+    change_type TEXT NOT NULL,
+    old_text TEXT,
+    new_text TEXT,
+    completed BOOLEAN NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX todo_history_todo_id_idx ON todo_history (todo_id);
+
+-- todo_id の付け替えで消えた一意制約を作り直す
+CREATE UNIQUE INDEX IF NOT EXISTS todo_labels_todo_id_label_id_idx ON todo_labels (todo_id, label_id);
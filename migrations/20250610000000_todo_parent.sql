@@ -0,0 +1,5 @@
+-- 親TODO(削除時は子TODOも削除する)
+ALTER TABLE todos
+    ADD COLUMN parent_id INTEGER REFERENCES todos (id) ON DELETE CASCADE;
+
+CREATE INDEX todos_parent_id_idx ON todos (parent_id);
@@ -0,0 +1,4 @@
+-- TODOの期限
+ALTER TABLE todos ADD COLUMN due_date TIMESTAMPTZ;
+
+CREATE INDEX todos_due_date_idx ON todos (due_date);
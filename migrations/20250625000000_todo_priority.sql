@@ -0,0 +1,2 @@
+-- TODOの優先度(未設定はNULL)
+ALTER TABLE todos ADD COLUMN priority INTEGER;
@@ -0,0 +1,11 @@
+-- 削除取り消し用に直近削除したTODOを保持する
+CREATE TABLE deleted_todos
+(
+    id INTEGER PRIMARY KEY,
+    text TEXT NOT NULL,
+    completed BOOLEAN NOT NULL,
+    parent_id INTEGER,
+    due_date TIMESTAMPTZ,
+    priority INTEGER,
+    deleted_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
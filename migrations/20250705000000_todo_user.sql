@@ -0,0 +1,10 @@
+-- TODOの所有ユーザー(既存データは既定ユーザーのものとする)
+ALTER TABLE todos ADD COLUMN user_id TEXT NOT NULL DEFAULT 'default';
+CREATE INDEX todos_user_id_idx ON todos (user_id);
+
+ALTER TABLE deleted_todos ADD COLUMN user_id TEXT NOT NULL DEFAULT 'default';
+
+-- 冪等キーはユーザーごとに持つ
+ALTER TABLE idempotency_keys ADD COLUMN user_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE idempotency_keys DROP CONSTRAINT idempotency_keys_pkey;
+ALTER TABLE idempotency_keys ADD PRIMARY KEY (user_id, key);
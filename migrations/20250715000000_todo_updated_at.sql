@@ -0,0 +1,3 @@
+-- TODOの最終更新日時(既存データは適用時点の日時にする)
+ALTER TABLE todos ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE deleted_todos ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,3 @@
+-- TODOの繰り返し(daily・weekly・monthly、未設定はNULL)
+ALTER TABLE todos ADD COLUMN recurrence TEXT;
+ALTER TABLE deleted_todos ADD COLUMN recurrence TEXT;
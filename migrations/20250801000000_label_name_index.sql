@@ -0,0 +1,2 @@
+-- ラベル名の前方一致検索(大文字小文字を区別しない)用
+CREATE INDEX labels_lower_name_idx ON labels (lower(name) text_pattern_ops);
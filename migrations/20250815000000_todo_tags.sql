@@ -0,0 +1,3 @@
+-- TODOのタグ(ラベルより手軽な文字列の付箋、未設定は空配列)
+ALTER TABLE todos ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE deleted_todos ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,14 @@
+-- TODOの変更履歴(TODOを削除しても残すので外部キーは付けない)
+CREATE TABLE todo_history
+(
+    id BIGSERIAL PRIMARY KEY,
+    todo_id INTEGER NOT NULL,
+    user_id TEXT NOT NULL,
+    change_type TEXT NOT NULL,
+    old_text TEXT,
+    new_text TEXT,
+    completed BOOLEAN NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX todo_history_todo_id_idx ON todo_history (todo_id);
@@ -0,0 +1,163 @@
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+use crate::repositories::todo::Todo;
+
+/// TODO変更イベント
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TodoEvent {
+    Created {
+        todo: Todo,
+    },
+    /// changed は変わった項目名
+    Updated {
+        todo: Todo,
+        changed: Vec<&'static str>,
+    },
+    Deleted {
+        todo: Todo,
+    },
+}
+
+impl TodoEvent {
+    /// 対象のTODO
+    pub fn todo(&self) -> &Todo {
+        match self {
+            Self::Created { todo } | Self::Updated { todo, .. } | Self::Deleted { todo } => todo,
+        }
+    }
+}
+
+/// TODO変更イベントの配信チャネル
+#[derive(Debug, Clone)]
+pub struct TodoEvents {
+    sender: broadcast::Sender<TodoEvent>,
+}
+
+impl TodoEvents {
+    /// new
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(100);
+        Self { sender }
+    }
+
+    /// 配信する(購読者がいなくてもエラーにしない)
+    pub fn publish(&self, event: TodoEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    /// 購読する(購読開始以降のイベントを受け取る)
+    pub fn subscribe(&self) -> broadcast::Receiver<TodoEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// まとめて起きた変更の件数
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename = "bulk")]
+pub struct BulkEvent {
+    pub count: usize,
+}
+
+/// 購読者に送るイベント(短い間に続いた変更は件数だけにまとめる)
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum FeedEvent {
+    Single(TodoEvent),
+    Bulk(BulkEvent),
+}
+
+/// 一括操作などで続けて起きたイベントをまとめる
+/// 間が空いた後のイベントはすぐに送り、そこから window の間に続いたイベントは
+/// 1件ならそのまま、複数なら件数だけの bulk イベントにして window の終わりに送る(window が0ならまとめない)
+pub fn debounce<S>(events: S, window: Duration) -> impl Stream<Item = FeedEvent>
+where
+    S: Stream<Item = TodoEvent> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(16);
+    tokio::spawn(async move {
+        tokio::pin!(events);
+        while let Some(event) = events.next().await {
+            if sender.send(FeedEvent::Single(event)).await.is_err() {
+                return;
+            }
+            if window.is_zero() {
+                continue;
+            }
+
+            let deadline = tokio::time::sleep(window);
+            tokio::pin!(deadline);
+            let mut pending = Vec::new();
+            let mut closed = false;
+            loop {
+                tokio::select! {
+                    _ = &mut deadline => break,
+                    event = events.next() => match event {
+                        Some(event) => pending.push(event),
+                        None => {
+                            closed = true;
+                            break;
+                        }
+                    },
+                }
+            }
+            let feed = match pending.len() {
+                0 => None,
+                1 => pending.pop().map(FeedEvent::Single),
+                count => Some(FeedEvent::Bulk(BulkEvent { count })),
+            };
+            if let Some(feed) = feed {
+                if sender.send(feed).await.is_err() {
+                    return;
+                }
+            }
+            if closed {
+                return;
+            }
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "uuid-id"))]
+mod test {
+    use super::*;
+    use crate::repositories::todo::TodoId;
+
+    /// 単発のイベントはすぐ送り、続けて起きたイベントは件数にまとめる
+    #[tokio::test(start_paused = true)]
+    async fn should_debounce_events() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let feed = debounce(
+            tokio_stream::wrappers::UnboundedReceiverStream::new(receiver),
+            Duration::from_millis(50),
+        );
+        tokio::pin!(feed);
+        let event = |id| TodoEvent::Created {
+            todo: Todo::new(id, "event".to_string()),
+        };
+
+        sender.send(event(1)).unwrap();
+        assert!(
+            matches!(feed.next().await, Some(FeedEvent::Single(event)) if event.todo().id() == TodoId::from(1))
+        );
+        for id in 2..=4 {
+            sender.send(event(id)).unwrap();
+        }
+        let bulk = feed.next().await.unwrap();
+        assert_eq!(
+            serde_json::to_value(&bulk).unwrap(),
+            serde_json::json!({ "type": "bulk", "count": 3 })
+        );
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        sender.send(event(5)).unwrap();
+        assert!(
+            matches!(feed.next().await, Some(FeedEvent::Single(event)) if event.todo().id() == TodoId::from(5))
+        );
+    }
+}
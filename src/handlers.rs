@@ -1 +1,374 @@
+pub mod label;
 pub mod todo;
+
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, FromRequest, Path, RequestParts},
+    http::{
+        header::{ALLOW, RETRY_AFTER},
+        HeaderValue, Request, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    convert::Infallible,
+    env,
+    future::{ready, Ready},
+    sync::OnceLock,
+    task::{Context, Poll},
+};
+use tower::{BoxError, Service};
+use validator::{Validate, ValidationErrors};
+
+use crate::repositories::{
+    is_connection_lost, is_pool_timeout, mark_connection_lost, RepositoryError,
+};
+
+/// 接続プールの混雑時・DBとの接続が切れたときに再試行を待ってもらう秒数
+const RETRY_AFTER_SECS: &str = "1";
+
+/// 一覧・検索で一度に返す最大件数(環境変数 MAX_PAGE_SIZE、既定100)
+pub fn max_page_size() -> i64 {
+    static MAX_PAGE_SIZE: OnceLock<i64> = OnceLock::new();
+    *MAX_PAGE_SIZE.get_or_init(|| {
+        env::var("MAX_PAGE_SIZE")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|size| *size > 0)
+            .unwrap_or(100)
+    })
+}
+
+/// 指定の件数(省略時は default)を最大件数までに切り詰める
+/// 大きすぎる limit はエラーにせず最大件数として扱う
+pub fn clamp_limit(limit: Option<i64>, default: i64) -> i64 {
+    limit.unwrap_or(default).min(max_page_size())
+}
+
+/// リポジトリのエラーをレスポンスにする
+/// NotFound は404、件数の上限超過は403、本文の最大文字数超過はバリデーションエラーと同じ形で422
+/// 接続プールの取得待ちのタイムアウトとDBとの接続切れは Retry-After を付けて503、それ以外はログに出して500
+pub fn repository_error_response(error: anyhow::Error) -> Response {
+    match error.downcast_ref::<RepositoryError>() {
+        Some(RepositoryError::NotFound(_)) => return StatusCode::NOT_FOUND.into_response(),
+        Some(RepositoryError::QuotaExceeded(limit)) => {
+            let body = Json(json!({ "error": "quota exceeded", "limit": limit }));
+            return (StatusCode::FORBIDDEN, body).into_response();
+        }
+        Some(RepositoryError::TextTooLong(_)) => {
+            let errors = vec![FieldError {
+                field: "text".to_string(),
+                message: "Over text length".to_string(),
+            }];
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ValidationErrorBody { errors }),
+            )
+                .into_response();
+        }
+        _ => {}
+    }
+    if is_pool_timeout(&error) {
+        tracing::warn!("database busy: {}", error);
+        return service_unavailable("database busy");
+    }
+    if is_connection_lost(&error) {
+        tracing::warn!("database connection lost: {}", error);
+        mark_connection_lost();
+        return service_unavailable("database unavailable");
+    }
+    tracing::error!("unexpected repository error: {}", error);
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+/// Retry-After を付けた503
+fn service_unavailable(message: &str) -> Response {
+    let body = Json(json!({ "error": message }));
+    let mut res = (StatusCode::SERVICE_UNAVAILABLE, body).into_response();
+    res.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+    res
+}
+
+/// 項目ごとのバリデーションエラー
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    field: String,
+    message: String,
+}
+
+/// バリデーションエラーのレスポンス本文
+#[derive(Debug, Serialize)]
+pub struct ValidationErrorBody {
+    errors: Vec<FieldError>,
+}
+
+/// バリデーションエラーを項目ごとのエラー一覧にして status で返す
+fn validation_error_response(status: StatusCode, errors: ValidationErrors) -> Response {
+    let mut errors: Vec<FieldError> = errors
+        .field_errors()
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            errors.iter().map(move |error| FieldError {
+                field: field.to_string(),
+                message: error
+                    .message
+                    .as_ref()
+                    .map(|message| message.to_string())
+                    .unwrap_or_else(|| error.code.to_string()),
+            })
+        })
+        .collect();
+    // 項目の並びを一定にする
+    errors.sort_by(|a, b| a.field.cmp(&b.field));
+    (status, Json(ValidationErrorBody { errors })).into_response()
+}
+
+/// エラーの原因をたどって最も内側のエラーのメッセージを返す
+fn root_cause(error: &dyn std::error::Error) -> String {
+    let mut error = error;
+    while let Some(source) = error.source() {
+        error = source;
+    }
+    error.to_string()
+}
+
+/// 受け付けない Content-Type の415(本文は { "error": "expected <受け付ける形式>" })
+pub fn unsupported_media_type(expected: &str) -> Response {
+    let body = Json(json!({ "error": format!("expected {}", expected) }));
+    (StatusCode::UNSUPPORTED_MEDIA_TYPE, body).into_response()
+}
+
+/// JSONの本文(バリデーションはしない)
+/// Content-Type が application/json でなければ本文のエラーと区別してJSONの415、JSONとして読めなければ400
+#[derive(Debug)]
+pub struct JsonBody<T>(T);
+#[async_trait]
+impl<T, B> FromRequest<B> for JsonBody<T>
+where
+    T: DeserializeOwned,
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Json(value) =
+            Json::<T>::from_request(req)
+                .await
+                .map_err(|rejection| match rejection {
+                    JsonRejection::MissingJsonContentType(_) => {
+                        unsupported_media_type(mime::APPLICATION_JSON.as_ref())
+                    }
+                    rejection => {
+                        // どの項目が読めなかったかは serde のエラーにしか無いので原因をたどって返す
+                        let message = format!("Json parse error: [{}]", root_cause(&rejection));
+                        (StatusCode::BAD_REQUEST, message).into_response()
+                    }
+                })?;
+        Ok(JsonBody(value))
+    }
+}
+
+/// バリデーション済みのリクエストを保持する
+/// JSONとして読めなければ400、読めてもバリデーションに通らなければ422(以前はどちらも400)
+#[derive(Debug)]
+pub struct ValidatedJson<T>(T);
+/// バリデーション実施
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    /// リクエストをstructにパースしてバリデーションする
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        // Jsonにパース
+        let JsonBody(value) = JsonBody::<T>::from_request(req).await?;
+        // バリデーション
+        value.validate().map_err(|errors| {
+            validation_error_response(StatusCode::UNPROCESSABLE_ENTITY, errors)
+        })?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+/// バリデーション済みのクエリパラメータを保持する
+#[derive(Debug)]
+pub struct ValidatedQuery<T>(T);
+/// バリデーション実施
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    B: Send,
+{
+    type Rejection = Response;
+
+    /// クエリ文字列をstructにパースしてバリデーションする
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        // クエリ文字列をパース
+        let query = req.uri().query().unwrap_or_default();
+        let value = serde_urlencoded::from_str::<T>(query).map_err(|rejection| {
+            let message = format!("Query parse error: [{}]", rejection);
+            (StatusCode::BAD_REQUEST, message).into_response()
+        })?;
+        // バリデーション(クエリ文字列の誤りはパースと同じく400)
+        value
+            .validate()
+            .map_err(|errors| validation_error_response(StatusCode::BAD_REQUEST, errors))?;
+        Ok(ValidatedQuery(value))
+    }
+}
+
+/// パスパラメータのID(数値・UUIDとして読めなければJSONで400を返す)
+#[derive(Debug)]
+pub struct IdPath<T>(T);
+#[async_trait]
+impl<T, B> FromRequest<B> for IdPath<T>
+where
+    T: DeserializeOwned + Send,
+    B: Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Path(id) = Path::<T>::from_request(req).await.map_err(|_| {
+            let body = Json(json!({ "error": "invalid id" }));
+            (StatusCode::BAD_REQUEST, body).into_response()
+        })?;
+        Ok(IdPath(id))
+    }
+}
+
+/// 成功時のレスポンスを包むか指定するヘッダ名
+pub const ENVELOPE_HEADER: &str = "x-envelope";
+
+/// 成功時のレスポンスを { "data": ... } で包む指定(既定は包まない)
+/// 一覧と同じクエリ envelope=true か X-Envelope: true ヘッダで指定する。作成・取得・更新は wrap で揃える
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UseEnvelope(bool);
+
+impl UseEnvelope {
+    /// 指定があれば { "data": value } にする
+    pub fn wrap<T: Serialize>(self, value: T) -> Json<Value> {
+        let value = serde_json::to_value(value).unwrap_or_default();
+        if self.0 {
+            Json(json!({ "data": value }))
+        } else {
+            Json(value)
+        }
+    }
+}
+
+/// envelope のクエリ
+#[derive(Debug, Deserialize)]
+struct EnvelopeQuery {
+    #[serde(default)]
+    envelope: bool,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for UseEnvelope
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let query = req.uri().query().unwrap_or_default();
+        let by_query = serde_urlencoded::from_str::<EnvelopeQuery>(query)
+            .map(|query| query.envelope)
+            .unwrap_or(false);
+        let by_header = req
+            .headers()
+            .and_then(|headers| headers.get(ENVELOPE_HEADER))
+            .is_some_and(|value| value == "true");
+        Ok(UseEnvelope(by_query || by_header))
+    }
+}
+
+/// どのルートにも一致しないパスへの404(ハンドラーが返す404とは別にルーターの fallback で使う)
+pub async fn not_found(uri: Uri) -> Response {
+    let body = Json(json!({ "error": "not found", "path": uri.path() }));
+    (StatusCode::NOT_FOUND, body).into_response()
+}
+
+/// ルートで受け付けないメソッドへの405(Allowヘッダーと本文にルートのメソッドを並べる)
+/// 各ルートの MethodRouter::fallback に渡す
+#[derive(Debug, Clone, Copy)]
+pub struct MethodNotAllowed(&'static [&'static str]);
+
+/// ルートで受け付けるメソッドを指定して405の応答を作る
+pub fn allow(methods: &'static [&'static str]) -> MethodNotAllowed {
+    MethodNotAllowed(methods)
+}
+
+impl<B> Service<Request<B>> for MethodNotAllowed {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Ready<Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request<B>) -> Self::Future {
+        let body = Json(json!({ "error": "method not allowed", "allowed": self.0 }));
+        let mut res = (StatusCode::METHOD_NOT_ALLOWED, body).into_response();
+        if let Ok(value) = HeaderValue::from_str(&self.0.join(", ")) {
+            res.headers_mut().insert(ALLOW, value);
+        }
+        ready(Ok(res))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::label::LabelId;
+
+    /// リポジトリのエラーごとのステータス
+    #[test]
+    fn should_map_repository_errors() {
+        let res =
+            repository_error_response(RepositoryError::NotFound(LabelId::from(1).into()).into());
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = repository_error_response(RepositoryError::QuotaExceeded(10).into());
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = repository_error_response(RepositoryError::TextTooLong(100).into());
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        for error in [
+            anyhow::Error::from(RepositoryError::PoolTimedOut),
+            anyhow::Error::from(sqlx::Error::PoolTimedOut),
+            anyhow::Error::from(RepositoryError::ConnectionLost("reset".to_string())),
+            anyhow::Error::from(sqlx::Error::Io(std::io::ErrorKind::ConnectionReset.into())),
+            anyhow::Error::from(sqlx::Error::PoolClosed),
+        ] {
+            let res = repository_error_response(error);
+            assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(res.headers()[RETRY_AFTER], RETRY_AFTER_SECS);
+        }
+
+        let res = repository_error_response(RepositoryError::Unexpected("boom".to_string()).into());
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    /// 405の本文とAllowヘッダー
+    #[tokio::test]
+    async fn should_return_method_not_allowed() {
+        let req = Request::builder().body(()).unwrap();
+        let res = allow(&["GET", "POST"]).call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[ALLOW], "GET, POST");
+    }
+}
@@ -0,0 +1,220 @@
+use axum::{
+    extract::{Extension, Query},
+    http::{header::LOCATION, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use validator::Validate;
+
+use super::{clamp_limit, repository_error_response, IdPath, ValidatedJson};
+use crate::middleware::auth::CurrentUser;
+use crate::repositories::label::{CreateLabel, LabelId, LabelRepository, UpdateLabel};
+use crate::repositories::todo::{TodoId, TodoRepository};
+use crate::repositories::RepositoryError;
+
+/// ラベル作成(同名のラベルがあれば409と既存ラベルのIDを返す)
+pub async fn create_label<T: LabelRepository>(
+    ValidatedJson(payload): ValidatedJson<CreateLabel>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let label = repository.create(payload).await.map_err(error_response)?;
+    let mut headers = HeaderMap::new();
+    let location = format!("/labels/{}", label.id);
+    headers.insert(LOCATION, HeaderValue::from_str(&location).unwrap());
+
+    Ok((StatusCode::CREATED, headers, Json(label)))
+}
+
+/// ラベル更新(同名のラベルがあれば409と既存ラベルのIDを返す)
+pub async fn update_label<T: LabelRepository>(
+    IdPath(id): IdPath<LabelId>,
+    ValidatedJson(payload): ValidatedJson<UpdateLabel>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let label = repository
+        .update(id, payload)
+        .await
+        .map_err(error_response)?;
+
+    Ok((StatusCode::OK, Json(label)))
+}
+
+/// リポジトリのエラーをレスポンスにする(同名のラベルがあれば409)
+fn error_response(error: anyhow::Error) -> Response {
+    if let Some(RepositoryError::Duplicate(id)) = error.downcast_ref::<RepositoryError>() {
+        let body = Json(json!({ "error": "label exists", "id": id }));
+        return (StatusCode::CONFLICT, body).into_response();
+    }
+    repository_error_response(error)
+}
+
+/// 全件取得
+pub async fn all_label<T: LabelRepository>(
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let labels = repository.all().await.map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(labels)))
+}
+
+/// 付いているTODOの件数と合わせて全件取得(使われていないラベルの整理用)
+pub async fn all_label_with_counts<T: LabelRepository>(
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let labels = repository
+        .all_with_counts()
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(labels)))
+}
+
+/// どのTODOにも付いていないラベルの取得
+pub async fn unused_labels<T: LabelRepository>(
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let labels = repository
+        .unused()
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(labels)))
+}
+
+/// TODOに付いたラベルの取得(TODOが無ければ404)
+pub async fn todo_labels<T: TodoRepository, L: LabelRepository>(
+    IdPath(id): IdPath<TodoId>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(todo_repository): Extension<Arc<T>>,
+    Extension(label_repository): Extension<Arc<L>>,
+) -> Result<impl IntoResponse, Response> {
+    todo_repository
+        .find(&user_id, id)
+        .await
+        .map_err(repository_error_response)?;
+    let labels = label_repository
+        .find_by_todo(id)
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(labels)))
+}
+
+/// 検索結果の既定の件数
+const SEARCH_LIMIT: i64 = 10;
+
+/// ラベル検索のクエリ
+#[derive(Debug, Deserialize)]
+pub struct SearchLabelQuery {
+    #[serde(default)]
+    prefix: String,
+    /// 最大件数(省略時は10、一覧と同じ最大件数に切り詰める)
+    limit: Option<i64>,
+}
+
+/// ラベル検索(名前の前方一致、大文字小文字を区別しない。空なら名前順の先頭から)
+pub async fn search_label<T: LabelRepository>(
+    Query(query): Query<SearchLabelQuery>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let labels = repository
+        .search(&query.prefix, clamp_limit(query.limit, SEARCH_LIMIT).max(1))
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(labels)))
+}
+
+/// TODOにラベルを付ける(新たに付けたら201、既に付いていれば200)
+/// TODO・ラベルが無ければ、どちらが無いかを付けて404を返す
+pub async fn add_todo_label<T: TodoRepository, L: LabelRepository>(
+    IdPath((id, label_id)): IdPath<(TodoId, LabelId)>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(todo_repository): Extension<Arc<T>>,
+    Extension(label_repository): Extension<Arc<L>>,
+) -> Result<impl IntoResponse, Response> {
+    todo_repository
+        .find(&user_id, id)
+        .await
+        .map_err(|error| todo_error_response(id, error))?;
+    let created = label_repository
+        .add_label(id, label_id)
+        .await
+        .map_err(|error| match error.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::NotFound(_)) => {
+                let body = Json(json!({ "error": "label not found", "id": label_id }));
+                (StatusCode::NOT_FOUND, body).into_response()
+            }
+            _ => repository_error_response(error),
+        })?;
+    let labels = label_repository
+        .find_by_todo(id)
+        .await
+        .map_err(repository_error_response)?;
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(labels)))
+}
+
+/// TODO取得のエラーをレスポンスにする(見つからなければIDを付けて404)
+fn todo_error_response(id: TodoId, error: anyhow::Error) -> Response {
+    if let Some(RepositoryError::NotFound(_)) = error.downcast_ref::<RepositoryError>() {
+        let body = Json(json!({ "error": "todo not found", "id": id }));
+        return (StatusCode::NOT_FOUND, body).into_response();
+    }
+    repository_error_response(error)
+}
+
+/// TODOからラベルを外す(TODOが無いか、ラベルが付いていなければ404)
+pub async fn remove_todo_label<T: TodoRepository, L: LabelRepository>(
+    IdPath((id, label_id)): IdPath<(TodoId, LabelId)>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(todo_repository): Extension<Arc<T>>,
+    Extension(label_repository): Extension<Arc<L>>,
+) -> Result<impl IntoResponse, Response> {
+    todo_repository
+        .find(&user_id, id)
+        .await
+        .map_err(|error| todo_error_response(id, error))?;
+    let removed = label_repository
+        .remove_label(id, label_id)
+        .await
+        .map_err(repository_error_response)?;
+    if !removed {
+        let body = Json(json!({ "error": "label not attached", "id": label_id }));
+        return Err((StatusCode::NOT_FOUND, body).into_response());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// ラベルの一括削除内容
+#[derive(Debug, Deserialize, Validate)]
+pub struct BulkDeleteLabels {
+    #[validate(length(min = 1, max = 1000, message = "Out of range"))]
+    ids: Vec<LabelId>,
+}
+
+/// ラベルの一括削除(存在しないIDは無視し、削除した件数を返す)
+pub async fn bulk_delete_labels<T: LabelRepository>(
+    ValidatedJson(payload): ValidatedJson<BulkDeleteLabels>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let deleted = repository
+        .delete_many(&payload.ids)
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(json!({ "deleted": deleted }))))
+}
+
+/// ラベル削除(見つからなければ404、DBの失敗はそれに応じたステータス)
+pub async fn delete_label<T: LabelRepository>(
+    IdPath(id): IdPath<LabelId>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<StatusCode, Response> {
+    repository
+        .delete(id)
+        .await
+        .map_err(repository_error_response)?;
+    Ok(StatusCode::NO_CONTENT)
+}
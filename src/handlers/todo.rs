@@ -1,99 +1,1144 @@
 use axum::{
-    async_trait,
-    extract::{Extension, FromRequest, Path, RequestParts},
-    http::StatusCode,
-    response::IntoResponse,
+    body::{Bytes, StreamBody},
+    extract::{Extension, Query},
+    http::{
+        header::{CONTENT_TYPE, IF_MODIFIED_SINCE, LAST_MODIFIED, LINK, LOCATION},
+        HeaderMap, HeaderValue, StatusCode, Uri,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Headers, IntoResponse, Response,
+    },
     Json,
 };
-use serde::de::DeserializeOwned;
-use std::sync::Arc;
-use tower::BoxError;
-use validator::Validate;
-
-use crate::repositories::todo::{CreateTodo, TodoRepository, UpdateTodo};
-
-/// バリデーション済みのリクエストを保持する
-#[derive(Debug)]
-pub struct ValidatedJson<T>(T);
-/// バリデーション実施
-#[async_trait]
-impl<T, B> FromRequest<B> for ValidatedJson<T>
-where
-    T: DeserializeOwned + Validate,
-    B: http_body::Body + Send,
-    B::Data: Send,
-    B::Error: Into<BoxError>,
-{
-    type Rejection = (StatusCode, String);
-
-    /// リクエストをstructにパースしてバリデーションする
-    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        // Jsonにパース
-        let Json(value) = Json::<T>::from_request(req).await.map_err(|rejection| {
-            let message = format!("Json parse error: [{}]", rejection);
-            (StatusCode::BAD_REQUEST, message)
-        })?;
-        // バリデーション
-        value.validate().map_err(|rejection| {
-            let message = format!("Validation error: [{}]", rejection).replace('\n', ", ");
-            (StatusCode::BAD_REQUEST, message)
-        })?;
-        Ok(ValidatedJson(value))
-    }
-}
-
-/// TODO作成
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+use std::{env, io, sync::Arc, time::Duration};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use validator::{Validate, ValidationError};
+
+use super::{
+    clamp_limit, max_page_size, repository_error_response, unsupported_media_type, IdPath,
+    JsonBody, UseEnvelope, ValidatedJson, ValidatedQuery,
+};
+use crate::events::{debounce, TodoEvent, TodoEvents};
+use crate::middleware::auth::CurrentUser;
+use crate::repositories::label::LabelRepository;
+use crate::repositories::todo::{
+    app_tz, CreateTodo, Dataset, Todo, TodoFilter, TodoId, TodoLabel, TodoRepository, UpdateTodo,
+};
+use crate::repositories::RepositoryError;
+
+/// 冪等キーのヘッダ名
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// 冪等キーの有効期間(環境変数 IDEMPOTENCY_TTL_SECS、既定24時間)
+fn idempotency_ttl() -> Duration {
+    let secs = env::var("IDEMPOTENCY_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(24 * 60 * 60);
+    Duration::from_secs(secs)
+}
+
+/// 書き込みを行わずに結果だけ返す指定
+#[derive(Debug, Deserialize)]
+pub struct DryRunQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// TODO作成(Idempotency-Keyヘッダがあれば同じキーでの再作成を防ぐ)
+/// dry_run=true なら検証のみ行い、作成されるTODOを200で返す。envelope の指定で { "data": ... } にする
 pub async fn create_todo<T: TodoRepository>(
+    Query(query): Query<DryRunQuery>,
+    envelope: UseEnvelope,
     ValidatedJson(payload): ValidatedJson<CreateTodo>,
+    headers: HeaderMap,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    // 親TODOが存在すること
+    if let Some(parent_id) = payload.parent_id() {
+        repository
+            .find(&user_id, parent_id)
+            .await
+            .map_err(repository_error_response)?;
+    }
+    if query.dry_run {
+        let todo = Todo::draft(&user_id, payload);
+        return Ok((StatusCode::OK, HeaderMap::new(), envelope.wrap(todo)));
+    }
+    let key = headers
+        .get(IDEMPOTENCY_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let (todo, created) = match key {
+        Some(key) => repository
+            .create_idempotent(&user_id, payload, key, idempotency_ttl())
+            .await
+            .map_err(repository_error_response)?,
+        None => (
+            repository
+                .create(&user_id, payload)
+                .await
+                .map_err(repository_error_response)?,
+            true,
+        ),
+    };
+    if !created {
+        return Ok((StatusCode::OK, HeaderMap::new(), envelope.wrap(todo)));
+    }
+    events.publish(TodoEvent::Created { todo: todo.clone() });
+
+    Ok((
+        StatusCode::CREATED,
+        location(todo.id()),
+        envelope.wrap(todo),
+    ))
+}
+
+/// TODO複製のクエリ
+#[derive(Debug, Deserialize)]
+pub struct DuplicateQuery {
+    /// 付いているラベルも付ける
+    #[serde(default)]
+    with_labels: bool,
+}
+
+/// TODO複製(本文に "Copy of " を付けた未完了のTODOを新しいIDで作成する。元が無ければ404)
+pub async fn duplicate_todo<T: TodoRepository, L: LabelRepository>(
+    IdPath(id): IdPath<TodoId>,
+    Query(query): Query<DuplicateQuery>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(label_repository): Extension<Arc<L>>,
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    let source = repository
+        .find_opt(&user_id, id)
+        .await
+        .map_err(repository_error_response)?
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
     let todo = repository
-        .create(payload)
+        .create(&user_id, source.duplicate())
         .await
-        .or(Err(StatusCode::NOT_FOUND))?;
+        .map_err(repository_error_response)?;
+    if query.with_labels {
+        let labels = label_repository
+            .find_by_todo(id)
+            .await
+            .map_err(repository_error_response)?;
+        for label in labels {
+            label_repository
+                .add_label(todo.id(), label.id)
+                .await
+                .map_err(repository_error_response)?;
+        }
+    }
+    events.publish(TodoEvent::Created { todo: todo.clone() });
+
+    Ok((StatusCode::CREATED, location(todo.id()), Json(todo)))
+}
+
+/// 作成したTODOを指すLocationヘッダ
+fn location(id: TodoId) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let value = format!("/todos/{}", id);
+    headers.insert(LOCATION, HeaderValue::from_str(&value).unwrap());
+    headers
+}
+
+/// TODOの項目名(fields で指定できるもの)
+const TODO_FIELDS: [&str; 12] = [
+    "id",
+    "user_id",
+    "text",
+    "completed",
+    "parent_id",
+    "due_date",
+    "priority",
+    "recurrence",
+    "position",
+    "updated_at",
+    "tags",
+    "completed_at",
+];
+
+/// 返す項目の指定(カンマ区切り、省略時はすべて)
+#[derive(Debug, Deserialize, Validate)]
+pub struct FieldsQuery {
+    #[validate(custom = "validate_fields")]
+    fields: Option<String>,
+}
 
-    Ok((StatusCode::CREATED, Json(todo)))
+impl FieldsQuery {
+    /// 指定した項目だけを残す
+    fn project(&self, todo: &Todo) -> Value {
+        let mut value = serde_json::to_value(todo).unwrap_or_default();
+        if let (Some(fields), Some(object)) = (&self.fields, value.as_object_mut()) {
+            let fields: Vec<&str> = fields.split(',').map(str::trim).collect();
+            object.retain(|key, _| fields.contains(&key.as_str()));
+        }
+        value
+    }
+
+    /// 一覧の各TODOについて指定した項目だけを残す
+    fn project_all(&self, todos: &[Todo]) -> Vec<Value> {
+        todos.iter().map(|todo| self.project(todo)).collect()
+    }
 }
 
-/// TODO検索
+/// TODOの項目名だけが指定されていること
+fn validate_fields(fields: &str) -> Result<(), ValidationError> {
+    let unknown: Vec<&str> = fields
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !TODO_FIELDS.contains(field))
+        .collect();
+    if !unknown.is_empty() {
+        let mut error = ValidationError::new("fields");
+        error.message = Some(format!("Unknown field [{}]", unknown.join(",")).into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// TODO検索(If-Modified-Since 以降に更新が無ければ304を返す)
+/// fields の指定があればその項目だけを返す
 pub async fn find_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
+    IdPath(id): IdPath<TodoId>,
+    ValidatedQuery(fields): ValidatedQuery<FieldsQuery>,
+    envelope: UseEnvelope,
+    headers: HeaderMap,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::OK, Json(todo)))
+) -> Result<Response, Response> {
+    let todo = repository
+        .find_opt(&user_id, id)
+        .await
+        .map_err(repository_error_response)?
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
+    // HTTP日付は秒単位なので切り捨てて比較する
+    let last_modified = Utc.timestamp_opt(todo.updated_at().timestamp(), 0).unwrap();
+    let mut response_headers = HeaderMap::new();
+    let value = last_modified
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string();
+    response_headers.insert(LAST_MODIFIED, HeaderValue::from_str(&value).unwrap());
+    let if_modified_since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok());
+    if matches!(if_modified_since, Some(since) if last_modified <= since) {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+    let todo = fields.project(&todo);
+    Ok((StatusCode::OK, response_headers, envelope.wrap(todo)).into_response())
+}
+
+/// 子TODO取得
+pub async fn children_todo<T: TodoRepository>(
+    IdPath(id): IdPath<TodoId>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    repository
+        .find(&user_id, id)
+        .await
+        .map_err(repository_error_response)?;
+    let todos = repository
+        .children(&user_id, id)
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+/// TODOの変更履歴(古い順。作成されたことのないTODOなら404)
+pub async fn todo_history<T: TodoRepository>(
+    IdPath(id): IdPath<TodoId>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let changes = repository
+        .history(&user_id, id)
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(changes)))
+}
+
+/// ページング指定
+#[derive(Debug, Deserialize, Validate)]
+pub struct Pagination {
+    /// 1ページの件数(最大件数を超える指定は最大件数に切り詰める)
+    #[validate(range(min = 1, message = "Out of range"))]
+    limit: Option<i64>,
+    #[validate(range(min = 0, message = "Out of range"))]
+    offset: Option<i64>,
+    /// カーソル(前ページ最後のID、空なら先頭から)
+    #[serde(default, deserialize_with = "deserialize_cursor")]
+    after: Option<Option<TodoId>>,
+    /// 付いているラベルも含めて全件返す(ページング・エンベロープの指定は無視する)
+    #[serde(default)]
+    labels: bool,
+}
+
+/// 一覧のエンベロープ(envelope=true か X-Envelope: true 指定時)
+/// { "data": [TODO...], "meta": { "total": ユーザーのTODOの総件数, "limit": 実際に使った1ページの件数 } }
+#[derive(Debug, Serialize)]
+pub struct Envelope<T> {
+    data: T,
+    meta: EnvelopeMeta,
+}
+
+/// エンベロープのメタデータ
+#[derive(Debug, Serialize)]
+pub struct EnvelopeMeta {
+    total: i64,
+    /// ページングしていなければ省く
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+}
+
+/// 総件数のヘッダ名
+const TOTAL_COUNT: &str = "x-total-count";
+
+/// 総件数のヘッダ
+fn total_count_header(total: i64) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(TOTAL_COUNT, HeaderValue::from(total));
+    headers
+}
+
+/// 総件数と前後のページのURL(Link ヘッダ、GitHub API と同じ形式)のヘッダ
+/// URLは今のリクエストのクエリの limit・offset だけを差し替えて作る
+fn pagination_headers(uri: &Uri, total: i64, limit: i64, offset: i64) -> HeaderMap {
+    let mut headers = total_count_header(total);
+    let query: Vec<(String, String)> =
+        serde_urlencoded::from_str(uri.query().unwrap_or_default()).unwrap_or_default();
+    let page_url = |offset: i64| {
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .filter(|(key, _)| key != "limit" && key != "offset")
+            .cloned()
+            .collect();
+        query.push(("limit".to_string(), limit.to_string()));
+        query.push(("offset".to_string(), offset.to_string()));
+        let query = serde_urlencoded::to_string(query).unwrap_or_default();
+        format!("{}?{}", uri.path(), query)
+    };
+    let mut links = Vec::new();
+    if offset + limit < total {
+        links.push(format!(r#"<{}>; rel="next""#, page_url(offset + limit)));
+    }
+    if offset > 0 {
+        links.push(format!(
+            r#"<{}>; rel="prev""#,
+            page_url((offset - limit).max(0))
+        ));
+    }
+    if !links.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&links.join(", ")) {
+            headers.insert(LINK, value);
+        }
+    }
+    headers
+}
+
+/// カーソルを読む(空文字は先頭からを表す)
+fn deserialize_cursor<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<TodoId>>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    if value.is_empty() {
+        return Ok(Some(None));
+    }
+    value
+        .parse()
+        .map(|id| Some(Some(id)))
+        .map_err(D::Error::custom)
+}
+
+/// カーソル指定時のページ
+#[derive(Debug, Serialize)]
+pub struct CursorPage<T> {
+    todos: Vec<T>,
+    /// 次ページのカーソル(最後のページなら null)
+    next_cursor: Option<TodoId>,
+    /// 実際に使った1ページの件数
+    limit: i64,
+}
+
+/// 今日が期限の未完了TODO取得(日の区切りは環境変数 APP_TZ のタイムゾーン、既定UTC)
+pub async fn today_todo<T: TodoRepository>(
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let (start, end) = today_range(app_tz(), Utc::now());
+    let todos = repository
+        .due_between(&user_id, start, end)
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+/// 期限が近いTODOの取得期間
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpcomingQuery {
+    /// 何日先までか(既定7日)
+    #[validate(range(min = 1, max = 365, message = "Out of range"))]
+    days: Option<i64>,
+}
+
+/// 期限が現在からN日以内の未完了TODOを期限の早い順に取得(期限の無いTODOは含めない)
+pub async fn upcoming_todo<T: TodoRepository>(
+    ValidatedQuery(query): ValidatedQuery<UpcomingQuery>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let now = Utc::now();
+    let end = now + ChronoDuration::days(query.days.unwrap_or(7));
+    let todos = repository
+        .due_between(&user_id, now, end)
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+/// 最近更新したTODOの件数
+#[derive(Debug, Deserialize, Validate)]
+pub struct RecentQuery {
+    /// 件数(既定10、最大件数を超える指定は最大件数に切り詰める)
+    #[validate(range(min = 1, message = "Out of range"))]
+    limit: Option<i64>,
+}
+
+/// 最近更新したTODOを更新日時の新しい順に取得
+pub async fn recent_todo<T: TodoRepository>(
+    ValidatedQuery(query): ValidatedQuery<RecentQuery>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let todos = repository
+        .recent(&user_id, clamp_limit(query.limit, 10))
+        .await
+        .map_err(repository_error_response)?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+/// 指定タイムゾーンでの当日の範囲[開始, 翌日開始)をUTCで求める
+fn today_range(tz: Tz, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let today = now.with_timezone(&tz).date_naive();
+    let start_of = |date: chrono::NaiveDate| {
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+        // 夏時間の切り替えで0時が存在しない日は1時間後を使う
+        tz.from_local_datetime(&midnight)
+            .earliest()
+            .or_else(|| {
+                tz.from_local_datetime(&(midnight + ChronoDuration::hours(1)))
+                    .earliest()
+            })
+            .map(|date_time| date_time.with_timezone(&Utc))
+            .unwrap_or(now)
+    };
+    (start_of(today), start_of(today.succ_opt().unwrap()))
 }
 
-/// 全件取得
+/// 全件取得(limit・offsetの指定があればページングする)
+/// limit は最大件数(MAX_PAGE_SIZE)を超えてもエラーにせず切り詰め、エンベロープの meta.limit で実際の件数を返す
+/// after の指定があればカーソル以降を取得し、次ページのカーソルを付けて返す
+/// envelope=true か X-Envelope: true の指定があれば配列ではなく総件数を付けたエンベロープで返す
+/// ページングの指定が無い場合も含め、総件数を X-Total-Count、前後のページを Link ヘッダで返す
+/// fields の指定があれば各TODOのその項目だけを返す
+/// completed・q・label_id・due_before・due_after の指定があれば組み合わせて絞り込んだ全件を返す(ページングの指定は無視する)
 pub async fn all_todo<T: TodoRepository>(
+    uri: Uri,
+    UseEnvelope(envelope): UseEnvelope,
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    ValidatedQuery(fields): ValidatedQuery<FieldsQuery>,
+    ValidatedQuery(filter): ValidatedQuery<TodoFilter>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository.all().await.unwrap();
-    Ok((StatusCode::OK, Json(todo)))
+) -> Result<Response, Response> {
+    if !filter.is_empty() {
+        let todos = repository
+            .filter(&user_id, &filter)
+            .await
+            .map_err(repository_error_response)?;
+        return Ok((StatusCode::OK, Json(fields.project_all(&todos))).into_response());
+    }
+    if pagination.labels {
+        let todos = repository
+            .all_with_labels(&user_id)
+            .await
+            .map_err(repository_error_response)?;
+        let todos: Vec<Value> = todos
+            .into_iter()
+            .map(|todo| {
+                let mut value = fields.project(&todo.todo);
+                value["labels"] = json!(todo.labels);
+                value
+            })
+            .collect();
+        return Ok((StatusCode::OK, Json(todos)).into_response());
+    }
+    if let Some(cursor) = pagination.after {
+        let limit = clamp_limit(pagination.limit, max_page_size());
+        let todos = repository
+            .after(&user_id, cursor, limit)
+            .await
+            .map_err(repository_error_response)?;
+        let next_cursor = match todos.last() {
+            Some(todo) if todos.len() as i64 == limit => Some(todo.id()),
+            _ => None,
+        };
+        let todos = fields.project_all(&todos);
+        let page = CursorPage {
+            todos,
+            next_cursor,
+            limit,
+        };
+        return Ok((StatusCode::OK, Json(page)).into_response());
+    }
+    let total = repository
+        .count(&user_id)
+        .await
+        .map_err(repository_error_response)?;
+    let (todo, headers, limit) = match pagination {
+        Pagination {
+            limit: None,
+            offset: None,
+            ..
+        } => (
+            repository.all(&user_id).await,
+            total_count_header(total),
+            None,
+        ),
+        Pagination { limit, offset, .. } => {
+            let (limit, offset) = (clamp_limit(limit, max_page_size()), offset.unwrap_or(0));
+            (
+                repository.page(&user_id, limit, offset).await,
+                pagination_headers(&uri, total, limit, offset),
+                Some(limit),
+            )
+        }
+    };
+    let todo = fields.project_all(&todo.map_err(repository_error_response)?);
+    if envelope {
+        let envelope = Envelope {
+            data: todo,
+            meta: EnvelopeMeta { total, limit },
+        };
+        return Ok((StatusCode::OK, headers, Json(envelope)).into_response());
+    }
+    Ok((StatusCode::OK, headers, Json(todo)).into_response())
 }
 
-/// TODO更新
+/// TODO更新(dry_run=true なら更新せずに更新後のTODOを返す)
+/// 省略した項目は変更しない(JSON Merge Patch)。変更する項目が無ければ何もせず現在のTODOを返す
+/// 更新イベントには更新前と比べて変わった項目名を付ける
 pub async fn update_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
+    IdPath(id): IdPath<TodoId>,
+    Query(query): Query<DryRunQuery>,
+    envelope: UseEnvelope,
     ValidatedJson(payload): ValidatedJson<UpdateTodo>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    if payload.is_empty() {
+        let todo = repository
+            .find(&user_id, id)
+            .await
+            .map_err(repository_error_response)?;
+        return Ok((StatusCode::OK, envelope.wrap(todo)));
+    }
+    if query.dry_run {
+        let todo = repository
+            .find(&user_id, id)
+            .await
+            .map_err(repository_error_response)?
+            .apply(payload);
+        return Ok((StatusCode::OK, envelope.wrap(todo)));
+    }
+    let old = repository
+        .find(&user_id, id)
+        .await
+        .map_err(repository_error_response)?;
     let todo = repository
-        .update(id, payload)
+        .update(&user_id, id, payload)
         .await
-        .or(Err(StatusCode::NOT_FOUND))?;
+        .map_err(repository_error_response)?;
+    events.publish(TodoEvent::Updated {
+        changed: todo.changed_fields(&old),
+        todo: todo.clone(),
+    });
 
-    Ok((StatusCode::OK, Json(todo)))
+    Ok((StatusCode::OK, envelope.wrap(todo)))
 }
 
-/// TODO削除
+/// 一括更新で一度に受け付ける件数の上限
+const BATCH_UPDATE_LIMIT: usize = 1000;
+
+/// 一括更新の1件ごとの結果
+#[derive(Debug, Serialize)]
+pub struct BatchUpdateResult {
+    id: Option<TodoId>,
+    /// ok・not_found・invalid
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl BatchUpdateResult {
+    /// 検証エラー
+    fn invalid(id: Option<TodoId>, error: impl ToString) -> Self {
+        Self {
+            id,
+            status: "invalid",
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// 1件分の更新内容を読んで検証する
+fn parse_batch_item(item: Value) -> Result<(TodoId, UpdateTodo), BatchUpdateResult> {
+    let id = item
+        .get("id")
+        .and_then(|id| serde_json::from_value::<TodoId>(id.clone()).ok())
+        .ok_or_else(|| BatchUpdateResult::invalid(None, "invalid id"))?;
+    let payload = serde_json::from_value::<UpdateTodo>(item)
+        .map_err(|e| BatchUpdateResult::invalid(Some(id), e))?;
+    payload
+        .validate()
+        .map_err(|e| BatchUpdateResult::invalid(Some(id), e))?;
+    Ok((id, payload))
+}
+
+/// IDごとの一括更新(オフライン中の編集の同期用)
+/// 1件ずつ結果を返し、検証エラーや見つからないIDがあっても他の更新は行う
+pub async fn batch_update_todos<T: TodoRepository>(
+    JsonBody(items): JsonBody<Vec<Value>>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    if items.len() > BATCH_UPDATE_LIMIT {
+        let body = Json(json!({ "error": "too many items", "limit": BATCH_UPDATE_LIMIT }));
+        return Err((StatusCode::BAD_REQUEST, body).into_response());
+    }
+    let mut results = Vec::with_capacity(items.len());
+    let mut updates = Vec::new();
+    for item in items {
+        match parse_batch_item(item) {
+            Ok((id, payload)) => {
+                updates.push((results.len(), id, payload));
+                results.push(BatchUpdateResult {
+                    id: Some(id),
+                    status: "not_found",
+                    error: None,
+                });
+            }
+            Err(result) => results.push(result),
+        }
+    }
+
+    let ids: Vec<TodoId> = updates.iter().map(|(_, id, _)| *id).collect();
+    let old_todos = repository
+        .find_many(&user_id, &ids)
+        .await
+        .map_err(repository_error_response)?;
+    let indexes: Vec<usize> = updates.iter().map(|(index, _, _)| *index).collect();
+    let updates = updates
+        .into_iter()
+        .map(|(_, id, payload)| (id, payload))
+        .collect();
+    let todos = repository
+        .update_many(&user_id, updates)
+        .await
+        .map_err(repository_error_response)?;
+    for (index, todo) in indexes.into_iter().zip(todos) {
+        let todo = match todo {
+            Some(todo) => todo,
+            None => continue,
+        };
+        results[index].status = "ok";
+        if let Some(old) = old_todos.iter().find(|old| old.id() == todo.id()) {
+            events.publish(TodoEvent::Updated {
+                changed: todo.changed_fields(old),
+                todo,
+            });
+        }
+    }
+
+    Ok((StatusCode::OK, Json(results)))
+}
+
+/// 完了状態の一括更新内容
+#[derive(Debug, Deserialize, Validate)]
+pub struct BulkComplete {
+    #[validate(length(min = 1, max = 1000, message = "Out of range"))]
+    ids: Vec<TodoId>,
+    completed: bool,
+}
+
+/// 完了状態の一括更新結果
+#[derive(Debug, Serialize)]
+pub struct BulkCompleteSummary {
+    updated: u64,
+}
+
+/// 完了状態の一括更新(存在しないIDは無視し、更新した件数を返す)
+/// 1件ごとに更新イベントを送る(繰り返しTODOの次の回もここから作成される)
+pub async fn bulk_complete_todos<T: TodoRepository>(
+    ValidatedJson(payload): ValidatedJson<BulkComplete>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    let changes = repository
+        .set_completed_many(&user_id, &payload.ids, payload.completed)
+        .await
+        .map_err(repository_error_response)?;
+    let updated = changes.len() as u64;
+    for (old, todo) in changes {
+        events.publish(TodoEvent::Updated {
+            changed: todo.changed_fields(&old),
+            todo,
+        });
+    }
+
+    Ok((StatusCode::OK, Json(BulkCompleteSummary { updated })))
+}
+
+/// 複数のIDでの取得内容
+#[derive(Debug, Deserialize, Validate)]
+pub struct TodoIds {
+    #[validate(length(min = 1, max = 1000, message = "Out of range"))]
+    ids: Vec<TodoId>,
+}
+
+/// 複数のIDで取得(指定したIDの順に返し、見つからないIDは飛ばす)
+pub async fn find_todos_by_ids<T: TodoRepository>(
+    ValidatedJson(payload): ValidatedJson<TodoIds>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let todos = repository
+        .find_many(&user_id, &payload.ids)
+        .await
+        .map_err(repository_error_response)?;
+
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+/// 生存確認(プロセスが動いていれば常に200。DBは見ないので liveness probe に使う)
+pub async fn livez() -> impl IntoResponse {
+    (StatusCode::OK, Json(json!({ "ok": true })))
+}
+
+/// 準備完了の確認(DBへの疎通に失敗したか、マイグレーションが未適用なら503)
+/// readiness probe に使う。/health も同じ(確認自体に失敗しても ok: false の503)
+pub async fn health<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let health = repository.health().await.map_err(|e| {
+        tracing::error!("fail check health: {}", e);
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "ok": false })),
+        )
+            .into_response()
+    })?;
+    let status = if health.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok((status, Json(health)))
+}
+
+/// TODO削除のクエリ
+#[derive(Debug, Deserialize)]
+pub struct DeleteTodoQuery {
+    /// 子TODOごと削除する
+    #[serde(default)]
+    cascade: bool,
+}
+
+/// TODO削除(子TODOがある場合は cascade=true の指定が必要)
+/// 見つからなければ404とIDを本文で返す
 pub async fn delete_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
+    IdPath(id): IdPath<TodoId>,
+    Query(query): Query<DeleteTodoQuery>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
     Extension(repository): Extension<Arc<T>>,
-) -> StatusCode {
-    repository
-        .delete(id)
+    Extension(events): Extension<TodoEvents>,
+) -> Response {
+    let todo = match repository.find_opt(&user_id, id).await {
+        Ok(Some(todo)) => todo,
+        Ok(None) => return todo_not_found(id),
+        Err(e) => return repository_error_response(e),
+    };
+    if !query.cascade {
+        match repository.children(&user_id, id).await {
+            Ok(children) if !children.is_empty() => return StatusCode::CONFLICT.into_response(),
+            Ok(_) => {}
+            Err(e) => return repository_error_response(e),
+        }
+    }
+    match repository.delete(&user_id, id).await {
+        Ok(_) => {
+            events.publish(TodoEvent::Deleted { todo });
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => match e.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::NotFound(_)) => todo_not_found(id),
+            _ => repository_error_response(e),
+        },
+    }
+}
+
+/// TODOが見つからない場合の404(本文にIDを付ける)
+fn todo_not_found(id: TodoId) -> Response {
+    let body = Json(json!({ "error": "todo not found", "id": id }));
+    (StatusCode::NOT_FOUND, body).into_response()
+}
+
+/// TODO削除の取り消し
+pub async fn restore_todo<T: TodoRepository>(
+    IdPath(id): IdPath<TodoId>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    let todo = repository
+        .restore(&user_id, id)
+        .await
+        .map_err(repository_error_response)?;
+    events.publish(TodoEvent::Created { todo: todo.clone() });
+
+    Ok((StatusCode::CREATED, location(todo.id()), Json(todo)))
+}
+
+/// TODO移動先
+#[derive(Debug, Deserialize, Validate)]
+pub struct MoveTodo {
+    /// 兄弟TODOの中での位置(0始まり)
+    #[validate(range(min = 0, message = "Out of range"))]
+    position: i32,
+}
+
+/// TODO移動(兄弟TODOの中での並び順を変える)
+pub async fn move_todo<T: TodoRepository>(
+    IdPath(id): IdPath<TodoId>,
+    ValidatedJson(payload): ValidatedJson<MoveTodo>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    let todo = repository
+        .move_to(&user_id, id, payload.position)
+        .await
+        .map_err(repository_error_response)?;
+    events.publish(TodoEvent::Updated {
+        todo: todo.clone(),
+        changed: vec!["position"],
+    });
+
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+/// TODO本文への追記
+#[derive(Debug, Deserialize, Validate)]
+pub struct AppendTodo {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    text: String,
+}
+
+/// TODO本文の末尾に追記する(追記後の本文が最大文字数を超えるなら422)
+pub async fn append_todo<T: TodoRepository>(
+    IdPath(id): IdPath<TodoId>,
+    ValidatedJson(payload): ValidatedJson<AppendTodo>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    let todo = repository
+        .append_text(&user_id, id, &payload.text)
         .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::NOT_FOUND)
+        .map_err(repository_error_response)?;
+    events.publish(TodoEvent::Updated {
+        todo: todo.clone(),
+        changed: vec!["text"],
+    });
+
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+/// TODO本文の一括置換内容
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReplaceText {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    find: String,
+    replace: String,
+}
+
+/// find を含む本文をすべて置換する(置換後に最大文字数を超えるTODOは変更せず skipped に返す)
+pub async fn replace_todo_text<T: TodoRepository>(
+    ValidatedJson(payload): ValidatedJson<ReplaceText>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    let result = repository
+        .replace_text(&user_id, &payload.find, &payload.replace)
+        .await
+        .map_err(repository_error_response)?;
+    for (old, todo) in &result.changes {
+        events.publish(TodoEvent::Updated {
+            changed: todo.changed_fields(old),
+            todo: todo.clone(),
+        });
+    }
+
+    Ok((StatusCode::OK, Json(result)))
+}
+
+/// SSEで続けて起きたイベントをまとめる間隔(環境変数 SSE_DEBOUNCE_MS、既定50ミリ秒。0でまとめない)
+fn sse_debounce_window() -> Duration {
+    let millis = env::var("SSE_DEBOUNCE_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(50);
+    Duration::from_millis(millis)
+}
+
+/// TODO変更イベントのSSEストリーム(自分のTODOのイベントのみ)
+/// 一括操作などで続けて起きたイベントは件数だけの bulk イベントにまとめる
+pub async fn todo_events(
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(events): Extension<TodoEvents>,
+) -> Sse<impl Stream<Item = Result<Event, serde_json::Error>>> {
+    // 購読が遅れて取りこぼしたイベントは読み飛ばす
+    let stream = BroadcastStream::new(events.subscribe())
+        .filter_map(|event| event.ok())
+        .filter(move |event| event.todo().user_id() == user_id);
+    let stream =
+        debounce(stream, sse_debounce_window()).map(|event| Event::default().json_data(event));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// インポート結果
+#[derive(Debug, Serialize)]
+pub struct ImportSummary {
+    imported: usize,
+    skipped: usize,
+    errors: Vec<ImportError>,
+}
+
+/// インポートできなかった行
+#[derive(Debug, Serialize)]
+pub struct ImportError {
+    line: usize,
+    message: String,
+}
+
+/// TODOインポート(Content-TypeによりJSON配列とCSVを切り替える)
+pub async fn import_todos<T: TodoRepository>(
+    headers: HeaderMap,
+    body: Bytes,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(events): Extension<TodoEvents>,
+) -> Result<impl IntoResponse, Response> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let rows = if content_type.starts_with(mime::TEXT_CSV.as_ref()) {
+        parse_csv_rows(&body).map_err(IntoResponse::into_response)?
+    } else if content_type.starts_with(mime::APPLICATION_JSON.as_ref()) {
+        parse_json_rows(&body).map_err(IntoResponse::into_response)?
+    } else {
+        return Err(unsupported_media_type("application/json or text/csv"));
+    };
+
+    // 行ごとにバリデーションし、不正な行はエラーとして記録する
+    let mut payloads = Vec::new();
+    let mut errors = Vec::new();
+    for (line, row) in rows {
+        let row = row.and_then(|payload| {
+            payload
+                .validate()
+                .map(|_| payload)
+                .map_err(|e| format!("Validation error: [{}]", e).replace('\n', ", "))
+        });
+        match row {
+            Ok(payload) => payloads.push(payload),
+            Err(message) => errors.push(ImportError { line, message }),
+        }
+    }
+
+    let todos = repository
+        .create_many(&user_id, payloads)
+        .await
+        .map_err(repository_error_response)?;
+    let imported = todos.len();
+    for todo in todos {
+        events.publish(TodoEvent::Created { todo });
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(ImportSummary {
+            imported,
+            skipped: errors.len(),
+            errors,
+        }),
+    ))
+}
+
+/// JSON Linesの Content-Type
+const NDJSON: &str = "application/x-ndjson";
+
+/// 自分のTODOをJSON Lines(1行に1件)で書き出す
+/// 全件をメモリに載せず、リポジトリから読んだ順に送る
+pub async fn export_ndjson<T: TodoRepository>(
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let todos = repository
+        .stream(&user_id)
+        .await
+        .map_err(repository_error_response)?;
+    // StreamBody は Into<BoxError> のエラーしか受け付けないので io::Error にする
+    let lines = todos.map(|todo| {
+        let todo = todo.map_err(|e| io::Error::other(e.to_string()))?;
+        let mut line = serde_json::to_vec(&todo)?;
+        line.push(b'\n');
+        Ok::<_, io::Error>(line)
+    });
+
+    Ok((
+        Headers([(CONTENT_TYPE, HeaderValue::from_static(NDJSON))]),
+        StreamBody::new(lines),
+    ))
+}
+
+/// データ一式の書き出し(自分のTODO・全ラベル・TODOとラベルの対応。POST /import で戻せる)
+pub async fn export_dataset<T: TodoRepository, L: LabelRepository>(
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(label_repository): Extension<Arc<L>>,
+) -> Result<impl IntoResponse, Response> {
+    let todos = repository
+        .all_with_labels(&user_id)
+        .await
+        .map_err(repository_error_response)?;
+    let labels = label_repository
+        .all()
+        .await
+        .map_err(repository_error_response)?;
+    let associations = todos
+        .iter()
+        .flat_map(|todo| {
+            todo.labels.iter().map(|label| TodoLabel {
+                todo_id: todo.todo.id(),
+                label_id: label.id,
+            })
+        })
+        .collect();
+    let todos = todos.into_iter().map(|todo| todo.todo).collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(Dataset {
+            todos,
+            labels,
+            associations,
+        }),
+    ))
+}
+
+/// データ一式の取り込み方法
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// 既存のTODOに追加する
+    #[default]
+    Merge,
+    /// 自分のTODOを全て消してから取り込む
+    Replace,
+}
+
+/// データ一式の取り込みのクエリ
+#[derive(Debug, Deserialize)]
+pub struct ImportDatasetQuery {
+    #[serde(default)]
+    mode: ImportMode,
+}
+
+/// データ一式の取り込み(GET /export の形式。構造が不正なら何も取り込まずに422)
+/// TODOは新しいIDで登録し、ラベルは他のユーザーとも共有するため置き換えずに同名のものを使う
+pub async fn import_dataset<T: TodoRepository>(
+    Query(query): Query<ImportDatasetQuery>,
+    ValidatedJson(dataset): ValidatedJson<Dataset>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, Response> {
+    let counts = repository
+        .import_dataset(&user_id, dataset, query.mode == ImportMode::Replace)
+        .await
+        .map_err(repository_error_response)?;
+
+    Ok((StatusCode::OK, Json(counts)))
+}
+
+/// 行番号とその行のパース結果
+type ImportRows = Vec<(usize, Result<CreateTodo, String>)>;
+
+/// JSON配列を行(1始まりの要素番号)ごとにパースする
+fn parse_json_rows(body: &[u8]) -> Result<ImportRows, (StatusCode, String)> {
+    let values: Vec<serde_json::Value> = serde_json::from_slice(body).map_err(|e| {
+        let message = format!("Json parse error: [{}]", e);
+        (StatusCode::BAD_REQUEST, message)
+    })?;
+    Ok(values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let row = serde_json::from_value::<CreateTodo>(value)
+                .map_err(|e| format!("Json parse error: [{}]", e));
+            (i + 1, row)
+        })
+        .collect())
+}
+
+/// CSV(ヘッダ行にtext列が必要)を行番号ごとにパースする
+fn parse_csv_rows(body: &[u8]) -> Result<ImportRows, (StatusCode, String)> {
+    let mut reader = csv::Reader::from_reader(body);
+    let headers = reader.headers().cloned().map_err(|e| {
+        let message = format!("Csv parse error: [{}]", e);
+        (StatusCode::BAD_REQUEST, message)
+    })?;
+    Ok(reader
+        .records()
+        .map(|record| match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line() as usize);
+                let row = record
+                    .deserialize::<CreateTodo>(Some(&headers))
+                    .map_err(|e| format!("Csv parse error: [{}]", e));
+                (line, row)
+            }
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line() as usize);
+                (line, Err(format!("Csv parse error: [{}]", e)))
+            }
+        })
+        .collect())
 }
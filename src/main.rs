@@ -1,19 +1,69 @@
+mod events;
 mod handlers;
+mod middleware;
+mod recurrence;
 mod repositories;
+mod seed;
+mod shutdown;
+mod webhook;
 
-use crate::repositories::todo::{TodoRepository, TodoRepositoryForDb};
+#[cfg(not(feature = "uuid-id"))]
+use crate::repositories::{label::LabelRepositoryForSqlite, todo::TodoRepositoryForSqlite};
+use crate::repositories::{
+    label::{LabelRepository, LabelRepositoryForDb},
+    todo::{max_text_len, TodoRepository, TodoRepositoryForDb},
+    RepositoryError,
+};
 use axum::{
+    error_handling::HandleErrorLayer,
     extract::Extension,
-    routing::{get, post},
-    Router,
+    handler::Handler,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    routing::{delete, get, patch, post},
+    Json, Router,
 };
+use axum_server::{tls_rustls::RustlsConfig, Handle};
 use dotenv::dotenv;
-use handlers::todo::{all_todo, create_todo, delete_todo, find_todo, update_todo};
-use hyper::header::CONTENT_TYPE;
+use events::TodoEvents;
+use handlers::label::{
+    add_todo_label, all_label, all_label_with_counts, bulk_delete_labels, create_label,
+    delete_label, remove_todo_label, search_label, todo_labels, unused_labels, update_label,
+};
+use handlers::todo::{
+    all_todo, append_todo, batch_update_todos, bulk_complete_todos, children_todo, create_todo,
+    delete_todo, duplicate_todo, export_dataset, export_ndjson, find_todo, find_todos_by_ids,
+    health, import_dataset, import_todos, livez, move_todo, recent_todo, replace_todo_text,
+    restore_todo, today_todo, todo_events, todo_history, upcoming_todo, update_todo,
+    IDEMPOTENCY_KEY,
+};
+use handlers::{allow, not_found, ENVELOPE_HEADER};
+use hyper::header::{HeaderName, ACCEPT, AUTHORIZATION, CONTENT_TYPE, LINK, LOCATION};
+use middleware::access_log::AccessLog;
+use middleware::auth::{self, BearerAuth, USER_ID_HEADER};
+use middleware::body_limit::{self, BodyLimit};
+use middleware::metrics::{install_recorder, metrics_router, spawn_todo_gauge, MetricsLayer};
+use middleware::rate_limit::{self, RateLimiter};
+use middleware::read_only::{self, ReadOnly};
+use middleware::request_id::{RequestIdLayer, REQUEST_ID_HEADER};
+use middleware::timeout;
+use recurrence::spawn_recurrence;
+use seed::seed_demo_data;
+use serde_json::json;
 use sqlx::PgPool;
 use std::net::SocketAddr;
-use std::{env, sync::Arc};
-use tower_http::cors::{Any, CorsLayer, Origin};
+use std::{env, sync::Arc, time::Duration};
+use tower::{filter::FilterLayer, ServiceBuilder};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    cors::{Any, CorsLayer, Origin},
+    trace::TraceLayer,
+};
+use tracing_subscriber::EnvFilter;
+use webhook::{spawn_webhook, Webhook};
 
 /// メインメソッド
 #[tokio::main]
@@ -26,59 +76,540 @@ async fn main() {
     unsafe {
         env::set_var("RUST_LOG", log_level);
     }
-    tracing_subscriber::fmt::init();
+    init_logging();
+    tracing::debug!("max todo text length is {}", max_text_len());
+    // --seed で空のストアに見本データを入れてから起動する
+    let seed = env::args().skip(1).any(|arg| arg == "--seed");
 
-    let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+    let database_url = &database_url();
     tracing::debug!("start connect database...");
-    let pool = PgPool::connect(database_url)
-        .await
-        .expect(&format!("fail connect database, url is [{}]", database_url));
+    // インメモリDBは接続ごとに別のDBになるので1接続に限る
+    let max_connections: u32 = match database_url.as_str() {
+        MEMORY_DATABASE_URL => 1,
+        _ => env::var("DB_MAX_CONNECTIONS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(10),
+    };
+    // マイグレーション(環境変数 RUN_MIGRATIONS=false で無効化)
+    let run_migrations = env::var("RUN_MIGRATIONS")
+        .map(|value| value != "false")
+        .unwrap_or(true);
+
+    // DATABASE_URL が sqlite: で始まる場合はSQLiteを使う
+    if database_url.starts_with("sqlite:") {
+        serve_sqlite(database_url, max_connections, run_migrations, seed).await;
+        return;
+    }
+
+    let retries: u32 = env::var("DB_CONNECT_RETRIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(5);
+    let todo_repository = match connect_with_retry(database_url, max_connections, retries).await {
+        Ok(repository) => repository,
+        Err(e) => {
+            tracing::error!("fail connect database, url is [{}]: {}", database_url, e);
+            std::process::exit(1);
+        }
+    };
+    if run_migrations {
+        if let Err(e) = migrate(todo_repository.pool()).await {
+            tracing::error!("fail run migrations: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let label_repository = LabelRepositoryForDb::new(todo_repository.pool().clone());
+    exit_on_schema_error(todo_repository.check_schema().await, "todos");
+    exit_on_schema_error(label_repository.check_schema().await, "labels");
+    serve(todo_repository, label_repository, seed).await;
+}
+
+/// DATABASE_URL 未指定時に使うDB(SQLiteのインメモリDB、再起動でデータが消える)
+const MEMORY_DATABASE_URL: &str = "sqlite::memory:";
+
+/// 接続先のDBを読む(DATABASE_URL 未指定ならインメモリDB)
+/// リリースビルドでは誤ってデータを失わないよう、ALLOW_MEMORY_BACKEND=true の指定が無ければ起動を中止する
+fn database_url() -> String {
+    if let Ok(database_url) = env::var("DATABASE_URL") {
+        return database_url;
+    }
+    let flag = env::var("ALLOW_MEMORY_BACKEND").ok();
+    if !allow_memory_backend(cfg!(debug_assertions), flag.as_deref()) {
+        tracing::error!(
+            "[DATABASE_URL] is not set, set it or set ALLOW_MEMORY_BACKEND=true to start with a non-persistent in-memory database"
+        );
+        std::process::exit(1);
+    }
+    tracing::warn!("[DATABASE_URL] is not set, using in-memory database (data is lost on restart)");
+    MEMORY_DATABASE_URL.to_string()
+}
+
+/// インメモリDBで起動してよいか(デバッグビルドなら常に、リリースビルドは明示した場合のみ)
+fn allow_memory_backend(debug_build: bool, flag: Option<&str>) -> bool {
+    debug_build || flag == Some("true")
+}
+
+/// スキーマが想定と違えば、マイグレーションの適用を促して終了する
+/// (リクエスト時に分かりにくい500になるのを防ぐ)
+fn exit_on_schema_error(result: anyhow::Result<()>, table: &str) {
+    if let Err(e) = result {
+        tracing::error!(
+            "table [{}] is missing or has unexpected columns, apply migrations (RUN_MIGRATIONS=true or `sqlx migrate run`): {}",
+            table,
+            e
+        );
+        std::process::exit(1);
+    }
+}
+
+/// メトリクスを設定してサーバを立ち上げる(seed が true なら先に見本データを作成する)
+async fn serve<T: TodoRepository, L: LabelRepository>(
+    todo_repository: T,
+    label_repository: L,
+    seed: bool,
+) {
+    let tls_config = load_tls_config().await;
+    if seed {
+        match seed_demo_data(&todo_repository, &label_repository).await {
+            Ok(seeded) if seeded.todos == 0 && seeded.labels == 0 => {
+                tracing::info!("store is not empty, skipped seeding")
+            }
+            Ok(seeded) => {
+                tracing::info!("seeded {} todos and {} labels", seeded.todos, seeded.labels)
+            }
+            Err(e) => {
+                tracing::error!("fail seed demo data: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // メトリクス(METRICS_ADDR を指定すると /metrics を別アドレスで公開する)
+    let metrics_handle = install_recorder();
+    let refresh_secs: u64 = env::var("METRICS_REFRESH_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(15);
+    spawn_todo_gauge(todo_repository.clone(), Duration::from_secs(refresh_secs));
 
     // サーバ立ち上げ
-    let repository = TodoRepositoryForDb::new(pool.clone());
-    let app = create_app(repository);
+    let mut app = create_app(todo_repository, label_repository);
+    match env::var("METRICS_ADDR") {
+        Ok(metrics_addr) => {
+            let metrics_addr: SocketAddr = metrics_addr.parse().expect("invalid [METRICS_ADDR]");
+            tracing::debug!("metrics listening on {}", metrics_addr);
+            tokio::spawn(
+                axum::Server::bind(&metrics_addr)
+                    .serve(metrics_router(metrics_handle).into_make_service()),
+            );
+        }
+        Err(_) => app = app.merge(metrics_router(metrics_handle)),
+    }
     let addr = SocketAddr::from(([127, 0, 0, 1], 6178));
-    tracing::debug!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+    let app = app.into_make_service_with_connect_info::<SocketAddr, _>();
+    // Ctrl+C・SIGTERMで処理中の接続を待ってから終了する(待ちきれない接続は猶予後に切断する)
+    let handle = Handle::new();
+    shutdown::spawn_graceful_shutdown(
+        handle.clone(),
+        shutdown::grace_from_env(),
+        shutdown::signal(),
+    );
+    match tls_config {
+        Some(config) => {
+            tracing::debug!("listening on https://{}", addr);
+            axum_server::bind_rustls(addr, config)
+                .handle(handle)
+                .serve(app)
+                .await
+                .unwrap();
+        }
+        None => {
+            tracing::debug!("listening on {}", addr);
+            axum_server::bind(addr)
+                .handle(handle)
+                .serve(app)
+                .await
+                .unwrap();
+        }
+    }
+}
+
+/// TLS設定を読む(TLS_CERT_PATH・TLS_KEY_PATH の両方を指定するとHTTPSにする)
+/// 片方だけの指定や読み込みに失敗した場合は起動を中止する
+async fn load_tls_config() -> Option<RustlsConfig> {
+    let (cert_path, key_path) = match (env::var("TLS_CERT_PATH"), env::var("TLS_KEY_PATH")) {
+        (Ok(cert_path), Ok(key_path)) => (cert_path, key_path),
+        (Err(_), Err(_)) => return None,
+        _ => {
+            tracing::error!("both [TLS_CERT_PATH] and [TLS_KEY_PATH] must be set to enable TLS");
+            std::process::exit(1);
+        }
+    };
+    match RustlsConfig::from_pem_file(&cert_path, &key_path).await {
+        Ok(config) => Some(config),
+        Err(e) => {
+            tracing::error!(
+                "fail load TLS certificate [{}] and key [{}]: {}",
+                cert_path,
+                key_path,
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// SQLiteに接続し、migrations-sqlite のマイグレーションを実行してサーバを立ち上げる
+#[cfg(not(feature = "uuid-id"))]
+async fn serve_sqlite(database_url: &str, max_connections: u32, run_migrations: bool, seed: bool) {
+    let todo_repository =
+        match TodoRepositoryForSqlite::connect(database_url, max_connections).await {
+            Ok(repository) => repository,
+            Err(e) => {
+                tracing::error!("fail connect database, url is [{}]: {}", database_url, e);
+                std::process::exit(1);
+            }
+        };
+    if run_migrations {
+        if let Err(e) = sqlx::migrate!("./migrations-sqlite")
+            .run(todo_repository.pool())
+            .await
+        {
+            tracing::error!("fail run migrations: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let label_repository = LabelRepositoryForSqlite::new(todo_repository.pool().clone());
+    exit_on_schema_error(todo_repository.check_schema().await, "todos");
+    exit_on_schema_error(label_repository.check_schema().await, "labels");
+    serve(todo_repository, label_repository, seed).await;
+}
+
+/// SQLiteはUUIDのIDに対応していない
+#[cfg(feature = "uuid-id")]
+async fn serve_sqlite(
+    _database_url: &str,
+    _max_connections: u32,
+    _run_migrations: bool,
+    _seed: bool,
+) {
+    tracing::error!("sqlite backend is not supported with the uuid-id feature");
+    std::process::exit(1);
+}
+
+/// DB接続(失敗したら指数バックオフでリトライする)
+async fn connect_with_retry(
+    database_url: &str,
+    max_connections: u32,
+    retries: u32,
+) -> Result<TodoRepositoryForDb, RepositoryError> {
+    let mut wait = Duration::from_secs(1);
+    let mut attempt = 0;
+    loop {
+        match TodoRepositoryForDb::connect(database_url, max_connections).await {
+            Ok(repository) => return Ok(repository),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                tracing::warn!(
+                    "fail connect database, retry {}/{} after {:?}: {}",
+                    attempt,
+                    retries,
+                    wait,
+                    e
+                );
+                tokio::time::sleep(wait).await;
+                wait = (wait * 2).min(Duration::from_secs(30));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// 埋め込んだマイグレーションを実行し、新たに適用したものをログに出す
+async fn migrate(pool: &PgPool) -> anyhow::Result<()> {
+    let migrator = sqlx::migrate!();
+    // 初回は管理テーブルが無いので空扱い
+    let applied: Vec<i64> = sqlx::query_scalar(r#"select version from _sqlx_migrations"#)
+        .fetch_all(pool)
         .await
-        .unwrap();
+        .unwrap_or_default();
+    migrator.run(pool).await?;
+    for migration in migrator.iter() {
+        if !applied.contains(&migration.version) {
+            tracing::info!(
+                "applied migration {} {}",
+                migration.version,
+                migration.description
+            );
+        }
+    }
+    Ok(())
 }
 
-/// ルーティングを設定
-fn create_app<T: TodoRepository>(repository: T) -> Router {
+/// ルーティングを設定(繰り返しTODOのタスクも起動する)
+fn create_app<T: TodoRepository, L: LabelRepository>(
+    todo_repository: T,
+    label_repository: L,
+) -> Router {
+    let todo_repository = Arc::new(todo_repository);
+    let events = TodoEvents::new();
+    spawn_recurrence(Arc::clone(&todo_repository), events.clone());
+    if let Some(webhook) = Webhook::from_env() {
+        spawn_webhook(webhook, events.clone());
+    }
+
+    // SSE・CSV取り込み・JSON Linesの書き出しは長くかかるので処理時間の上限をかけない
+    let streaming = Router::new()
+        .route(
+            "/todos/import",
+            post(import_todos::<T>).fallback(allow(&["POST"])),
+        )
+        .route(
+            "/todos/export.ndjson",
+            get(export_ndjson::<T>).fallback(allow(&["GET"])),
+        )
+        .route("/todos/events", get(todo_events).fallback(allow(&["GET"])));
     Router::new()
-        .route("/", get(root))
-        .route("/todos", post(create_todo::<T>).get(all_todo::<T>))
+        .route("/", get(root).fallback(allow(&["GET"])))
+        .route("/health", get(health::<T>).fallback(allow(&["GET"])))
+        .route("/livez", get(livez).fallback(allow(&["GET"])))
+        .route("/readyz", get(health::<T>).fallback(allow(&["GET"])))
+        .route("/version", get(version).fallback(allow(&["GET"])))
+        .route(
+            "/todos",
+            post(create_todo::<T>)
+                .get(all_todo::<T>)
+                .fallback(allow(&["GET", "POST"])),
+        )
+        .route(
+            "/todos/batch",
+            patch(batch_update_todos::<T>).fallback(allow(&["PATCH"])),
+        )
+        .route(
+            "/todos/by-ids",
+            post(find_todos_by_ids::<T>).fallback(allow(&["POST"])),
+        )
+        .route(
+            "/todos/bulk-complete",
+            post(bulk_complete_todos::<T>).fallback(allow(&["POST"])),
+        )
+        .route(
+            "/todos/replace-text",
+            post(replace_todo_text::<T>).fallback(allow(&["POST"])),
+        )
+        .route(
+            "/todos/upcoming",
+            get(upcoming_todo::<T>).fallback(allow(&["GET"])),
+        )
+        .route(
+            "/todos/today",
+            get(today_todo::<T>).fallback(allow(&["GET"])),
+        )
+        .route(
+            "/todos/recent",
+            get(recent_todo::<T>).fallback(allow(&["GET"])),
+        )
         .route(
             "/todos/:id",
             get(find_todo::<T>)
                 .delete(delete_todo::<T>)
-                .patch(update_todo::<T>),
+                .patch(update_todo::<T>)
+                .fallback(allow(&["GET", "DELETE", "PATCH"])),
+        )
+        .route(
+            "/todos/:id/children",
+            get(children_todo::<T>).fallback(allow(&["GET"])),
+        )
+        .route(
+            "/todos/:id/history",
+            get(todo_history::<T>).fallback(allow(&["GET"])),
+        )
+        .route(
+            "/todos/:id/restore",
+            post(restore_todo::<T>).fallback(allow(&["POST"])),
+        )
+        .route(
+            "/todos/:id/move",
+            patch(move_todo::<T>).fallback(allow(&["PATCH"])),
+        )
+        .route(
+            "/todos/:id/append",
+            post(append_todo::<T>).fallback(allow(&["POST"])),
+        )
+        .route(
+            "/todos/:id/duplicate",
+            post(duplicate_todo::<T, L>).fallback(allow(&["POST"])),
+        )
+        .route(
+            "/todos/:id/labels",
+            get(todo_labels::<T, L>).fallback(allow(&["GET"])),
+        )
+        .route(
+            "/todos/:id/labels/:label_id",
+            post(add_todo_label::<T, L>)
+                .delete(remove_todo_label::<T, L>)
+                .fallback(allow(&["DELETE", "POST"])),
+        )
+        .route(
+            "/export",
+            get(export_dataset::<T, L>).fallback(allow(&["GET"])),
+        )
+        .route(
+            "/import",
+            post(import_dataset::<T>).fallback(allow(&["POST"])),
+        )
+        .route(
+            "/labels",
+            post(create_label::<L>)
+                .get(all_label::<L>)
+                .fallback(allow(&["GET", "POST"])),
+        )
+        .route(
+            "/labels/with-counts",
+            get(all_label_with_counts::<L>).fallback(allow(&["GET"])),
+        )
+        .route(
+            "/labels/unused",
+            get(unused_labels::<L>).fallback(allow(&["GET"])),
+        )
+        .route(
+            "/labels/search",
+            get(search_label::<L>).fallback(allow(&["GET"])),
+        )
+        .route(
+            "/labels/delete",
+            post(bulk_delete_labels::<L>).fallback(allow(&["POST"])),
+        )
+        .route(
+            "/labels/:id",
+            delete(delete_label::<L>)
+                .patch(update_label::<L>)
+                .fallback(allow(&["DELETE", "PATCH"])),
+        )
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(timeout::handle_error))
+                .timeout(timeout::from_env()),
+        )
+        .merge(streaming)
+        .fallback(not_found.into_service())
+        .layer(Extension(todo_repository))
+        .layer(Extension(Arc::new(label_repository)))
+        .layer(Extension(events))
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(body_limit::handle_error))
+                .layer(FilterLayer::new(BodyLimit::from_env())),
+        )
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(read_only::handle_error))
+                .layer(FilterLayer::new(ReadOnly::from_env())),
+        )
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(auth::handle_error))
+                .layer(FilterLayer::new(BearerAuth::from_env())),
+        )
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(rate_limit::handle_error))
+                .layer(FilterLayer::new(RateLimiter::from_env())),
+        )
+        .layer(MetricsLayer)
+        .layer(
+            // アクセスログ(リクエストIDのspanの内側で出す。5xxは on_response で出すので on_failure は使わない)
+            TraceLayer::new_for_http()
+                .on_response(AccessLog::from_env())
+                .on_failure(()),
+        )
+        .layer(RequestIdLayer)
+        .layer(
+            // 小さなレスポンスとSSEストリームは圧縮しない
+            CompressionLayer::new().compress_when(
+                SizeAbove::new(1024)
+                    .and(NotForContentType::GRPC)
+                    .and(NotForContentType::IMAGES)
+                    .and(NotForContentType::const_new("text/event-stream")),
+            ),
         )
-        .layer(Extension(Arc::new(repository)))
         .layer(
             CorsLayer::new()
                 .allow_origin(Origin::exact("http://localhost:3001".parse().unwrap()))
                 .allow_methods(Any)
-                .allow_headers(vec![CONTENT_TYPE]),
+                .allow_headers(vec![
+                    CONTENT_TYPE,
+                    AUTHORIZATION,
+                    HeaderName::from_static(ENVELOPE_HEADER),
+                    HeaderName::from_static(IDEMPOTENCY_KEY),
+                    HeaderName::from_static(USER_ID_HEADER),
+                ])
+                .expose_headers(vec![
+                    LOCATION,
+                    LINK,
+                    HeaderName::from_static("x-total-count"),
+                    HeaderName::from_static(REQUEST_ID_HEADER),
+                ]),
         )
 }
 
-/// ルートのコントローラ
-async fn root() -> &'static str {
-    "Hello! axum!!"
+/// ビルド情報(バージョン・コミットハッシュ・ビルド日時は build.rs で埋め込む)
+async fn version() -> Json<serde_json::Value> {
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "commit": env!("GIT_COMMIT_HASH"),
+        "built_at": env!("BUILD_TIMESTAMP"),
+    }))
+}
+
+/// ログ出力の初期化(環境変数 LOG_FORMAT が json ならJSON、pretty か未設定なら人が読む形式)
+/// レベルは RUST_LOG で指定する
+fn init_logging() {
+    let format = env::var("LOG_FORMAT").unwrap_or_else(|_| "pretty".to_string());
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+    match format.as_str() {
+        "json" => builder.json().init(),
+        "pretty" => builder.init(),
+        _ => {
+            builder.init();
+            tracing::warn!("unknown LOG_FORMAT {}, using pretty", format);
+        }
+    }
+}
+
+/// ルートのコントローラ(サービス名とバージョン、Accept: text/plain なら挨拶文を返す)
+async fn root(headers: HeaderMap) -> Response {
+    let plain = headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/plain"));
+    if plain {
+        return "Hello! axum!!".into_response();
+    }
+    Json(json!({
+        "service": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+    }))
+    .into_response()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::repositories::todo::{test_utils::TodoRepositoryForMemory, CreateTodo, Todo};
+    use crate::repositories::label::{
+        test_utils::LabelRepositoryForMemory, CreateLabel, Label, LabelId, LabelWithCount,
+    };
+    use crate::repositories::todo::{
+        test_utils::{MockTodoRepository, TodoRepositoryForMemory},
+        CreateTodo, Dataset, Health, Todo, TodoId, TodoLabel, TodoWithLabels, DEFAULT_USER,
+    };
     use axum::response::Response;
     use axum::{
         body::Body,
         http::{header, Method, Request, StatusCode},
     };
+    use hyper::body::HttpBody;
     use tower::ServiceExt;
 
     /// Json入りリクエストを作成する
@@ -115,31 +646,87 @@ mod test {
         todo
     }
 
+    /// 既定のユーザーでTODOを順に作成し、作成したTODOを返す
+    async fn create_todos(repository: &TodoRepositoryForMemory, texts: &[&str]) -> Vec<Todo> {
+        let mut todos = Vec::new();
+        for text in texts {
+            let todo = repository
+                .create(DEFAULT_USER, CreateTodo::new(text.to_string()))
+                .await
+                .expect("failed create todo");
+            todos.push(todo);
+        }
+        todos
+    }
+
+    /// IDの形式に合ったTODOのID(存在しないIDやモックの戻り値に使う)
+    #[cfg(not(feature = "uuid-id"))]
+    fn any_todo_id() -> TodoId {
+        TodoId::from(99)
+    }
+
+    /// IDの形式に合ったTODOのID(存在しないIDやモックの戻り値に使う)
+    #[cfg(feature = "uuid-id")]
+    fn any_todo_id() -> TodoId {
+        TodoId::new_v4()
+    }
+
     /// ルートへのリクエスト
     #[tokio::test]
     async fn should_return_hello_world() {
         let repository: TodoRepositoryForMemory = TodoRepositoryForMemory::new();
-        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
-        let res = create_app(repository).oneshot(req).await.unwrap();
+        let req = Request::builder()
+            .uri("/")
+            .header(ACCEPT, "text/plain")
+            .body(Body::empty())
+            .unwrap();
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
         let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
         let body: String = String::from_utf8(bytes.to_vec()).unwrap();
         assert_eq!(body, "Hello! axum!!");
     }
 
+    /// ルートへのリクエスト サービス名とバージョン
+    #[tokio::test]
+    async fn should_return_service_version() {
+        let req = build_todo_req_with_empty("/", Method::GET);
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "service": "my-todo", "version": env!("CARGO_PKG_VERSION") })
+        );
+    }
+
     /// Todoの作成
     #[tokio::test]
     async fn should_created_todo() {
-        let expected = Todo::new(1, "should_return_created_todo".to_string());
-
         let repository = TodoRepositoryForMemory::new();
         let req = build_todo_req_with_json(
             "/todos",
             Method::POST,
             r#"{ "text": "should_return_created_todo" }"#.to_string(),
         );
-        let res = create_app(repository).oneshot(req).await.unwrap();
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        let location = res.headers()[header::LOCATION].clone();
         let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+        assert_eq!(location, format!("/todos/{}", todo.id()));
+        let expected = Todo::new(todo.id(), "should_return_created_todo".to_string());
+        assert_eq!(expected.with_updated_at(todo.updated_at()), todo);
     }
     /// Todoの作成 Jsonパースエラー
     #[tokio::test]
@@ -150,7 +737,10 @@ mod test {
             Method::POST,
             r#"{ "text" :"should_return_created_todo" "#.to_string(),
         );
-        let res = create_app(repository).oneshot(req).await.unwrap();
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
         assert_eq!(res.status(), StatusCode::BAD_REQUEST);
     }
     /// Todoの作成 textが未入力でエラー
@@ -159,8 +749,15 @@ mod test {
         let repository = TodoRepositoryForMemory::new();
         let req =
             build_todo_req_with_json("/todos", Method::POST, r#"{ "text" : "" }"#.to_string());
-        let res = create_app(repository).oneshot(req).await.unwrap();
-        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["errors"][0]["field"], "text");
+        assert_eq!(body["errors"][0]["message"], "Can not be empty");
     }
     /// Todoの作成 textが長すぎでエラー
     #[tokio::test]
@@ -168,121 +765,2863 @@ mod test {
         let repository = TodoRepositoryForMemory::new();
         let req =
             build_todo_req_with_json("/todos", Method::POST, r#"{ "text" : "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa" }"#.to_string());
-        let res = create_app(repository).oneshot(req).await.unwrap();
-        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
     }
 
     /// todoの検索
     #[tokio::test]
     async fn should_find_todo() {
-        let expected = Todo::new(1, "should_find_todo".to_string());
-
         let repository = TodoRepositoryForMemory::new();
-        repository
-            .create(CreateTodo::new("should_find_todo".to_string()))
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("should_find_todo".to_string()),
+            )
             .await
             .expect("failed create todo");
-        let req = build_todo_req_with_empty("/todos/1", Method::GET);
-        let res = create_app(repository).oneshot(req).await.unwrap();
+        let expected = Todo::new(created.id(), "should_find_todo".to_string());
+        let req = build_todo_req_with_empty(&format!("/todos/{}", created.id()), Method::GET);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
         let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+        assert_eq!(expected.with_updated_at(todo.updated_at()), todo);
     }
 
     #[tokio::test]
     async fn should_get_all_todos() {
-        let expected = Todo::new(1, "should_get_all_todos".to_string());
-
         let repository = TodoRepositoryForMemory::new();
-        repository
-            .create(CreateTodo::new("should_get_all_todos".to_string()))
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("should_get_all_todos".to_string()),
+            )
             .await
             .expect("failed create todo");
+        let expected = Todo::new(created.id(), "should_get_all_todos".to_string());
         let req = build_todo_req_with_empty("/todos", Method::GET);
-        let res = create_app(repository).oneshot(req).await.unwrap();
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
         let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
         let body: String = String::from_utf8(bytes.to_vec()).unwrap();
         let todo: Vec<Todo> = serde_json::from_str(&body)
             .expect(&format!("cannot convert Todo instance. body: {}", body));
-        assert_eq!(vec![expected], todo);
+        assert_eq!(vec![expected.with_updated_at(todo[0].updated_at())], todo);
     }
 
     /// Todoの更新
     #[tokio::test]
     async fn should_update_todo() {
-        let expected = Todo::new(1, "should_update_todo".to_string());
-
         let repository = TodoRepositoryForMemory::new();
-        repository
-            .create(CreateTodo::new("before_update_todo".to_string()))
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("before_update_todo".to_string()),
+            )
             .await
             .expect("failed create todo");
+        let expected = Todo::new(created.id(), "should_update_todo".to_string());
         let req = build_todo_req_with_json(
-            "/todos/1",
+            &format!("/todos/{}", created.id()),
             Method::PATCH,
             r#"{
-                "id": 1,
                 "text": "should_update_todo",
                 "completed": false
             }"#
             .to_string(),
         );
-        let res = create_app(repository).oneshot(req).await.unwrap();
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
         let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+        assert_eq!(expected.with_updated_at(todo.updated_at()), todo);
     }
     /// Todoの更新エラー textが未入力
     #[tokio::test]
     async fn should_fail_update_todo_by_text_is_empty() {
         let repository = TodoRepositoryForMemory::new();
-        repository
-            .create(CreateTodo::new("before_update_todo".to_string()))
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("before_update_todo".to_string()),
+            )
             .await
             .expect("failed create todo");
         let req = build_todo_req_with_json(
-            "/todos/1",
+            &format!("/todos/{}", created.id()),
             Method::PATCH,
             r#"{
-                "id": 1,
                 "text": "",
                 "completed": false
             }"#
             .to_string(),
         );
-        let res = create_app(repository).oneshot(req).await.unwrap();
-        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
     }
     /// Todoの更新エラー textが長すぎる
     #[tokio::test]
     async fn should_fail_update_todo_by_text_is_too_long() {
         let repository = TodoRepositoryForMemory::new();
-        repository
-            .create(CreateTodo::new("before_update_todo".to_string()))
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("before_update_todo".to_string()),
+            )
             .await
             .expect("failed create todo");
         let req = build_todo_req_with_json(
-            "/todos/1",
+            &format!("/todos/{}", created.id()),
             Method::PATCH,
             r#"{
-                "id": 1,
                 "text": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
                 "completed": false
             }"#
             .to_string(),
         );
-        let res = create_app(repository).oneshot(req).await.unwrap();
-        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
     }
 
     /// Todoの削除
     #[tokio::test]
     async fn should_delete_todo() {
         let repository = TodoRepositoryForMemory::new();
-        repository
-            .create(CreateTodo::new("should_delete_todo".to_string()))
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("should_delete_todo".to_string()),
+            )
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(&format!("/todos/{}", created.id()), Method::DELETE);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+
+    /// TodoのインポートJSON 長すぎる行はエラーとして数える
+    #[tokio::test]
+    async fn should_import_todos_from_json() {
+        let repository = TodoRepositoryForMemory::new();
+        let req = build_todo_req_with_json(
+            "/todos/import",
+            Method::POST,
+            r#"[
+                { "text": "import 1" },
+                { "text": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa" },
+                { "text": "import 3" }
+            ]"#
+            .to_string(),
+        );
+        let res = create_app(repository.clone(), LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(summary["imported"], 2);
+        assert_eq!(summary["skipped"], 1);
+        assert_eq!(summary["errors"][0]["line"], 2);
+        assert_eq!(repository.all(DEFAULT_USER).await.unwrap().len(), 2);
+    }
+
+    /// TodoのインポートCSV
+    #[tokio::test]
+    async fn should_import_todos_from_csv() {
+        let repository = TodoRepositoryForMemory::new();
+        let req = Request::builder()
+            .uri("/todos/import")
+            .method(Method::POST)
+            .header(CONTENT_TYPE, mime::TEXT_CSV.as_ref())
+            .body(Body::from("text\nimport 1\n\"\"\n"))
+            .unwrap();
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(summary["imported"], 1);
+        assert_eq!(summary["skipped"], 1);
+        assert_eq!(summary["errors"][0]["line"], 3);
+    }
+
+    /// Todoの変更がSSEで配信される
+    #[tokio::test]
+    async fn should_stream_todo_events() {
+        let repository = TodoRepositoryForMemory::new();
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let req = build_todo_req_with_empty("/todos/events", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(
+            res.headers()[CONTENT_TYPE],
+            mime::TEXT_EVENT_STREAM.as_ref()
+        );
+        let mut body = res.into_body();
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "should_stream_todo_events" }"#.to_string(),
+        );
+        app.oneshot(req).await.unwrap();
+        let chunk = body.data().await.unwrap().unwrap();
+        let event = String::from_utf8(chunk.to_vec()).unwrap();
+        assert!(event.contains(r#""type":"created""#));
+        assert!(event.contains("should_stream_todo_events"));
+    }
+
+    /// 子Todoの作成・取得・削除
+    #[tokio::test]
+    async fn should_manage_child_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        let parent = repository
+            .create(DEFAULT_USER, CreateTodo::new("parent".to_string()))
             .await
             .expect("failed create todo");
-        let req = build_todo_req_with_empty("/todos/1", Method::DELETE);
-        let res = create_app(repository).oneshot(req).await.unwrap();
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        // 存在しない親
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            serde_json::json!({ "text": "child", "parent_id": any_todo_id() }).to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            serde_json::json!({ "text": "child", "parent_id": parent.id() }).to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let child = res_to_todo(res).await;
+
+        let req =
+            build_todo_req_with_empty(&format!("/todos/{}/children", parent.id()), Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let children: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![child.clone()], children);
+
+        // 子がいる場合はcascade指定が必要
+        let req = build_todo_req_with_empty(&format!("/todos/{}", parent.id()), Method::DELETE);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let req = build_todo_req_with_empty(
+            &format!("/todos/{}?cascade=true", parent.id()),
+            Method::DELETE,
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
         assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let req = build_todo_req_with_empty(&format!("/todos/{}", child.id()), Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// ラベルの作成 前後の空白は取り除く
+    #[tokio::test]
+    async fn should_created_label() {
+        let req = build_todo_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{ "name": "  should_created_label  " }"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers()[header::LOCATION], "/labels/1");
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let label: Label = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(label.name, "should_created_label");
+    }
+
+    /// ラベルの作成・更新 色の指定
+    #[tokio::test]
+    async fn should_set_label_color() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        // 省略時はグレー
+        let req =
+            build_todo_req_with_json("/labels", Method::POST, r#"{ "name": "gray" }"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let label: Label = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(label.color, "#808080");
+
+        let req = build_todo_req_with_json(
+            "/labels/1",
+            Method::PATCH,
+            r##"{ "color": "#AABBCC" }"##.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let label: Label = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(label.name, "gray");
+        assert_eq!(label.color, "#AABBCC");
+
+        for color in ["red", "#abc", "#gggggg"] {
+            let req = build_todo_req_with_json(
+                "/labels",
+                Method::POST,
+                format!(r#"{{ "name": "red", "color": "{}" }}"#, color),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(
+                res.status(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "color: {}",
+                color
+            );
+        }
+
+        let req = build_todo_req_with_json(
+            "/labels/9",
+            Method::PATCH,
+            r##"{ "color": "#aabbcc" }"##.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// ラベルの作成 同名のラベルがあれば409
+    #[tokio::test]
+    async fn should_conflict_created_label_by_duplicate_name() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let json = r#"{ "name": "duplicate_label" }"#;
+        let req = build_todo_req_with_json("/labels", Method::POST, json.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let req = build_todo_req_with_json("/labels", Method::POST, json.to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["error"], "label exists");
+        assert_eq!(body["id"], 1);
+    }
+
+    /// ラベルの作成 空白のみ・制御文字・長すぎでエラー
+    #[tokio::test]
+    async fn should_fail_created_label_by_invalid_name() {
+        let names = [
+            r#""   ""#.to_string(),
+            r#""bad\u0007name""#.to_string(),
+            format!(r#""{}""#, "a".repeat(51)),
+        ];
+        for name in names {
+            let req = build_todo_req_with_json(
+                "/labels",
+                Method::POST,
+                format!(r#"{{ "name": {} }}"#, name),
+            );
+            let res = create_app(
+                TodoRepositoryForMemory::new(),
+                LabelRepositoryForMemory::new(),
+            )
+            .oneshot(req)
+            .await
+            .unwrap();
+            assert_eq!(
+                res.status(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "name: {}",
+                name
+            );
+        }
+    }
+
+    /// ページング指定で取得
+    #[tokio::test]
+    async fn should_get_paged_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        // ページは一覧と同じ並び順(既定は position、作成順に振られる)で切り出す
+        let created = create_todos(&repository, &["todo 1", "todo 2", "todo 3"]).await;
+        let req = build_todo_req_with_empty("/todos?limit=1&offset=1", Method::GET);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+        let expected = created[1].clone().with_updated_at(todos[0].updated_at());
+        assert_eq!(vec![expected], todos);
+    }
+
+    /// カーソル指定で取得
+    #[tokio::test]
+    async fn should_get_todos_by_cursor() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = create_todos(&repository, &["todo 1", "todo 2", "todo 3"]).await;
+        // カーソルはIDの順に進む
+        let mut ids: Vec<TodoId> = created.iter().map(|todo| todo.id()).collect();
+        ids.sort();
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        // 空のカーソルは先頭から
+        let req = build_todo_req_with_empty("/todos?after=&limit=2", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let page: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(page["todos"][0]["id"], serde_json::json!(ids[0]));
+        assert_eq!(page["todos"][1]["id"], serde_json::json!(ids[1]));
+        assert_eq!(page["next_cursor"], serde_json::json!(ids[1]));
+
+        let req =
+            build_todo_req_with_empty(&format!("/todos?after={}&limit=2", ids[1]), Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let page: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(page["todos"].as_array().unwrap().len(), 1);
+        assert_eq!(page["todos"][0]["id"], serde_json::json!(ids[2]));
+        assert!(page["next_cursor"].is_null());
+    }
+
+    /// envelope指定でメタデータ付きの一覧を取得
+    #[tokio::test]
+    async fn should_get_todos_in_envelope() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = create_todos(&repository, &["todo 1", "todo 2", "todo 3"]).await;
+        let first_id = created[0].id();
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let req = build_todo_req_with_empty("/todos?limit=1&envelope=true", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"][0]["id"], serde_json::json!(first_id));
+        assert_eq!(body["meta"]["total"], 3);
+
+        // 作成・取得と同じく X-Envelope ヘッダでも指定できる
+        let req = Request::builder()
+            .uri("/todos")
+            .header("X-Envelope", "true")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["data"].as_array().unwrap().len(), 3);
+        assert_eq!(body["meta"]["total"], 3);
+    }
+
+    /// ページング指定 limitが大きすぎる場合は最大件数に切り詰める
+    #[tokio::test]
+    async fn should_clamp_too_large_limit() {
+        let repository = TodoRepositoryForMemory::new();
+        for i in 0..101 {
+            repository
+                .create(DEFAULT_USER, CreateTodo::new(format!("todo {}", i)))
+                .await
+                .expect("failed create todo");
+        }
+        let req = build_todo_req_with_empty("/todos?limit=1000000&envelope=true", Method::GET);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["data"].as_array().unwrap().len(), 100);
+        assert_eq!(body["meta"]["total"], 101);
+        assert_eq!(body["meta"]["limit"], 100);
+    }
+
+    /// 大きなレスポンスはgzip圧縮される
+    #[tokio::test]
+    async fn should_compress_large_response() {
+        let repository = TodoRepositoryForMemory::new();
+        for i in 0..30 {
+            repository
+                .create(
+                    DEFAULT_USER,
+                    CreateTodo::new(format!("should_compress_large_response {}", i)),
+                )
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let req = Request::builder()
+            .uri("/todos")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+
+        // 小さなレスポンスは圧縮しない
+        let req = Request::builder()
+            .uri("/todos/1")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+    }
+
+    /// 同じIdempotency-Keyでの作成は作成済みのTodoを返す
+    #[tokio::test]
+    async fn should_return_same_todo_with_idempotency_key() {
+        let repository = TodoRepositoryForMemory::new();
+        let app = create_app(repository.clone(), LabelRepositoryForMemory::new());
+        let build_req = || {
+            Request::builder()
+                .uri("/todos")
+                .method(Method::POST)
+                .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("Idempotency-Key", "should_return_same_todo")
+                .body(Body::from(r#"{ "text": "idempotent" }"#))
+                .unwrap()
+        };
+        let res = app.clone().oneshot(build_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let created = res_to_todo(res).await;
+
+        let res = app.oneshot(build_req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(created, res_to_todo(res).await);
+        assert_eq!(repository.all(DEFAULT_USER).await.unwrap().len(), 1);
+    }
+
+    /// 今日が期限の未完了Todoを取得
+    #[tokio::test]
+    async fn should_get_today_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        let now = chrono::Utc::now();
+        let tomorrow = now + chrono::Duration::days(1);
+        for (text, due_date) in [("today", now), ("tomorrow", tomorrow)] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(
+                    r#"{{ "text": "{}", "due_date": "{}" }}"#,
+                    text,
+                    due_date.to_rfc3339()
+                ),
+            );
+            create_app(repository.clone(), LabelRepositoryForMemory::new())
+                .oneshot(req)
+                .await
+                .unwrap();
+        }
+        repository
+            .create(DEFAULT_USER, CreateTodo::new("no due date".to_string()))
+            .await
+            .expect("failed create todo");
+
+        let req = build_todo_req_with_empty("/todos/today", Method::GET);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<serde_json::Value> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0]["text"], "today");
+    }
+
+    /// Todoの更新 キー省略は変更なし、nullは値を消す
+    #[tokio::test]
+    async fn should_update_nullable_fields() {
+        let repository = TodoRepositoryForMemory::new();
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "nullable", "due_date": "2025-06-01T00:00:00Z", "priority": 3 }"#
+                .to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let path = format!("/todos/{}", res_to_todo(res).await.id());
+
+        // キー省略
+        let req = build_todo_req_with_json(
+            &path,
+            Method::PATCH,
+            r#"{ "text": "nullable updated" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todo: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(todo["due_date"], "2025-06-01T00:00:00Z");
+        assert_eq!(todo["priority"], 3);
+
+        // null指定
+        let req = build_todo_req_with_json(
+            &path,
+            Method::PATCH,
+            r#"{ "due_date": null, "priority": null }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todo: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(todo["text"], "nullable updated");
+        assert!(todo["due_date"].is_null());
+        assert!(todo["priority"].is_null());
+    }
+
+    /// 削除したTodoを復元する
+    #[tokio::test]
+    async fn should_restore_deleted_todo() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("should_restore_deleted_todo".to_string()),
+            )
+            .await
+            .expect("failed create todo");
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let req = build_todo_req_with_empty(&format!("/todos/{}", created.id()), Method::DELETE);
+        app.clone().oneshot(req).await.unwrap();
+
+        let restore_path = format!("/todos/{}/restore", created.id());
+        let req = build_todo_req_with_empty(&restore_path, Method::POST);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(created, res_to_todo(res).await);
+
+        // 復元済み・未削除のものは復元できない
+        let req = build_todo_req_with_empty(&restore_path, Method::POST);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// 他のユーザーのTodoは見えない
+    #[tokio::test]
+    async fn should_scope_todos_by_user() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create("alice", CreateTodo::new("alice todo".to_string()))
+            .await
+            .expect("failed create todo");
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let path = format!("/todos/{}", created.id());
+
+        let req = build_todo_req_with_empty(&path, Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let req = build_todo_req_with_empty(&path, Method::DELETE);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let req = Request::builder()
+            .uri(&path)
+            .header("x-user-id", "alice")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    /// 更新が無ければ304を返す
+    #[tokio::test]
+    async fn should_return_not_modified() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("should_return_not_modified".to_string()),
+            )
+            .await
+            .expect("failed create todo");
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let path = format!("/todos/{}", created.id());
+        let req = build_todo_req_with_empty(&path, Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let last_modified = res.headers()[header::LAST_MODIFIED].clone();
+
+        let req = Request::builder()
+            .uri(&path)
+            .header(header::IF_MODIFIED_SINCE, last_modified)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(bytes.is_empty());
+
+        // 古い日時を指定すると本文を返す
+        let req = Request::builder()
+            .uri(&path)
+            .header(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    /// dry_run 指定では作成・更新しない
+    #[tokio::test]
+    async fn should_not_write_on_dry_run() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new("before_dry_run".to_string()))
+            .await
+            .expect("failed create todo");
+        let app = create_app(repository.clone(), LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_json(
+            "/todos?dry_run=true",
+            Method::POST,
+            r#"{ "text": "dry_run_create" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let todo = res_to_todo(res).await;
+        let expected = Todo::new(TodoId::default(), "dry_run_create".to_string());
+        assert_eq!(expected.with_updated_at(todo.updated_at()), todo);
+
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}?dry_run=true", created.id()),
+            Method::PATCH,
+            r#"{ "text": "dry_run_update" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let todo = res_to_todo(res).await;
+        let expected = Todo::new(created.id(), "dry_run_update".to_string());
+        assert_eq!(expected.with_updated_at(todo.updated_at()), todo);
+
+        // 存在しないTODOの更新は404
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}?dry_run=true", any_todo_id()),
+            Method::PATCH,
+            r#"{ "text": "dry_run_update" }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let todos = repository.all(DEFAULT_USER).await.unwrap();
+        let expected = Todo::new(created.id(), "before_dry_run".to_string());
+        assert_eq!(vec![expected.with_updated_at(todos[0].updated_at())], todos);
+    }
+
+    /// 繰り返しTODOを完了にすると次の回が作成される
+    #[tokio::test]
+    async fn should_create_next_weekly_occurrence() {
+        let repository = TodoRepositoryForMemory::new();
+        let app = create_app(repository.clone(), LabelRepositoryForMemory::new());
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "water plants", "due_date": "2025-07-01T09:00:00Z", "recurrence": "weekly" }"#
+                .to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let first_id = res_to_todo(res).await.id();
+        let path = format!("/todos/{}", first_id);
+
+        let req =
+            build_todo_req_with_json(&path, Method::PATCH, r#"{ "completed": true }"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // 次の回はバックグラウンドで作成される
+        let mut next = None;
+        for _ in 0..100 {
+            let todos = repository.all(DEFAULT_USER).await.unwrap();
+            if let Some(todo) = todos.into_iter().find(|todo| todo.id() != first_id) {
+                next = Some(todo);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let next = serde_json::to_value(next.expect("next occurrence is not created")).unwrap();
+        assert_eq!(next["text"], "water plants");
+        assert_eq!(next["completed"], false);
+        assert_eq!(next["due_date"], "2025-07-08T09:00:00Z");
+        assert_eq!(next["recurrence"], "weekly");
+
+        // 完了した回は繰り返しでなくなり、再度更新しても次の回は増えない
+        let req = build_todo_req_with_empty(&path, Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let done: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(done["recurrence"].is_null());
+        let req =
+            build_todo_req_with_json(&path, Method::PATCH, r#"{ "completed": true }"#.to_string());
+        app.oneshot(req).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(repository.all(DEFAULT_USER).await.unwrap().len(), 2);
+    }
+
+    /// 繰り返しの指定が不正ならエラー
+    #[tokio::test]
+    async fn should_fail_created_todo_by_invalid_recurrence() {
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "water plants", "recurrence": "hourly" }"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    /// Todoの移動 兄弟の並び順が詰め直される
+    #[tokio::test]
+    async fn should_move_todo() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = create_todos(&repository, &["todo 1", "todo 2", "todo 3"]).await;
+        let ids: Vec<TodoId> = created.iter().map(|todo| todo.id()).collect();
+        let app = create_app(repository.clone(), LabelRepositoryForMemory::new());
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}/move", ids[2]),
+            Method::PATCH,
+            r#"{ "position": 0 }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let todos = repository.all(DEFAULT_USER).await.unwrap();
+        let moved: Vec<_> = todos.iter().map(|todo| todo.id()).collect();
+        assert_eq!(vec![ids[2], ids[0], ids[1]], moved);
+
+        // 範囲外は末尾
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}/move", ids[2]),
+            Method::PATCH,
+            r#"{ "position": 10 }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let todos = repository.all(DEFAULT_USER).await.unwrap();
+        let moved: Vec<_> = todos.iter().map(|todo| todo.id()).collect();
+        assert_eq!(ids, moved);
+
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}/move", any_todo_id()),
+            Method::PATCH,
+            r#"{ "position": 0 }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// 完了状態の一括更新 存在しないIDは無視する
+    #[tokio::test]
+    async fn should_bulk_complete_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = create_todos(&repository, &["todo 1", "todo 2", "todo 3"]).await;
+        let req = build_todo_req_with_json(
+            "/todos/bulk-complete",
+            Method::POST,
+            serde_json::json!({
+                "ids": [created[0].id(), created[2].id(), any_todo_id()],
+                "completed": true
+            })
+            .to_string(),
+        );
+        let res = create_app(repository.clone(), LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(summary["updated"], 2);
+
+        let stats = repository.stats().await.unwrap();
+        assert_eq!(stats.completed, 2);
+    }
+
+    /// 本文が大きすぎるリクエストは413
+    #[tokio::test]
+    async fn should_reject_too_large_body() {
+        let text = "a".repeat(64 * 1024);
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(r#"{{ "text": "{}" }}"#, text),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["error"], "payload too large");
+    }
+
+    /// Todoの更新 存在しなければ404、予期しないエラーは500
+    #[tokio::test]
+    async fn should_distinguish_update_errors() {
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", any_todo_id()),
+            Method::PATCH,
+            r#"{ "text": "should_distinguish_update_errors" }"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", any_todo_id()),
+            Method::PATCH,
+            r#"{ "text": "should_distinguish_update_errors" }"#.to_string(),
+        );
+        let repository = MockTodoRepository::new();
+        repository.push_result::<Todo>(
+            "update",
+            Err(RepositoryError::Unexpected("connection reset".to_string()).into()),
+        );
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    /// モックリポジトリは設定した順に結果を返し、無くなれば予期しないエラーになる
+    #[tokio::test]
+    async fn should_return_preset_results_from_mock() {
+        let repository = MockTodoRepository::new();
+        let todo = Todo::new(
+            any_todo_id(),
+            "should_return_preset_results_from_mock".to_string(),
+        );
+        repository.push_result("find_opt", Ok(Some(todo.clone())));
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_empty(&format!("/todos/{}", todo.id()), Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(todo, res_to_todo(res).await);
+
+        let req = build_todo_req_with_empty("/todos", Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    /// 本文は最大文字数(既定100)まで
+    #[tokio::test]
+    async fn should_limit_todo_text_length() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let text = "あ".repeat(max_text_len());
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(r#"{{ "text": "{}" }}"#, text),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(r#"{{ "text": "{}あ" }}"#, text),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["errors"][0]["message"], "Over text length");
+    }
+
+    /// Todoの部分更新 省略した項目は変更しない
+    #[tokio::test]
+    async fn should_merge_patched_todo() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new("before".to_string()))
+            .await
+            .expect("failed create todo");
+        let app = create_app(repository.clone(), LabelRepositoryForMemory::new());
+        let path = format!("/todos/{}", created.id());
+
+        let cases = [
+            (r#"{ "text": "only text" }"#, "only text", false),
+            (r#"{ "completed": true }"#, "only text", true),
+            (r#"{ "text": "both", "completed": false }"#, "both", false),
+            (r#"{}"#, "both", false),
+        ];
+        for (body, text, completed) in cases {
+            let req = build_todo_req_with_json(&path, Method::PATCH, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK, "body: {}", body);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todo: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(todo["text"], text, "body: {}", body);
+            assert_eq!(todo["completed"], completed, "body: {}", body);
+        }
+
+        // 項目が無ければ更新日時も変わらない
+        let before = repository.find(DEFAULT_USER, created.id()).await.unwrap();
+        let req = build_todo_req_with_json(&path, Method::PATCH, "{}".to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res_to_todo(res).await, before);
+        let after = repository.find(DEFAULT_USER, created.id()).await.unwrap();
+        assert_eq!(after, before);
+    }
+
+    /// Todoに付いたラベルの取得
+    #[tokio::test]
+    async fn should_get_todo_labels() {
+        let repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let todos = create_todos(&repository, &["labeled", "no labels"]).await;
+        let label = label_repository
+            .create(CreateLabel::new("label".to_string()))
+            .await
+            .expect("failed create label");
+        label_repository
+            .add_label(todos[0].id(), label.id)
+            .await
+            .expect("failed add label");
+        let app = create_app(repository, label_repository);
+
+        let req =
+            build_todo_req_with_empty(&format!("/todos/{}/labels", todos[0].id()), Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(labels, vec![label]);
+
+        let req =
+            build_todo_req_with_empty(&format!("/todos/{}/labels", todos[1].id()), Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&bytes[..], b"[]");
+
+        let req =
+            build_todo_req_with_empty(&format!("/todos/{}/labels", any_todo_id()), Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// 数値でないIDはJSONで400
+    #[tokio::test]
+    async fn should_reject_invalid_id() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        for (path, method) in [
+            ("/todos/abc", Method::GET),
+            ("/todos/abc/children", Method::GET),
+            ("/labels/abc", Method::DELETE),
+        ] {
+            let req = build_todo_req_with_empty(path, method);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "path: {}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["error"], "invalid id", "path: {}", path);
+        }
+    }
+
+    /// ヘルスチェック メモリではTODOの件数を返す
+    #[tokio::test]
+    async fn should_return_health() {
+        let repository = TodoRepositoryForMemory::new();
+        repository
+            .create(DEFAULT_USER, CreateTodo::new("todo".to_string()))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty("/health", Method::GET);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, serde_json::json!({ "ok": true, "todos": 1 }));
+    }
+
+    /// ラベルの前方一致検索
+    #[tokio::test]
+    async fn should_search_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["Work", "workout", "home", "wo%"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        let app = create_app(TodoRepositoryForMemory::new(), label_repository);
+
+        for (path, expected) in [
+            ("/labels/search?prefix=wo", vec!["Work", "wo%", "workout"]),
+            ("/labels/search?prefix=WORK", vec!["Work", "workout"]),
+            ("/labels/search?prefix=x", vec![]),
+            ("/labels/search", vec!["Work", "home", "wo%", "workout"]),
+            ("/labels/search?prefix=wo&limit=1", vec!["Work"]),
+        ] {
+            let req = build_todo_req_with_empty(path, Method::GET);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+            let names: Vec<&str> = labels.iter().map(|label| label.name.as_str()).collect();
+            assert_eq!(names, expected, "path: {}", path);
+        }
+    }
+
+    /// ラベル付きのTodo一覧
+    #[tokio::test]
+    async fn should_get_todos_with_labels() {
+        let repository = TodoRepositoryForMemory::new();
+        let todos = create_todos(&repository, &["todo 1", "todo 2", "todo 3"]).await;
+        let label = Label {
+            id: LabelId::from(1),
+            name: "shared".to_string(),
+            color: "#808080".to_string(),
+        };
+        repository.attach_label(todos[0].id(), label.clone());
+        repository.attach_label(todos[1].id(), label.clone());
+        let req = build_todo_req_with_empty("/todos?labels=true", Method::GET);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoWithLabels> = serde_json::from_slice(&bytes).unwrap();
+        let labels: Vec<Vec<Label>> = todos.into_iter().map(|todo| todo.labels).collect();
+        assert_eq!(labels, vec![vec![label.clone()], vec![label], vec![]]);
+    }
+
+    /// 処理が時間の上限(既定30秒)を超えたら504
+    #[tokio::test(start_paused = true)]
+    async fn should_time_out_slow_request() {
+        let repository = MockTodoRepository::new().with_delay(Duration::from_secs(60));
+        let todo = Todo::new(any_todo_id(), "slow".to_string());
+        let req = build_todo_req_with_empty(&format!("/todos/{}", todo.id()), Method::GET);
+        repository.push_result("find_opt", Ok(Some(todo)));
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["error"], "request timeout");
+    }
+
+    /// 返す項目の指定
+    #[tokio::test]
+    async fn should_return_only_specified_fields() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new("todo".to_string()))
+            .await
+            .expect("failed create todo");
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_empty(
+            &format!("/todos/{}?fields=id,completed", created.id()),
+            Method::GET,
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "id": created.id(), "completed": false })
+        );
+
+        let req = build_todo_req_with_empty("/todos?fields=text", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, serde_json::json!([{ "text": "todo" }]));
+
+        let req = build_todo_req_with_empty("/todos?fields=id,secret", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["errors"][0]["message"], "Unknown field [secret]");
+
+        // 指定が無ければすべての項目
+        let req = build_todo_req_with_empty(&format!("/todos/{}", created.id()), Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.as_object().unwrap().len(), 12);
+    }
+
+    /// Todoにラベルを付ける 新たに付けたら201、既に付いていれば200
+    #[tokio::test]
+    async fn should_add_todo_label() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new("todo".to_string()))
+            .await
+            .expect("failed create todo");
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("label".to_string()))
+            .await
+            .expect("failed create label");
+        let app = create_app(repository, label_repository);
+
+        for expected in [StatusCode::CREATED, StatusCode::OK] {
+            let req = build_todo_req_with_empty(
+                &format!("/todos/{}/labels/{}", created.id(), label.id),
+                Method::POST,
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), expected);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(labels, vec![label.clone()]);
+        }
+
+        for (path, error) in [
+            (
+                format!("/todos/{}/labels/{}", any_todo_id(), label.id),
+                "todo not found",
+            ),
+            (
+                format!("/todos/{}/labels/9", created.id()),
+                "label not found",
+            ),
+        ] {
+            let req = build_todo_req_with_empty(&path, Method::POST);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["error"], error);
+        }
+    }
+
+    /// ラベルの一括削除 存在しないIDは無視する
+    #[tokio::test]
+    async fn should_bulk_delete_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["a", "b", "c"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        let app = create_app(TodoRepositoryForMemory::new(), label_repository.clone());
+
+        let req = build_todo_req_with_json(
+            "/labels/delete",
+            Method::POST,
+            r#"{ "ids": [1, 3, 9] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, serde_json::json!({ "deleted": 2 }));
+        let labels = label_repository.all().await.unwrap();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].name, "b");
+
+        let req = build_todo_req_with_json(
+            "/labels/delete",
+            Method::POST,
+            r#"{ "ids": [] }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    /// 受け付けないメソッドはJSONの405とAllowヘッダーを返す
+    #[tokio::test]
+    async fn should_return_method_not_allowed() {
+        for (path, method, allowed) in [
+            ("/todos", Method::PUT, vec!["GET", "POST"]),
+            ("/todos/1", Method::POST, vec!["GET", "DELETE", "PATCH"]),
+        ] {
+            let req = build_todo_req_with_empty(path, method);
+            let res = create_app(
+                TodoRepositoryForMemory::new(),
+                LabelRepositoryForMemory::new(),
+            )
+            .oneshot(req)
+            .await
+            .unwrap();
+            assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(res.headers()["allow"], allowed.join(", ").as_str());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(
+                body,
+                serde_json::json!({ "error": "method not allowed", "allowed": allowed })
+            );
+        }
+    }
+
+    /// どのルートにも一致しないパスはJSONの404を返す
+    #[tokio::test]
+    async fn should_return_not_found_for_unknown_path() {
+        let req = build_todo_req_with_empty("/todoss", Method::GET);
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "not found", "path": "/todoss" })
+        );
+
+        // ハンドラーが返す404はそのまま
+        let req = build_todo_req_with_empty(&format!("/todos/{}", any_todo_id()), Method::GET);
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    /// completed は文字列の "true" / "false" も受け付ける
+    #[tokio::test]
+    async fn should_accept_completed_as_string() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let created = todo_repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("should_accept_completed_as_string".to_string()),
+            )
+            .await
+            .expect("failed create todo");
+        let app = create_app(todo_repository, LabelRepositoryForMemory::new());
+        let path = format!("/todos/{}", created.id());
+
+        for (completed, expected) in [(r#""TRUE""#, true), (r#""false""#, false), ("true", true)] {
+            let req = build_todo_req_with_json(
+                &path,
+                Method::PATCH,
+                format!(r#"{{ "completed": {} }}"#, completed),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todo: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(todo["completed"], expected);
+        }
+
+        let req = build_todo_req_with_json(
+            &path,
+            Method::PATCH,
+            r#"{ "completed": "yes" }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// 一覧の絞り込み条件を組み合わせる
+    #[tokio::test]
+    async fn should_filter_todos() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("shopping".to_string()))
+            .await
+            .unwrap();
+        let app = create_app(todo_repository.clone(), label_repository);
+        let mut created = Vec::new();
+        for (text, due_date) in [
+            ("Buy milk", "2030-01-10T00:00:00Z"),
+            ("buy bread", "2030-02-10T00:00:00Z"),
+            ("Write report", "2030-01-20T00:00:00Z"),
+        ] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}", "due_date": "{}" }}"#, text, due_date),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::CREATED);
+            created.push(res_to_todo(res).await.id());
+        }
+        let label_id = label.id;
+        todo_repository.attach_label(created[1], label);
+
+        for (query, expected) in [
+            ("q=BUY".to_string(), vec![0, 1]),
+            ("q=buy&due_before=2030-02-01T00:00:00Z".to_string(), vec![0]),
+            ("due_after=2030-01-15T00:00:00Z".to_string(), vec![1, 2]),
+            (format!("label_id={}", label_id), vec![1]),
+            ("completed=true".to_string(), vec![]),
+            ("completed=false&q=report&unknown=1".to_string(), vec![2]),
+        ] {
+            let req = build_todo_req_with_empty(&format!("/todos?{}", query), Method::GET);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK, "{}", query);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+            let ids: Vec<_> = todos.iter().map(|todo| todo.id()).collect();
+            let expected: Vec<_> = expected.into_iter().map(|index| created[index]).collect();
+            assert_eq!(ids, expected, "{}", query);
+        }
+
+        let req = build_todo_req_with_empty(
+            "/todos?due_after=2030-02-01T00:00:00Z&due_before=2030-01-01T00:00:00Z",
+            Method::GET,
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// ビルド情報を返す
+    #[tokio::test]
+    async fn should_return_version() {
+        let req = build_todo_req_with_empty("/version", Method::GET);
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["commit"], env!("GIT_COMMIT_HASH"));
+        assert!(body["built_at"]
+            .as_str()
+            .unwrap()
+            .parse::<chrono::DateTime<chrono::Utc>>()
+            .is_ok());
+    }
+
+    /// 更新イベントには変わった項目名が付く
+    #[tokio::test]
+    async fn should_stream_changed_fields() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new("before".to_string()))
+            .await
+            .expect("failed create todo");
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let req = build_todo_req_with_empty("/todos/events", Method::GET);
+        let mut body = app.clone().oneshot(req).await.unwrap().into_body();
+
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", created.id()),
+            Method::PATCH,
+            r#"{ "text": "after", "completed": false }"#.to_string(),
+        );
+        app.oneshot(req).await.unwrap();
+        let chunk = body.data().await.unwrap().unwrap();
+        let event = String::from_utf8(chunk.to_vec()).unwrap();
+        assert!(event.contains(r#""type":"updated""#));
+        assert!(event.contains(r#""changed":["text"]"#), "{}", event);
+    }
+
+    /// 完了済みとして作成できる(省略時は未完了)
+    #[tokio::test]
+    async fn should_create_completed_todo() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        for (body, expected) in [
+            (r#"{ "text": "done", "completed": true }"#, true),
+            (r#"{ "text": "open" }"#, false),
+        ] {
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todo: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(todo["completed"], expected);
+        }
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "invalid", "completed": "yes" }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// レスポンスにリクエストIDが付く
+    #[tokio::test]
+    async fn should_return_request_id() {
+        let req = Request::builder()
+            .uri("/todos")
+            .header("x-request-id", "should_return_request_id")
+            .body(Body::empty())
+            .unwrap();
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.headers()["x-request-id"], "should_return_request_id");
+    }
+
+    /// TODO件数の上限に達したら作成しない
+    #[tokio::test]
+    async fn should_limit_todos_per_user() {
+        let repository = TodoRepositoryForMemory::new().with_quota(1);
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        for (text, status) in [
+            ("first", StatusCode::CREATED),
+            ("second", StatusCode::FORBIDDEN),
+        ] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}" }}"#, text),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), status);
+        }
+
+        let req =
+            build_todo_req_with_json("/todos", Method::POST, r#"{ "text": "third" }"#.to_string());
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "quota exceeded", "limit": 1 })
+        );
+    }
+
+    /// リリースビルドでは明示しない限りインメモリDBで起動しない
+    #[test]
+    fn should_allow_memory_backend_only_explicitly_in_release() {
+        assert!(allow_memory_backend(true, None));
+        assert!(allow_memory_backend(false, Some("true")));
+        assert!(!allow_memory_backend(false, None));
+        assert!(!allow_memory_backend(false, Some("yes")));
+    }
+
+    /// 複数のIDで指定した順に取得する
+    #[tokio::test]
+    async fn should_find_todos_by_ids() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = create_todos(&repository, &["one", "two", "three"]).await;
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_json(
+            "/todos/by-ids",
+            Method::POST,
+            serde_json::json!({ "ids": [created[2].id(), any_todo_id(), created[0].id()] })
+                .to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+        let ids: Vec<_> = todos.iter().map(|todo| todo.id()).collect();
+        assert_eq!(ids, vec![created[2].id(), created[0].id()]);
+
+        let req = build_todo_req_with_json(
+            "/todos/by-ids",
+            Method::POST,
+            r#"{ "ids": [] }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    /// 期限がN日以内の未完了Todoを期限の早い順に取得
+    #[tokio::test]
+    async fn should_get_upcoming_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        let now = chrono::Utc::now();
+        let app = create_app(repository.clone(), LabelRepositoryForMemory::new());
+        for (text, days) in [
+            ("in 5 days", 5),
+            ("in 2 days", 2),
+            ("in 10 days", 10),
+            ("past", -1),
+        ] {
+            let due_date = now + chrono::Duration::days(days);
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(
+                    r#"{{ "text": "{}", "due_date": "{}" }}"#,
+                    text,
+                    due_date.to_rfc3339()
+                ),
+            );
+            app.clone().oneshot(req).await.unwrap();
+        }
+        repository
+            .create(DEFAULT_USER, CreateTodo::new("no due date".to_string()))
+            .await
+            .expect("failed create todo");
+
+        for (path, expected) in [
+            ("/todos/upcoming", vec!["in 2 days", "in 5 days"]),
+            (
+                "/todos/upcoming?days=30",
+                vec!["in 2 days", "in 5 days", "in 10 days"],
+            ),
+        ] {
+            let req = build_todo_req_with_empty(path, Method::GET);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todos: Vec<serde_json::Value> = serde_json::from_slice(&bytes).unwrap();
+            let texts: Vec<_> = todos
+                .iter()
+                .map(|todo| todo["text"].as_str().unwrap())
+                .collect();
+            assert_eq!(texts, expected);
+        }
+
+        for path in ["/todos/upcoming?days=0", "/todos/upcoming?days=366"] {
+            let req = build_todo_req_with_empty(path, Method::GET);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    /// IDごとの一括更新 1件ずつ結果を返す
+    #[tokio::test]
+    async fn should_batch_update_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = create_todos(&repository, &["one", "two"]).await;
+        let (one, two, missing) = (created[0].id(), created[1].id(), any_todo_id());
+        let app = create_app(repository.clone(), LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_json(
+            "/todos/batch",
+            Method::PATCH,
+            serde_json::json!([
+                { "id": one, "text": "a" },
+                { "id": missing, "completed": true },
+                { "id": two, "text": "" },
+                { "text": "no id" },
+                { "id": two, "completed": true }
+            ])
+            .to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let results: Vec<serde_json::Value> = serde_json::from_slice(&bytes).unwrap();
+        let statuses: Vec<_> = results
+            .iter()
+            .map(|result| (result["id"].clone(), result["status"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (serde_json::json!(one), "ok"),
+                (serde_json::json!(missing), "not_found"),
+                (serde_json::json!(two), "invalid"),
+                (serde_json::Value::Null, "invalid"),
+                (serde_json::json!(two), "ok"),
+            ]
+        );
+
+        let todo = repository.find(DEFAULT_USER, one).await.unwrap();
+        assert_eq!(
+            todo,
+            Todo::new(one, "a".to_string()).with_updated_at(todo.updated_at())
+        );
+        let todo = repository.find(DEFAULT_USER, two).await.unwrap();
+        let bytes = serde_json::to_value(&todo).unwrap();
+        assert_eq!(bytes["text"], "two");
+        assert_eq!(bytes["completed"], true);
+    }
+
+    /// ラベルごとの使用件数 使われていないラベルは0件
+    #[tokio::test]
+    async fn should_get_labels_with_counts() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new("todo".to_string()))
+            .await
+            .expect("failed create todo");
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["used", "unused"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        label_repository
+            .add_label(created.id(), LabelId::from(1))
+            .await
+            .expect("failed add label");
+
+        let req = build_todo_req_with_empty("/labels/with-counts", Method::GET);
+        let res = create_app(repository, label_repository)
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<LabelWithCount> = serde_json::from_slice(&bytes).unwrap();
+        let counts: Vec<_> = labels
+            .iter()
+            .map(|label| (label.name.as_str(), label.todo_count))
+            .collect();
+        assert_eq!(counts, vec![("used", 1), ("unused", 0)]);
+    }
+
+    /// 取得 見つからなければ404、リポジトリの失敗は500
+    #[tokio::test]
+    async fn should_distinguish_missing_todo_from_failure() {
+        let repository = MockTodoRepository::new();
+        repository.push_result("find_opt", Ok(None::<Todo>));
+        repository.push_result(
+            "find_opt",
+            Err::<Option<Todo>, _>(
+                RepositoryError::Unexpected("connection reset".to_string()).into(),
+            ),
+        );
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let path = format!("/todos/{}", any_todo_id());
+
+        let req = build_todo_req_with_empty(&path, Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let req = build_todo_req_with_empty(&path, Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    /// Content-Type が無い・JSONでない場合は415、JSONとして読めなければ400
+    #[tokio::test]
+    async fn should_reject_missing_json_content_type() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        for content_type in [None, Some("text/plain")] {
+            let mut req = Request::builder().uri("/todos").method(Method::POST);
+            if let Some(content_type) = content_type {
+                req = req.header(CONTENT_TYPE, content_type);
+            }
+            let req = req
+                .body(Body::from(r#"{ "text": "no content type" }"#))
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["error"], "expected application/json");
+        }
+
+        let req = build_todo_req_with_json("/todos", Method::POST, "{".to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// 複製 本文に "Copy of " を付けて未完了で作成し、指定があればラベルも付ける
+    #[tokio::test]
+    async fn should_duplicate_todo() {
+        let repository = TodoRepositoryForMemory::new();
+        let source = repository
+            .create(DEFAULT_USER, CreateTodo::new("source".to_string()))
+            .await
+            .expect("failed create todo");
+        repository
+            .set_completed_many(DEFAULT_USER, &[source.id()], true)
+            .await
+            .expect("failed complete todo");
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("label".to_string()))
+            .await
+            .expect("failed create label");
+        label_repository
+            .add_label(source.id(), label.id)
+            .await
+            .expect("failed add label");
+        let app = create_app(repository, label_repository.clone());
+
+        let path = format!("/todos/{}/duplicate", source.id());
+        let req = build_todo_req_with_empty(&path, Method::POST);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = res.headers()[header::LOCATION].clone();
+        let todo = res_to_todo(res).await;
+        assert_ne!(todo.id(), source.id());
+        assert_eq!(location, format!("/todos/{}", todo.id()));
+        assert_eq!(
+            todo,
+            Todo::new(todo.id(), "Copy of source".to_string())
+                .with_position(1)
+                .with_updated_at(todo.updated_at())
+        );
+        assert!(label_repository
+            .find_by_todo(todo.id())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let req = build_todo_req_with_empty(&format!("{}?with_labels=true", path), Method::POST);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let todo = res_to_todo(res).await;
+        assert_eq!(
+            label_repository.find_by_todo(todo.id()).await.unwrap(),
+            vec![label]
+        );
+
+        let req =
+            build_todo_req_with_empty(&format!("/todos/{}/duplicate", any_todo_id()), Method::POST);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// 存在しないTODOの削除 404とIDを返す
+    #[tokio::test]
+    async fn should_return_not_found_body_on_delete() {
+        let id = any_todo_id();
+        let req = build_todo_req_with_empty(&format!("/todos/{}", id), Method::DELETE);
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "todo not found", "id": id })
+        );
+    }
+
+    /// データ一式の書き出しと取り込み 置き換え指定で既存のTODOを消す
+    #[tokio::test]
+    async fn should_export_and_import_dataset() {
+        let repository = TodoRepositoryForMemory::new();
+        let parent = repository
+            .create(DEFAULT_USER, CreateTodo::new("parent".to_string()))
+            .await
+            .expect("failed create todo");
+        let child = serde_json::from_value(serde_json::json!({
+            "text": "child",
+            "parent_id": parent.id(),
+        }))
+        .unwrap();
+        let child = repository
+            .create(DEFAULT_USER, child)
+            .await
+            .expect("failed create todo");
+        let label = Label {
+            id: LabelId::from(1),
+            name: "label".to_string(),
+            color: "#808080".to_string(),
+        };
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository
+            .create(CreateLabel::new(label.name.clone()))
+            .await
+            .expect("failed create label");
+        repository.attach_label(child.id(), label.clone());
+        let app = create_app(repository.clone(), label_repository);
+
+        let parent_id = parent.id();
+        // 書き出しは並び順(同じならID順)
+        let mut todos = vec![parent, child.clone()];
+        todos.sort_by_key(|todo| todo.id());
+
+        let req = build_todo_req_with_empty("/export", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let dataset: Dataset = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            dataset,
+            Dataset {
+                todos,
+                labels: vec![label.clone()],
+                associations: vec![TodoLabel {
+                    todo_id: child.id(),
+                    label_id: label.id,
+                }],
+            }
+        );
+
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        let req = build_todo_req_with_json("/import?mode=replace", Method::POST, body.clone());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let counts: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            counts,
+            serde_json::json!({ "todos": 2, "labels": 1, "associations": 1, "replaced": 2 })
+        );
+        let todos = repository.all_with_labels(DEFAULT_USER).await.unwrap();
+        let mut imported: Vec<_> = todos
+            .iter()
+            .map(|todo| (serde_json::to_value(todo).unwrap(), todo.labels.len()))
+            .collect();
+        imported.sort_by_key(|(value, _)| !value["parent_id"].is_null());
+        // 新しいIDで作り直し、子は新しい親を指す
+        assert_eq!(imported.len(), 2);
+        assert_ne!(imported[0].0["id"], serde_json::json!(parent_id));
+        assert!(imported[0].0["parent_id"].is_null());
+        assert_eq!(imported[0].1, 0);
+        assert_eq!(imported[1].0["parent_id"], imported[0].0["id"]);
+        assert_eq!(imported[1].1, 1);
+
+        // 含まれないラベルへの参照があれば何も取り込まない
+        let body = body.replace(r#""label_id":1"#, r#""label_id":9"#);
+        let req = build_todo_req_with_json("/import", Method::POST, body);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(repository.count(DEFAULT_USER).await.unwrap(), 2);
+    }
+
+    /// 指定があれば作成・取得・更新の結果を { "data": ... } で包む(既定は包まない)
+    #[tokio::test]
+    async fn should_wrap_response_in_envelope() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let req = build_todo_req_with_json(
+            "/todos?envelope=true",
+            Method::POST,
+            r#"{ "text": "wrapped" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["data"]["text"], "wrapped");
+        let id = body["data"]["id"].clone();
+        let path = format!(
+            "/todos/{}",
+            serde_json::from_value::<TodoId>(id.clone()).unwrap()
+        );
+
+        let req = Request::builder()
+            .uri(&path)
+            .header("X-Envelope", "true")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["data"]["id"], id);
+
+        let req =
+            build_todo_req_with_json(&path, Method::PATCH, r#"{ "text": "bare" }"#.to_string());
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["text"], "bare");
+        assert!(body.get("data").is_none());
+    }
+
+    /// ページングの総件数と前後のページのLinkヘッダ
+    #[tokio::test]
+    async fn should_return_pagination_headers() {
+        let repository = TodoRepositoryForMemory::new();
+        for i in 0..5 {
+            repository
+                .create(DEFAULT_USER, CreateTodo::new(format!("todo {}", i)))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_empty("/todos?fields=id&limit=2&offset=2", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-total-count"], "5");
+        assert_eq!(
+            res.headers()[header::LINK],
+            r#"</todos?fields=id&limit=2&offset=4>; rel="next", </todos?fields=id&limit=2&offset=0>; rel="prev""#
+        );
+
+        let req = build_todo_req_with_empty("/todos?limit=2&offset=4", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(
+            res.headers()[header::LINK],
+            r#"</todos?limit=2&offset=2>; rel="prev""#
+        );
+
+        let req = build_todo_req_with_empty("/todos", Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.headers()["x-total-count"], "5");
+        assert!(res.headers().get(header::LINK).is_none());
+    }
+
+    /// タグを付けて作成・更新し、タグで絞り込む
+    #[tokio::test]
+    async fn should_filter_todos_by_tag() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let mut created = Vec::new();
+        for body in [
+            r#"{ "text": "fix bug", "tags": ["urgent", "work"] }"#,
+            r#"{ "text": "buy milk", "tags": ["home"] }"#,
+            r#"{ "text": "no tags" }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::CREATED);
+            created.push(res_to_todo(res).await.id());
+        }
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", created[1]),
+            Method::PATCH,
+            r#"{ "tags": ["home", "urgent"] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = build_todo_req_with_empty("/todos?tag=urgent", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+        let ids: Vec<_> = todos.iter().map(|todo| todo.id()).collect();
+        assert_eq!(ids, vec![created[0], created[1]]);
+
+        for tags in [r#"[""]"#, &format!(r#"["{}"]"#, "a".repeat(33))] {
+            let body = format!(r#"{{ "text": "bad tag", "tags": {} }}"#, tags);
+            let req = build_todo_req_with_json("/todos", Method::POST, body);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    /// 最近更新したTodoを更新日時の新しい順に返す
+    #[tokio::test]
+    async fn should_get_recent_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        let mut created = Vec::new();
+        for text in ["first", "second", "third"] {
+            let todo = repository
+                .create(DEFAULT_USER, CreateTodo::new(text.to_string()))
+                .await
+                .expect("failed create todo");
+            created.push(todo.id());
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        repository
+            .set_completed_many(DEFAULT_USER, &[created[0]], true)
+            .await
+            .expect("failed update todo");
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_empty("/todos/recent?limit=2", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+        let ids: Vec<_> = todos.iter().map(|todo| todo.id()).collect();
+        assert_eq!(ids, vec![created[0], created[2]]);
+
+        let req = build_todo_req_with_empty("/todos/recent?limit=0", Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// 完了にすると完了日時を記録し、完了日時で絞り込める
+    #[tokio::test]
+    async fn should_filter_todos_by_completed_since() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let mut created = Vec::new();
+        for text in ["done", "not yet"] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}" }}"#, text),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            created.push(res_to_todo(res).await.id());
+        }
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", created[0]),
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(body["completed_at"].is_string());
+
+        for (query, expected) in [
+            ("completed_since=2000-01-01T00:00:00Z", vec![created[0]]),
+            ("completed_since=2999-01-01T00:00:00Z", vec![]),
+        ] {
+            let req = build_todo_req_with_empty(&format!("/todos?{}", query), Method::GET);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK, "{}", query);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+            let ids: Vec<_> = todos.iter().map(|todo| todo.id()).collect();
+            assert_eq!(ids, expected, "{}", query);
+        }
+    }
+
+    /// TodoをJSON Linesで1行に1件書き出す
+    #[tokio::test]
+    async fn should_export_todos_as_ndjson() {
+        let repository = TodoRepositoryForMemory::new();
+        // 書き出しはID順
+        let mut expected: Vec<_> = create_todos(&repository, &["first", "second"])
+            .await
+            .iter()
+            .map(|todo| todo.id())
+            .collect();
+        expected.sort();
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_empty("/todos/export.ndjson", Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/x-ndjson");
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        let todos: Vec<Todo> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let ids: Vec<_> = todos.iter().map(|todo| todo.id()).collect();
+        assert_eq!(ids, expected);
+        assert!(body.ends_with('\n'));
+    }
+
+    /// Todoの本文に追記する(最大文字数を超えるなら422)
+    #[tokio::test]
+    async fn should_append_todo_text() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new("note".to_string()))
+            .await
+            .expect("failed create todo");
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let path = format!("/todos/{}/append", created.id());
+
+        let req = build_todo_req_with_json(
+            &path,
+            Method::POST,
+            r#"{ "text": " - follow up" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["text"], "note - follow up");
+
+        let body = serde_json::json!({ "text": "a".repeat(max_text_len()) }).to_string();
+        let req = build_todo_req_with_json(&path, Method::POST, body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["errors"][0]["field"], "text");
+
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}/append", any_todo_id()),
+            Method::POST,
+            r#"{ "text": "!" }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// どのTODOにも付いていないラベル 全て使われていれば空
+    #[tokio::test]
+    async fn should_get_unused_labels() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new("todo".to_string()))
+            .await
+            .expect("failed create todo");
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["used", "unused"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        label_repository
+            .add_label(created.id(), LabelId::from(1))
+            .await
+            .expect("failed add label");
+
+        let app = create_app(repository, label_repository.clone());
+        let req = build_todo_req_with_empty("/labels/unused", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        let names: Vec<_> = labels.iter().map(|label| label.name.as_str()).collect();
+        assert_eq!(names, vec!["unused"]);
+
+        label_repository
+            .add_label(created.id(), LabelId::from(2))
+            .await
+            .expect("failed add label");
+        let req = build_todo_req_with_empty("/labels/unused", Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        assert!(labels.is_empty());
+    }
+
+    /// 本文の一括置換 最大文字数を超えるTODOは変更せずに返す
+    #[tokio::test]
+    async fn should_replace_todo_text() {
+        let repository = TodoRepositoryForMemory::new();
+        let long = "foo".to_string() + &"a".repeat(max_text_len() - 3);
+        let created = create_todos(&repository, &["foo and foo", "bar", long.as_str()]).await;
+
+        let req = build_todo_req_with_json(
+            "/todos/replace-text",
+            Method::POST,
+            r#"{ "find": "foo", "replace": "bazz" }"#.to_string(),
+        );
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, json!({ "updated": 1, "skipped": [created[2].id()] }));
+
+        let req = build_todo_req_with_empty(&format!("/todos/{}", created[0].id()), Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["text"], "bazz and bazz");
+        let req = build_todo_req_with_empty(&format!("/todos/{}", created[2].id()), Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["text"], long);
+
+        let req = build_todo_req_with_json(
+            "/todos/replace-text",
+            Method::POST,
+            r#"{ "find": "", "replace": "x" }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    /// 期限はオフセット付きならUTCに直し、日付だけなら0時(APP_TZ 未設定ならUTC)として保存する
+    #[tokio::test]
+    async fn should_normalize_due_date() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let mut created = Vec::new();
+        for (due_date, expected) in [
+            ("2025-01-31T09:00:00+09:00", "2025-01-31T00:00:00Z"),
+            ("2025-01-31", "2025-01-31T00:00:00Z"),
+        ] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "todo", "due_date": "{}" }}"#, due_date),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todo: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(todo["due_date"], expected);
+            created.push(serde_json::from_value::<TodoId>(todo["id"].clone()).unwrap());
+        }
+        let path = format!("/todos/{}", created[0]);
+
+        let req = build_todo_req_with_json(
+            &path,
+            Method::PATCH,
+            r#"{ "due_date": "2025-02-01T12:00:00-03:00" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todo: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(todo["due_date"], "2025-02-01T15:00:00Z");
+
+        for (uri, method) in [("/todos", Method::POST), (path.as_str(), Method::PATCH)] {
+            let req = build_todo_req_with_json(
+                uri,
+                method,
+                r#"{ "text": "todo", "due_date": "next friday" }"#.to_string(),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert!(String::from_utf8_lossy(&bytes).contains("due_date"));
+        }
+    }
+
+    /// ラベルの付与を共有すれば付け外しが絞り込みにも反映される
+    #[tokio::test]
+    async fn should_attach_and_detach_todo_label() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = create_todos(&repository, &["todo 1", "todo 2"]).await;
+        let path = format!("/todos/{}/labels/1", created[1].id());
+        let label_repository = LabelRepositoryForMemory::with_todos(&repository);
+        label_repository
+            .create(CreateLabel::new("work".to_string()))
+            .await
+            .expect("failed create label");
+        let app = create_app(repository, label_repository);
+
+        let req = build_todo_req_with_empty(&path, Method::POST);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let req = build_todo_req_with_empty("/todos?label_id=1", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+        let ids: Vec<_> = todos.iter().map(|todo| todo.id()).collect();
+        assert_eq!(ids, vec![created[1].id()]);
+
+        let req = build_todo_req_with_empty(&path, Method::DELETE);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let req = build_todo_req_with_empty(&path, Method::DELETE);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let req = build_todo_req_with_empty("/todos?label_id=1", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+        assert!(todos.is_empty());
+        let req = build_todo_req_with_empty("/labels/unused", Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(labels.len(), 1);
+    }
+
+    /// liveness はDBを見ずに200、readiness はヘルスチェックが失敗すれば503
+    #[tokio::test]
+    async fn should_separate_liveness_and_readiness() {
+        let repository = MockTodoRepository::new();
+        repository.push_result(
+            "health",
+            Ok(Health {
+                ok: false,
+                migrated: Some(false),
+                latency_ms: None,
+                idle_connections: None,
+                active_connections: None,
+                todos: None,
+            }),
+        );
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_empty("/livez", Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = build_todo_req_with_empty("/readyz", Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["migrated"], false);
+    }
+
+    /// 変更履歴は削除後も古い順に返し、存在したことのないTODOは404
+    #[tokio::test]
+    async fn should_get_todo_history() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let req =
+            build_todo_req_with_json("/todos", Method::POST, r#"{ "text": "draft" }"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let path = format!("/todos/{}", res_to_todo(res).await.id());
+        let req = build_todo_req_with_json(
+            &path,
+            Method::PATCH,
+            r#"{ "text": "final", "completed": true }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let req = build_todo_req_with_empty(&path, Method::DELETE);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        let req = build_todo_req_with_empty(&format!("{}/history", path), Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&bytes).unwrap();
+        let changes: Vec<_> = body
+            .iter()
+            .map(|change| {
+                (
+                    change["change_type"].clone(),
+                    change["old_text"].clone(),
+                    change["new_text"].clone(),
+                    change["completed"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (json!("created"), json!(null), json!("draft"), json!(false)),
+                (
+                    json!("updated"),
+                    json!("draft"),
+                    json!("final"),
+                    json!(true)
+                ),
+                (json!("deleted"), json!("final"), json!(null), json!(true)),
+            ]
+        );
+
+        let req =
+            build_todo_req_with_empty(&format!("/todos/{}/history", any_todo_id()), Method::GET);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// 削除の取り消し 取り消せるTODOが無ければ404、DBが混み合っていれば503
+    #[tokio::test]
+    async fn should_distinguish_restore_errors() {
+        let id = any_todo_id();
+        let repository = MockTodoRepository::new();
+        repository.push_result::<Todo>("restore", Err(RepositoryError::NotFound(id.into()).into()));
+        repository.push_result::<Todo>("restore", Err(RepositoryError::PoolTimedOut.into()));
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_empty(&format!("/todos/{}/restore", id), Method::POST);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let req = build_todo_req_with_empty(&format!("/todos/{}/restore", id), Method::POST);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    /// 移動 見つからなければ404、DBが混み合っていれば503
+    #[tokio::test]
+    async fn should_distinguish_move_errors() {
+        let id = any_todo_id();
+        let repository = MockTodoRepository::new();
+        repository.push_result::<Todo>("move_to", Err(RepositoryError::NotFound(id.into()).into()));
+        repository.push_result::<Todo>("move_to", Err(RepositoryError::PoolTimedOut.into()));
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        for status in [StatusCode::NOT_FOUND, StatusCode::SERVICE_UNAVAILABLE] {
+            let req = build_todo_req_with_json(
+                &format!("/todos/{}/move", id),
+                Method::PATCH,
+                r#"{ "position": 0 }"#.to_string(),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), status);
+        }
+    }
+
+    /// 削除 見つからない場合だけ404の本文を返し、DBの失敗はそれに応じたステータスにする
+    #[tokio::test]
+    async fn should_distinguish_delete_errors() {
+        let todo = Todo::new(
+            any_todo_id(),
+            "should_distinguish_delete_errors".to_string(),
+        );
+        let path = format!("/todos/{}", todo.id());
+        let repository = MockTodoRepository::new();
+        for _ in 0..3 {
+            repository.push_result("find_opt", Ok(Some(todo.clone())));
+        }
+        repository.push_result::<Vec<Todo>>("children", Err(RepositoryError::PoolTimedOut.into()));
+        repository.push_result::<()>(
+            "delete",
+            Err(RepositoryError::NotFound(todo.id().into()).into()),
+        );
+        repository.push_result::<()>("delete", Err(RepositoryError::PoolTimedOut.into()));
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_empty(&path, Method::DELETE);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let req = build_todo_req_with_empty(&format!("{}?cascade=true", path), Method::DELETE);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "todo not found", "id": todo.id() })
+        );
+
+        let req = build_todo_req_with_empty(&format!("{}?cascade=true", path), Method::DELETE);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    /// ラベルの付け外し TODOが見つからなければIDを付けて404、DBの失敗はそれに応じたステータスにする
+    #[tokio::test]
+    async fn should_distinguish_todo_errors_on_labels() {
+        let id = any_todo_id();
+        let path = format!("/todos/{}/labels/1", id);
+        let repository = MockTodoRepository::new();
+        for _ in 0..2 {
+            repository
+                .push_result::<Todo>("find", Err(RepositoryError::NotFound(id.into()).into()));
+            repository.push_result::<Todo>("find", Err(RepositoryError::PoolTimedOut.into()));
+        }
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        for method in [Method::POST, Method::DELETE] {
+            let req = build_todo_req_with_empty(&path, method.clone());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(
+                body,
+                serde_json::json!({ "error": "todo not found", "id": id })
+            );
+
+            let req = build_todo_req_with_empty(&path, method);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+
+    /// インポート 件数の上限を超えるなら403、DBの失敗は本文にエラー内容を出さない
+    #[tokio::test]
+    async fn should_map_import_errors() {
+        let repository = TodoRepositoryForMemory::new().with_quota(1);
+        let req = build_todo_req_with_json(
+            "/todos/import",
+            Method::POST,
+            r#"[{ "text": "import 1" }, { "text": "import 2" }]"#.to_string(),
+        );
+        let res = create_app(repository.clone(), LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(repository.count(DEFAULT_USER).await.unwrap(), 0);
+
+        let repository = MockTodoRepository::new();
+        repository.push_result::<Vec<Todo>>(
+            "create_many",
+            Err(RepositoryError::Unexpected("password=secret".to_string()).into()),
+        );
+        let req = build_todo_req_with_json(
+            "/todos/import",
+            Method::POST,
+            r#"[{ "text": "import 1" }]"#.to_string(),
+        );
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(!String::from_utf8_lossy(&bytes).contains("secret"));
+    }
+
+    /// ヘルスチェック自体が失敗しても、ok: false の503を返す
+    #[tokio::test]
+    async fn should_return_unavailable_when_health_check_fails() {
+        let repository = MockTodoRepository::new();
+        repository.push_result::<Health>("health", Err(RepositoryError::PoolTimedOut.into()));
+        let req = build_todo_req_with_empty("/readyz", Method::GET);
+        let res = create_app(repository, LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, serde_json::json!({ "ok": false }));
+    }
+
+    /// 一括完了でも繰り返しTODOの次の回が作成される
+    #[tokio::test]
+    async fn should_create_next_occurrence_on_bulk_complete() {
+        let repository = TodoRepositoryForMemory::new();
+        let app = create_app(repository.clone(), LabelRepositoryForMemory::new());
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "water plants", "due_date": "2025-07-01T09:00:00Z", "recurrence": "weekly" }"#
+                .to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let created = res_to_todo(res).await;
+
+        let req = build_todo_req_with_json(
+            "/todos/bulk-complete",
+            Method::POST,
+            serde_json::json!({ "ids": [created.id()], "completed": true }).to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // 次の回はバックグラウンドで作成される
+        let mut next = None;
+        for _ in 0..100 {
+            let todos = repository.all(DEFAULT_USER).await.unwrap();
+            next = todos.into_iter().find(|todo| todo.id() != created.id());
+            if next.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let next = serde_json::to_value(next.expect("next occurrence is not created")).unwrap();
+        assert_eq!(next["text"], "water plants");
+        assert_eq!(next["completed"], false);
+        assert_eq!(next["due_date"], "2025-07-08T09:00:00Z");
+        assert_eq!(next["recurrence"], "weekly");
+    }
+
+    /// ブラウザからのプリフライトで、アプリが使うリクエストヘッダを許可する
+    #[tokio::test]
+    async fn should_allow_app_headers_in_preflight() {
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::OPTIONS)
+            .header("origin", "http://localhost:3001")
+            .header("access-control-request-method", "POST")
+            .header(
+                "access-control-request-headers",
+                format!("{}, {}", IDEMPOTENCY_KEY, USER_ID_HEADER),
+            )
+            .body(Body::empty())
+            .unwrap();
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let allowed = res.headers()["access-control-allow-headers"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        for header in [IDEMPOTENCY_KEY, USER_ID_HEADER] {
+            assert!(allowed.contains(header), "allowed: {}", allowed);
+        }
+    }
+
+    /// 取り込むラベルも POST /labels と同じ検証をし、不正なら何も取り込まない
+    #[tokio::test]
+    async fn should_reject_invalid_labels_on_import() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let app = create_app(TodoRepositoryForMemory::new(), label_repository.clone());
+        let long_name = "a".repeat(200);
+        for labels in [
+            serde_json::json!([{ "id": 1, "name": long_name, "color": "#808080" }]),
+            serde_json::json!([{ "id": 1, "name": "work", "color": "red" }]),
+            serde_json::json!([{ "id": 1, "name": "wo\trk", "color": "#808080" }]),
+            serde_json::json!([
+                { "id": 1, "name": "Work", "color": "#808080" },
+                { "id": 2, "name": " work ", "color": "#808080" },
+            ]),
+        ] {
+            let body = serde_json::json!({ "todos": [], "labels": labels, "associations": [] });
+            let req = build_todo_req_with_json("/import", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+        }
+        assert!(label_repository.all().await.unwrap().is_empty());
+    }
+
+    /// ラベル削除 見つからなければ404、DBに繋がらなければ404にせず503
+    #[cfg(not(feature = "uuid-id"))]
+    #[tokio::test]
+    async fn should_distinguish_delete_label_errors() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let req = build_todo_req_with_empty("/labels/9", Method::DELETE);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:")
+            .await
+            .expect("fail connect sqlite");
+        pool.close().await;
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForSqlite::new(pool),
+        );
+        let req = build_todo_req_with_empty("/labels/9", Method::DELETE);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    /// uuid-id機能ではUUIDのパスで取得でき、数値などUUIDでないIDは400
+    #[cfg(feature = "uuid-id")]
+    #[tokio::test]
+    async fn should_accept_only_uuid_paths() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new("uuid".to_string()))
+            .await
+            .expect("failed create todo");
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+
+        let req = build_todo_req_with_empty(&format!("/todos/{}", created.id()), Method::GET);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res_to_todo(res).await, created);
+
+        for path in ["/todos/1", "/todos/1/children", "/todos/not-a-uuid"] {
+            let req = build_todo_req_with_empty(path, Method::GET);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "path: {}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["error"], "invalid id", "path: {}", path);
+        }
+    }
+
+    /// 一括更新・インポートも Content-Type が違えば本文がJSONの415
+    #[tokio::test]
+    async fn should_reject_unsupported_content_type_as_json() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        for (path, method, expected) in [
+            ("/todos/batch", Method::PATCH, "expected application/json"),
+            (
+                "/todos/import",
+                Method::POST,
+                "expected application/json or text/csv",
+            ),
+        ] {
+            let req = Request::builder()
+                .uri(path)
+                .method(method)
+                .header(CONTENT_TYPE, "text/plain")
+                .body(Body::from("[]"))
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(
+                res.status(),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "path: {}",
+                path
+            );
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(
+                body,
+                serde_json::json!({ "error": expected }),
+                "path: {}",
+                path
+            );
+        }
+    }
+
+    /// 本文の一括置換でも置換したTODOごとに更新イベントを流す
+    #[tokio::test]
+    async fn should_stream_replaced_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = create_todos(&repository, &["foo", "bar"]).await;
+        let app = create_app(repository, LabelRepositoryForMemory::new());
+        let req = build_todo_req_with_empty("/todos/events", Method::GET);
+        let mut body = app.clone().oneshot(req).await.unwrap().into_body();
+
+        let req = build_todo_req_with_json(
+            "/todos/replace-text",
+            Method::POST,
+            r#"{ "find": "foo", "replace": "bazz" }"#.to_string(),
+        );
+        app.oneshot(req).await.unwrap();
+        let chunk = body.data().await.unwrap().unwrap();
+        let event = String::from_utf8(chunk.to_vec()).unwrap();
+        assert!(event.contains(r#""type":"updated""#), "{}", event);
+        assert!(event.contains(r#""changed":["text"]"#), "{}", event);
+        assert!(event.contains(r#""text":"bazz""#), "{}", event);
+        assert!(
+            event.contains(&format!(r#""id":{}"#, json!(created[0].id()))),
+            "{}",
+            event
+        );
     }
 }
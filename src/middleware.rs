@@ -0,0 +1,8 @@
+pub mod access_log;
+pub mod auth;
+pub mod body_limit;
+pub mod metrics;
+pub mod rate_limit;
+pub mod read_only;
+pub mod request_id;
+pub mod timeout;
@@ -0,0 +1,87 @@
+use axum::http::Response;
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tower_http::trace::OnResponse;
+use tracing::Span;
+
+/// アクセスログ(TraceLayer の on_response で出す)
+/// 4xx・5xx はすべて出し、それ以外は指定した割合だけ出す
+#[derive(Debug, Clone)]
+pub struct AccessLog {
+    /// 2xx などを出す割合(0.0〜1.0)
+    sample_rate: f64,
+    /// 割合の判定に使うレスポンスの通し番号(クローン間で共有する)
+    counter: Arc<AtomicU64>,
+}
+
+impl AccessLog {
+    /// new(割合は0.0〜1.0に丸める)
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            sample_rate: sample_rate.clamp(0.0, 1.0),
+            counter: Arc::default(),
+        }
+    }
+
+    /// 環境変数 ACCESS_LOG_SAMPLE(既定1.0、すべて出す)から読む
+    pub fn from_env() -> Self {
+        let sample_rate = env::var("ACCESS_LOG_SAMPLE")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|rate: &f64| rate.is_finite())
+            .unwrap_or(1.0);
+        Self::new(sample_rate)
+    }
+
+    /// このレスポンスをログに出すか
+    /// 乱数は使わず、通し番号に割合を掛けた値の整数部が増えたときに出す(0.25なら4件に1件)
+    fn should_log(&self, status: u16) -> bool {
+        if status >= 400 {
+            return true;
+        }
+        let count = self.counter.fetch_add(1, Ordering::Relaxed) as f64;
+        ((count + 1.0) * self.sample_rate).floor() > (count * self.sample_rate).floor()
+    }
+}
+
+impl<B> OnResponse<B> for AccessLog {
+    fn on_response(self, response: &Response<B>, latency: Duration, _span: &Span) {
+        let status = response.status().as_u16();
+        if !self.should_log(status) {
+            return;
+        }
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        match status {
+            500.. => tracing::error!(status, latency_ms, "response"),
+            400.. => tracing::warn!(status, latency_ms, "response"),
+            _ => tracing::info!(status, latency_ms, "response"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 4xx・5xx は割合によらず出し、2xx は割合だけ出す
+    #[test]
+    fn should_sample_successful_responses() {
+        let count = |access_log: &AccessLog, status| {
+            (0..100).filter(|_| access_log.should_log(status)).count()
+        };
+        let access_log = AccessLog::new(0.25);
+        assert_eq!(count(&access_log, 200), 25);
+        assert_eq!(count(&access_log, 404), 100);
+        assert_eq!(count(&access_log, 500), 100);
+
+        assert_eq!(count(&AccessLog::new(0.0), 200), 0);
+        assert_eq!(count(&AccessLog::new(1.0), 200), 100);
+        assert_eq!(count(&AccessLog::new(5.0), 204), 100);
+    }
+}
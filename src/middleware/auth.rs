@@ -0,0 +1,195 @@
+use axum::{
+    body::Body,
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        HeaderMap, HeaderValue, Request, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::{env, sync::Arc};
+use thiserror::Error;
+use tower::{filter::Predicate, BoxError};
+
+use crate::repositories::todo::DEFAULT_USER;
+
+/// 認証の対象外パス
+const EXEMPT_PATHS: [&str; 4] = ["/", "/health", "/livez", "/readyz"];
+
+/// ユーザーIDのヘッダ名
+pub const USER_ID_HEADER: &str = "x-user-id";
+
+/// 認証失敗
+#[derive(Debug, Error)]
+#[error("Unauthorized")]
+pub struct Unauthorized;
+
+/// 不正なユーザーID
+#[derive(Debug, Error)]
+#[error("Invalid user id")]
+pub struct InvalidUserId;
+
+/// 認証済みのユーザー(X-User-Idヘッダ、未指定なら既定ユーザー)
+/// 認証レイヤーがリクエストの Extension に入れる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrentUser(pub String);
+
+/// Authorization: Bearer <token> を検証する認証
+#[derive(Debug, Clone)]
+pub struct BearerAuth {
+    /// None の場合は認証しない
+    token: Option<Arc<str>>,
+}
+
+impl BearerAuth {
+    /// new
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            token: token.map(Arc::from),
+        }
+    }
+
+    /// 環境変数 API_TOKEN から生成(未設定なら認証を無効にする)
+    pub fn from_env() -> Self {
+        let token = env::var("API_TOKEN").ok().filter(|token| !token.is_empty());
+        if token.is_none() {
+            tracing::warn!("[API_TOKEN] is not set, authentication is disabled");
+        }
+        Self::new(token)
+    }
+}
+
+/// tower::filter から呼ばれる判定
+impl Predicate<Request<Body>> for BearerAuth {
+    type Request = Request<Body>;
+
+    fn check(&mut self, mut req: Request<Body>) -> Result<Self::Request, BoxError> {
+        if EXEMPT_PATHS.contains(&req.uri().path()) {
+            return Ok(req);
+        }
+        if let Some(token) = &self.token {
+            let presented = req
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "));
+            match presented {
+                Some(presented) if constant_time_eq(presented.as_bytes(), token.as_bytes()) => {}
+                _ => return Err(Unauthorized.into()),
+            }
+        }
+        let user = current_user(&req)?;
+        req.extensions_mut().insert(user);
+        Ok(req)
+    }
+}
+
+/// X-User-Idヘッダからユーザーを決める(1〜64文字、制御文字不可)
+fn current_user<B>(req: &Request<B>) -> Result<CurrentUser, InvalidUserId> {
+    let user_id = match req.headers().get(USER_ID_HEADER) {
+        Some(value) => value.to_str().map_err(|_| InvalidUserId)?.trim(),
+        None => DEFAULT_USER,
+    };
+    if user_id.is_empty() || user_id.chars().count() > 64 || user_id.chars().any(char::is_control) {
+        return Err(InvalidUserId);
+    }
+    Ok(CurrentUser(user_id.to_string()))
+}
+
+/// 比較時間からトークンを推測されないよう、全バイトを比較する
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 認証エラーを401レスポンスに変換する
+pub async fn handle_error(error: BoxError) -> Response {
+    if error.is::<Unauthorized>() {
+        let mut headers = HeaderMap::new();
+        headers.insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        let body = Json(json!({ "error": "unauthorized" }));
+        return (StatusCode::UNAUTHORIZED, headers, body).into_response();
+    }
+    if error.is::<InvalidUserId>() {
+        let body = Json(json!({ "error": "invalid user id" }));
+        return (StatusCode::BAD_REQUEST, body).into_response();
+    }
+    (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_req(path: &str, authorization: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().uri(path);
+        if let Some(authorization) = authorization {
+            builder = builder.header(AUTHORIZATION, authorization);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    /// トークンが一致しなければ拒否し、対象外パスは通す
+    #[tokio::test]
+    async fn should_require_bearer_token() {
+        let mut auth = BearerAuth::new(Some("secret".to_string()));
+        assert!(auth
+            .check(build_req("/todos", Some("Bearer secret")))
+            .is_ok());
+        assert!(auth
+            .check(build_req("/labels", Some("Bearer wrong")))
+            .is_err());
+        assert!(auth.check(build_req("/todos", Some("secret"))).is_err());
+        let error = auth.check(build_req("/todos", None)).unwrap_err();
+        // 対象外パス
+        assert!(auth.check(build_req("/", None)).is_ok());
+        assert!(auth.check(build_req("/health", None)).is_ok());
+
+        let res = handle_error(error).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers()[WWW_AUTHENTICATE], "Bearer");
+    }
+
+    /// トークンを設定していても、死活監視のプローブはトークンなしで通す
+    #[test]
+    fn should_exempt_probes_with_token() {
+        let mut auth = BearerAuth::new(Some("secret".to_string()));
+        assert!(auth.check(build_req("/livez", None)).is_ok());
+        assert!(auth.check(build_req("/readyz", None)).is_ok());
+        assert!(auth.check(build_req("/todos", None)).is_err());
+    }
+
+    /// トークン未設定なら認証しない
+    #[test]
+    fn should_skip_when_token_unset() {
+        let mut auth = BearerAuth::new(None);
+        assert!(auth.check(build_req("/todos", None)).is_ok());
+    }
+
+    /// X-User-Idヘッダのユーザーをリクエストに付ける(未指定なら既定ユーザー)
+    #[tokio::test]
+    async fn should_attach_current_user() {
+        let mut auth = BearerAuth::new(None);
+        let req = auth.check(build_req("/todos", None)).unwrap();
+        assert_eq!(
+            req.extensions().get::<CurrentUser>(),
+            Some(&CurrentUser(DEFAULT_USER.to_string()))
+        );
+
+        let mut req = build_req("/todos", None);
+        req.headers_mut()
+            .insert(USER_ID_HEADER, HeaderValue::from_static("alice"));
+        let req = auth.check(req).unwrap();
+        assert_eq!(
+            req.extensions().get::<CurrentUser>(),
+            Some(&CurrentUser("alice".to_string()))
+        );
+
+        let mut req = build_req("/todos", None);
+        req.headers_mut()
+            .insert(USER_ID_HEADER, HeaderValue::from_static(""));
+        let error = auth.check(req).unwrap_err();
+        let res = handle_error(error).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,110 @@
+use axum::{
+    body::Body,
+    http::{header::CONTENT_LENGTH, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use http_body::Body as _;
+use serde_json::json;
+use std::env;
+use thiserror::Error;
+use tokio_stream::StreamExt;
+use tower::{filter::Predicate, BoxError};
+
+/// リクエスト本文の上限超過
+#[derive(Debug, Error)]
+#[error("Payload too large, limit is {0} bytes")]
+pub struct PayloadTooLarge(u64);
+
+/// リクエスト本文の大きさを制限する
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimit {
+    limit: u64,
+}
+
+impl BodyLimit {
+    /// new
+    pub fn new(limit: u64) -> Self {
+        Self { limit }
+    }
+
+    /// 環境変数 BODY_LIMIT_BYTES(既定64KB)から生成
+    pub fn from_env() -> Self {
+        let limit = env::var("BODY_LIMIT_BYTES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(64 * 1024);
+        Self::new(limit)
+    }
+}
+
+/// tower::filter から呼ばれる判定
+/// Content-Length・本文の大きさが分かれば先に拒否し、分からない場合は読みながら数える
+impl Predicate<Request<Body>> for BodyLimit {
+    type Request = Request<Body>;
+
+    fn check(&mut self, req: Request<Body>) -> Result<Self::Request, BoxError> {
+        let limit = self.limit;
+        let content_length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        let size = content_length.unwrap_or_else(|| req.body().size_hint().lower());
+        if size > limit {
+            return Err(PayloadTooLarge(limit).into());
+        }
+
+        let (parts, body) = req.into_parts();
+        let mut read = 0;
+        let body = body.map(move |chunk| {
+            let chunk = chunk?;
+            read += chunk.len() as u64;
+            if read > limit {
+                return Err(BoxError::from(PayloadTooLarge(limit)));
+            }
+            Ok(chunk)
+        });
+        Ok(Request::from_parts(parts, Body::wrap_stream(body)))
+    }
+}
+
+/// 本文の上限超過を413レスポンスに変換する
+pub async fn handle_error(error: BoxError) -> Response {
+    match error.downcast_ref::<PayloadTooLarge>() {
+        Some(PayloadTooLarge(limit)) => {
+            let body = Json(json!({ "error": "payload too large", "limit": limit }));
+            (StatusCode::PAYLOAD_TOO_LARGE, body).into_response()
+        }
+        None => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 上限を超える本文は拒否する
+    #[tokio::test]
+    async fn should_reject_large_body() {
+        let mut limit = BodyLimit::new(4);
+        let req = Request::builder().body(Body::from("1234")).unwrap();
+        assert!(limit.check(req).is_ok());
+        let req = Request::builder().body(Body::from("12345")).unwrap();
+        let error = limit.check(req).unwrap_err();
+
+        let res = handle_error(error).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    /// 大きさが分からない本文は読みながら数える
+    #[tokio::test]
+    async fn should_limit_streaming_body() {
+        let mut limit = BodyLimit::new(4);
+        let chunks: Vec<Result<_, BoxError>> = vec![Ok("123"), Ok("45")];
+        let body = Body::wrap_stream(tokio_stream::iter(chunks));
+        let req = Request::builder().body(body).unwrap();
+        let req = limit.check(req).unwrap();
+        assert!(hyper::body::to_bytes(req.into_body()).await.is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use axum::{
+    extract::{Extension, MatchedPath},
+    http::{Request, Response},
+    routing::get,
+    Router,
+};
+use metrics::{gauge, histogram, increment_counter};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::{Layer, Service};
+
+use crate::repositories::todo::TodoRepository;
+
+/// レイテンシのヒストグラムのバケット(秒)
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Prometheusのレコーダーをグローバルに登録する
+pub fn install_recorder() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .set_buckets(&LATENCY_BUCKETS)
+        .expect("invalid latency buckets")
+        .install_recorder()
+        .expect("fail install metrics recorder")
+}
+
+/// GET /metrics のみのルーター(認証・レート制限の対象外)
+pub fn metrics_router(handle: PrometheusHandle) -> Router {
+    Router::new()
+        .route("/metrics", get(render_metrics))
+        .layer(Extension(handle))
+}
+
+/// Prometheus形式でメトリクスを出力
+async fn render_metrics(Extension(handle): Extension<PrometheusHandle>) -> String {
+    handle.render()
+}
+
+/// TODO件数のゲージを定期的に更新する
+pub fn spawn_todo_gauge<T: TodoRepository>(repository: T, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match repository.stats().await {
+                Ok(stats) => gauge!("todos_total", stats.total as f64),
+                Err(e) => tracing::warn!("fail refresh todo metrics: {}", e),
+            }
+        }
+    });
+}
+
+/// ルート・ステータスごとのリクエスト数とレイテンシを記録するレイヤー
+#[derive(Debug, Clone, Default)]
+pub struct MetricsLayer;
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = RequestMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestMetrics { inner }
+    }
+}
+
+/// MetricsLayer のサービス
+#[derive(Debug, Clone)]
+pub struct RequestMetrics<S> {
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for RequestMetrics<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // パスパラメータごとに系列が増えないよう、マッチしたルートで集計する
+        let path = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_string())
+            .unwrap_or_else(|| "unmatched".to_string());
+        let method = req.method().to_string();
+        let start = Instant::now();
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let res = future.await?;
+            let status = res.status().as_u16().to_string();
+            let latency = start.elapsed().as_secs_f64();
+            increment_counter!(
+                "http_requests_total",
+                "method" => method.clone(),
+                "path" => path.clone(),
+                "status" => status.clone()
+            );
+            histogram!(
+                "http_request_duration_seconds",
+                latency,
+                "method" => method,
+                "path" => path,
+                "status" => status
+            );
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,191 @@
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header::RETRY_AFTER, HeaderMap, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tower::{filter::Predicate, BoxError};
+
+/// レート制限の対象外パス
+const EXEMPT_PATHS: [&str; 4] = ["/", "/health", "/livez", "/readyz"];
+
+/// レート制限超過
+#[derive(Debug, Error)]
+#[error("Too many requests, retry after {0:?}")]
+pub struct RateLimited(Duration);
+
+/// クライアントIPごとの固定ウィンドウ方式レートリミッター
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    /// 数えるクライアント数の上限(x-forwarded-for を変えながら送られてもメモリを使い切らないため)
+    max_clients: usize,
+    clients: Arc<Mutex<HashMap<IpAddr, (Instant, u32)>>>,
+}
+
+impl RateLimiter {
+    /// new
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            max_clients: 10_000,
+            clients: Arc::default(),
+        }
+    }
+
+    /// 環境変数 RATE_LIMIT_REQUESTS(既定100)・RATE_LIMIT_WINDOW_SECS(既定60)から生成
+    /// RATE_LIMIT_MAX_CLIENTS(既定10000)で数えるクライアント数の上限を指定する
+    pub fn from_env() -> Self {
+        let limit = env::var("RATE_LIMIT_REQUESTS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(100);
+        let window = env::var("RATE_LIMIT_WINDOW_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(60);
+        let max_clients = env::var("RATE_LIMIT_MAX_CLIENTS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|max_clients| *max_clients > 0)
+            .unwrap_or(10_000);
+        Self {
+            max_clients,
+            ..Self::new(limit, Duration::from_secs(window))
+        }
+    }
+
+    /// 1リクエスト分数える(超過時は次のウィンドウまでの待ち時間を返す)
+    fn hit(&self, ip: IpAddr) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if !clients.contains_key(&ip) && clients.len() >= self.max_clients {
+            // 期限切れのクライアントを掃除し、それでも上限ならウィンドウの開始が最も古いクライアントを消す
+            let window = self.window;
+            clients.retain(|_, (start, _)| now.duration_since(*start) < window);
+            if clients.len() >= self.max_clients {
+                let oldest = clients
+                    .iter()
+                    .min_by_key(|(_, (start, _))| *start)
+                    .map(|(ip, _)| *ip);
+                if let Some(oldest) = oldest {
+                    clients.remove(&oldest);
+                }
+            }
+        }
+        let (start, count) = clients.entry(ip).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return Err(RateLimited(self.window - now.duration_since(*start)));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// tower::filter から呼ばれる判定
+impl Predicate<Request<Body>> for RateLimiter {
+    type Request = Request<Body>;
+
+    fn check(&mut self, req: Request<Body>) -> Result<Self::Request, BoxError> {
+        if EXEMPT_PATHS.contains(&req.uri().path()) {
+            return Ok(req);
+        }
+        if let Some(ip) = client_ip(&req) {
+            self.hit(ip)?;
+        }
+        Ok(req)
+    }
+}
+
+/// クライアントIPを取得する(x-forwarded-for を優先し、なければ接続元アドレス)
+fn client_ip<B>(req: &Request<B>) -> Option<IpAddr> {
+    req.headers()
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .and_then(|value| value.trim().parse().ok())
+        .or_else(|| {
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+}
+
+/// レート制限エラーを429レスポンスに変換する
+pub async fn handle_error(error: BoxError) -> Response {
+    match error.downcast_ref::<RateLimited>() {
+        Some(RateLimited(retry_after)) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, retry_after.as_secs().max(1).into());
+            let body = Json(json!({ "error": "too many requests" }));
+            (StatusCode::TOO_MANY_REQUESTS, headers, body).into_response()
+        }
+        None => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_req(path: &str, ip: &str) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .header("x-forwarded-for", ip)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    /// 上限を超えたら拒否し、別のIPは影響を受けない
+    #[tokio::test]
+    async fn should_limit_per_client_ip() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert!(limiter.check(build_req("/todos", "10.0.0.1")).is_ok());
+        assert!(limiter.check(build_req("/todos", "10.0.0.1")).is_ok());
+        let error = limiter.check(build_req("/todos", "10.0.0.1")).unwrap_err();
+        assert!(limiter.check(build_req("/todos", "10.0.0.2")).is_ok());
+        // 対象外パス
+        assert!(limiter.check(build_req("/", "10.0.0.1")).is_ok());
+        assert!(limiter.check(build_req("/livez", "10.0.0.1")).is_ok());
+        assert!(limiter.check(build_req("/readyz", "10.0.0.1")).is_ok());
+
+        let res = handle_error(error).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().contains_key(RETRY_AFTER));
+    }
+
+    /// 数えるクライアント数は上限を超えず、最も古いクライアントから消す
+    #[tokio::test]
+    async fn should_cap_tracked_clients() {
+        let mut limiter = RateLimiter {
+            max_clients: 2,
+            ..RateLimiter::new(1, Duration::from_secs(60))
+        };
+        for ip in ["10.0.0.1", "10.0.0.2", "10.0.0.3"] {
+            assert!(limiter.check(build_req("/todos", ip)).is_ok());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(limiter.clients.lock().unwrap().len(), 2);
+        // 残っているクライアントは数え続ける
+        assert!(limiter.check(build_req("/todos", "10.0.0.3")).is_err());
+        // 消されたクライアントは数え直しになる
+        assert!(limiter.check(build_req("/todos", "10.0.0.1")).is_ok());
+        assert_eq!(limiter.clients.lock().unwrap().len(), 2);
+    }
+}
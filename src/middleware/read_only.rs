@@ -0,0 +1,84 @@
+use axum::{
+    http::{Method, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::env;
+use thiserror::Error;
+use tower::{filter::Predicate, BoxError};
+
+/// 読み取り専用モード中の書き込み
+#[derive(Debug, Error)]
+#[error("Service in read-only mode")]
+pub struct ReadOnlyMode;
+
+/// 読み取り専用モード(メンテナンス中に書き込みを止める)
+#[derive(Debug, Clone, Copy)]
+pub struct ReadOnly {
+    enabled: bool,
+}
+
+impl ReadOnly {
+    /// new
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    /// 環境変数 READ_ONLY=true で有効にする
+    pub fn from_env() -> Self {
+        let enabled = env::var("READ_ONLY")
+            .map(|value| value == "true")
+            .unwrap_or(false);
+        if enabled {
+            tracing::warn!("[READ_ONLY] is set, mutating requests are rejected");
+        }
+        Self::new(enabled)
+    }
+}
+
+/// tower::filter から呼ばれる判定(有効な間は POST・PUT・PATCH・DELETE を拒否する)
+impl<B> Predicate<Request<B>> for ReadOnly {
+    type Request = Request<B>;
+
+    fn check(&mut self, req: Request<B>) -> Result<Self::Request, BoxError> {
+        let mutating = matches!(
+            *req.method(),
+            Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+        );
+        if self.enabled && mutating {
+            return Err(ReadOnlyMode.into());
+        }
+        Ok(req)
+    }
+}
+
+/// 読み取り専用モード中の書き込みを503レスポンスに変換する
+pub async fn handle_error(error: BoxError) -> Response {
+    if error.is::<ReadOnlyMode>() {
+        let body = Json(json!({ "error": "service in read-only mode" }));
+        return (StatusCode::SERVICE_UNAVAILABLE, body).into_response();
+    }
+    (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 有効な間は書き込みだけ拒否する
+    #[tokio::test]
+    async fn should_reject_mutating_requests() {
+        let request = |method| Request::builder().method(method).body(()).unwrap();
+        let mut read_only = ReadOnly::new(true);
+        assert!(read_only.check(request(Method::GET)).is_ok());
+        for method in [Method::POST, Method::PATCH, Method::DELETE] {
+            let error = read_only.check(request(method)).unwrap_err();
+            let res = handle_error(error).await;
+            assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let mut read_write = ReadOnly::new(false);
+        assert!(read_write.check(request(Method::POST)).is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+use axum::http::{HeaderValue, Request, Response};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tower::{Layer, Service};
+use tracing::Instrument;
+
+/// リクエストIDのヘッダ名
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// 受け取るリクエストIDの最大長
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// リクエストID(ハンドラーからは Extension で参照できる)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// ヘッダの値を使う(空・長すぎる・表示できない文字を含む場合は使わない)
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let valid = !value.is_empty()
+            && value.len() <= MAX_REQUEST_ID_LEN
+            && value.chars().all(|c| c.is_ascii_graphic());
+        valid.then(|| Self(value.to_string()))
+    }
+
+    /// 新しいIDを採番する(現在時刻と連番から作る)
+    fn generate() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        Self(format!("{:016x}-{:08x}", now, count))
+    }
+}
+
+/// リクエストIDを付けるレイヤー
+/// X-Request-Id ヘッダがあればそれを使い、無ければ採番する。処理全体をIDを持つspanで囲むので
+/// リポジトリ呼び出し中のSQLのログにも同じIDが付く。レスポンスにも X-Request-Id を返す
+#[derive(Debug, Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = SetRequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SetRequestId { inner }
+    }
+}
+
+/// RequestIdLayer のサービス
+#[derive(Debug, Clone)]
+pub struct SetRequestId<S> {
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for SetRequestId<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(RequestId::from_header)
+            .unwrap_or_else(RequestId::generate);
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id.0,
+            method = %req.method(),
+            path = %req.uri().path(),
+        );
+        let header = HeaderValue::from_str(&request_id.0).ok();
+        req.extensions_mut().insert(request_id);
+        let future = {
+            let _enter = span.enter();
+            self.inner.call(req)
+        };
+
+        Box::pin(
+            async move {
+                let mut res = future.await?;
+                if let Some(header) = header {
+                    res.headers_mut().insert(REQUEST_ID_HEADER, header);
+                }
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::Body;
+    use std::convert::Infallible;
+    use tower::{service_fn, ServiceExt};
+
+    /// 受け取ったIDをそのまま返し、無ければ採番する
+    #[tokio::test]
+    async fn should_set_request_id() {
+        let service = RequestIdLayer.layer(service_fn(|req: Request<Body>| async move {
+            let RequestId(id) = req.extensions().get::<RequestId>().unwrap().clone();
+            Ok::<_, Infallible>(Response::new(Body::from(id)))
+        }));
+
+        let req = Request::builder()
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[REQUEST_ID_HEADER], "abc-123");
+
+        let req = Request::builder()
+            .header(REQUEST_ID_HEADER, "invalid id")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.oneshot(req).await.unwrap();
+        let id = res.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_ne!(id, "invalid id");
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(bytes, id.as_bytes());
+    }
+}
@@ -0,0 +1,38 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::{env, time::Duration};
+use tower::{timeout::error::Elapsed, BoxError};
+
+/// リクエストの処理時間の上限(環境変数 REQUEST_TIMEOUT_MS、既定30秒)
+pub fn from_env() -> Duration {
+    let millis = env::var("REQUEST_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30_000);
+    Duration::from_millis(millis)
+}
+
+/// 処理時間の上限超過を504レスポンスに変換する
+pub async fn handle_error(error: BoxError) -> Response {
+    if error.is::<Elapsed>() {
+        let body = Json(json!({ "error": "request timeout" }));
+        return (StatusCode::GATEWAY_TIMEOUT, body).into_response();
+    }
+    (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 上限超過は504
+    #[tokio::test]
+    async fn should_convert_elapsed_to_gateway_timeout() {
+        let res = handle_error(Elapsed::new().into()).await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+}
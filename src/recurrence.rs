@@ -0,0 +1,48 @@
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::{TodoEvent, TodoEvents};
+use crate::repositories::todo::{TodoRepository, UpdateTodo};
+
+/// 繰り返しTODOの次の回を作成するタスクを起動する
+/// 繰り返しTODOが完了になったら、期限を間隔(daily・weekly・monthly)だけ進めた次の回を作成する
+/// 繰り返しの指定は次の回へ移し、完了した回からは消す(再度更新しても重複して作成しない)
+pub fn spawn_recurrence<T: TodoRepository>(repository: Arc<T>, events: TodoEvents) {
+    let mut receiver = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let todo = match receiver.recv().await {
+                Ok(TodoEvent::Updated { todo, .. }) => todo,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("recurrence skipped {} events", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let next = match todo.next_occurrence() {
+                Some(next) => next,
+                None => continue,
+            };
+            let user_id = todo.user_id();
+            let created = match repository.create(user_id, next).await {
+                Ok(created) => created,
+                Err(e) => {
+                    tracing::warn!("fail create next occurrence of {}: {}", todo.id(), e);
+                    continue;
+                }
+            };
+            events.publish(TodoEvent::Created { todo: created });
+            match repository
+                .update(user_id, todo.id(), UpdateTodo::end_recurrence())
+                .await
+            {
+                Ok(ended) => events.publish(TodoEvent::Updated {
+                    changed: ended.changed_fields(&todo),
+                    todo: ended,
+                }),
+                Err(e) => tracing::warn!("fail end recurrence of {}: {}", todo.id(), e),
+            }
+        }
+    });
+}
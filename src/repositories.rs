@@ -1,14 +1,167 @@
 pub mod todo;
 pub mod label;
+mod lru_cache;
+mod single_flight;
 
+use std::{
+    env, fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
 use thiserror::Error;
 
-#[derive(Debug, Error)]
-enum RepositoryError {
-    #[error("NotFound, id is {0}")]
-    NotFound(i32),
+use label::LabelId;
+use todo::TodoId;
+
+/// 見つからなかったもののID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceId {
+    Todo(TodoId),
+    Label(LabelId),
+}
+
+impl From<TodoId> for ResourceId {
+    fn from(id: TodoId) -> Self {
+        Self::Todo(id)
+    }
+}
+
+impl From<LabelId> for ResourceId {
+    fn from(id: LabelId) -> Self {
+        Self::Label(id)
+    }
+}
+
+impl fmt::Display for ResourceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Todo(id) => write!(f, "todo {}", id),
+            Self::Label(id) => write!(f, "label {}", id),
+        }
+    }
+}
+
+/// NotFound のメッセージからIDを除くか(環境変数 HIDE_RESOURCE_IDS=true、既定はIDを含める)
+/// IDの形式(連番など)を外に見せたくない環境向け
+fn hide_resource_ids() -> bool {
+    static HIDE_RESOURCE_IDS: OnceLock<bool> = OnceLock::new();
+    *HIDE_RESOURCE_IDS.get_or_init(|| {
+        env::var("HIDE_RESOURCE_IDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(false)
+    })
+}
+
+/// NotFound のメッセージ(hide_id ならIDを含めない)
+fn not_found_message(id: &ResourceId, hide_id: bool) -> String {
+    if hide_id {
+        "resource not found".to_string()
+    } else {
+        format!("NotFound, id is {}", id)
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum RepositoryError {
+    #[error("{}", not_found_message(.0, hide_resource_ids()))]
+    NotFound(ResourceId),
     #[error("Unexpected error: {0}")]
     Unexpected(String),
     #[error("Duplicate ID error: {0}")]
-    Duplicate(i32),
+    Duplicate(LabelId),
+    #[error("Database connection error: {0}")]
+    Connection(String),
+    #[error("Connection pool timed out")]
+    PoolTimedOut,
+    #[error("Database connection lost: {0}")]
+    ConnectionLost(String),
+    #[error("Quota exceeded, limit is {0}")]
+    QuotaExceeded(i64),
+    #[error("Text is longer than {0} characters")]
+    TextTooLong(usize),
+}
+
+/// sqlx のエラーを種類ごとにリポジトリのエラーにする(接続の問題は再試行できるものとして分ける)
+impl From<sqlx::Error> for RepositoryError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::PoolTimedOut => Self::PoolTimedOut,
+            sqlx::Error::Io(_) | sqlx::Error::PoolClosed => Self::ConnectionLost(e.to_string()),
+            _ => Self::Unexpected(e.to_string()),
+        }
+    }
+}
+
+/// 接続プールの取得待ちがタイムアウトしたエラーか(混雑しているだけなので再試行できる)
+pub fn is_pool_timeout(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<RepositoryError>(),
+        Some(RepositoryError::PoolTimedOut)
+    ) || matches!(
+        error.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::PoolTimedOut)
+    )
+}
+
+/// DBとの接続が切れたエラーか(フェイルオーバー中など。接続し直せば再試行で回復する)
+pub fn is_connection_lost(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<RepositoryError>(),
+        Some(RepositoryError::ConnectionLost(_))
+    ) || matches!(
+        error.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::Io(_) | sqlx::Error::PoolClosed)
+    )
+}
+
+/// 接続が切れてから新しい接続ができるまでの間か
+static CONNECTION_LOST: AtomicBool = AtomicBool::new(false);
+
+/// 接続が切れたことを記録する(次に接続できたときに再接続としてログに出す)
+pub fn mark_connection_lost() {
+    CONNECTION_LOST.store(true, Ordering::Relaxed);
+}
+
+/// 新しい接続ができたときに呼ぶ
+pub(crate) fn on_connect() {
+    if CONNECTION_LOST.swap(false, Ordering::Relaxed) {
+        tracing::info!("reconnected to database");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// NotFound のメッセージはIDの型に合わせて出し、指定があればIDを除く
+    #[cfg(not(feature = "uuid-id"))]
+    #[test]
+    fn should_format_not_found() {
+        let todo = ResourceId::from(TodoId::from(1));
+        let label = ResourceId::from(LabelId::from(2));
+        assert_eq!(not_found_message(&todo, false), "NotFound, id is todo 1");
+        assert_eq!(not_found_message(&label, false), "NotFound, id is label 2");
+        assert_eq!(not_found_message(&todo, true), "resource not found");
+        assert_eq!(not_found_message(&label, true), "resource not found");
+        assert_eq!(
+            RepositoryError::NotFound(todo).to_string(),
+            not_found_message(&todo, hide_resource_ids())
+        );
+    }
+
+    /// sqlx のエラーは接続の問題とそれ以外に分け、判定関数からも同じに見える
+    #[test]
+    fn should_convert_sqlx_errors() {
+        let error = anyhow::Error::from(RepositoryError::from(sqlx::Error::PoolTimedOut));
+        assert!(is_pool_timeout(&error));
+        let error = anyhow::Error::from(RepositoryError::from(sqlx::Error::PoolClosed));
+        assert!(is_connection_lost(&error));
+        assert!(matches!(
+            RepositoryError::from(sqlx::Error::RowNotFound),
+            RepositoryError::Unexpected(_)
+        ));
+    }
 }
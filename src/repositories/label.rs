@@ -1,28 +1,160 @@
 use axum::async_trait;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
 use sqlx::PgPool;
+#[cfg(not(feature = "uuid-id"))]
+use sqlx::SqlitePool;
+use std::{fmt, num::ParseIntError, str::FromStr};
+use validator::{Validate, ValidationError};
 use super::RepositoryError;
+use super::todo::TodoId;
 
 /// ラベルリポジトリ
 #[async_trait]
 pub trait LabelRepository: Clone + Send + Sync + 'static {
-    async fn create(&self, name: String) -> anyhow::Result<Label>;
+    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label>;
     async fn all(&self) -> anyhow::Result<Vec<Label>>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    async fn all_with_counts(&self) -> anyhow::Result<Vec<LabelWithCount>>;
+    async fn unused(&self) -> anyhow::Result<Vec<Label>>;
+    async fn find_by_todo(&self, todo_id: TodoId) -> anyhow::Result<Vec<Label>>;
+    async fn add_label(&self, todo_id: TodoId, label_id: LabelId) -> anyhow::Result<bool>;
+    async fn remove_label(&self, todo_id: TodoId, label_id: LabelId) -> anyhow::Result<bool>;
+    async fn search(&self, prefix: &str, limit: i64) -> anyhow::Result<Vec<Label>>;
+    async fn update(&self, id: LabelId, payload: UpdateLabel) -> anyhow::Result<Label>;
+    async fn delete(&self, id: LabelId) -> anyhow::Result<()>;
+    async fn delete_many(&self, ids: &[LabelId]) -> anyhow::Result<u64>;
+}
+
+/// ラベルのID(TODOのIDと取り違えないよう型を分ける。JSON・DBでは数値として扱う)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct LabelId(i32);
+
+impl From<i32> for LabelId {
+    fn from(id: i32) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for LabelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for LabelId {
+    type Err = ParseIntError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value.parse().map(Self)
+    }
+}
+
+/// id = any($1) で配列として渡せるようにする
+impl PgHasArrayType for LabelId {
+    fn array_type_info() -> PgTypeInfo {
+        <i32 as PgHasArrayType>::array_type_info()
+    }
 }
 
 /// ラベル
+/// 名前は大文字小文字を区別しない識別子として扱う("Work" と "work" は同じラベル)
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct Label {
-    pub id: i32,
+    pub id: LabelId,
+    /// 取り込みで読むときも CreateLabel と同じく前後の空白を除く
+    #[serde(deserialize_with = "deserialize_trimmed")]
     pub name: String,
+    pub color: String,
 }
 
-/// ラベル(Update用)
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct UpdateLabel {
-    pub id: i32,
+/// ラベルと付いているTODOの件数
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct LabelWithCount {
+    pub id: LabelId,
     pub name: String,
+    pub color: String,
+    pub todo_count: i64,
+}
+
+/// LIKEの特殊文字(% _ \)をエスケープする
+pub(crate) fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// 一意制約違反か(PostgreSQL は 23505、SQLite は SQLITE_CONSTRAINT_UNIQUE = 2067)
+fn is_unique_violation(error: &sqlx::Error) -> bool {
+    matches!(error.as_database_error().and_then(|e| e.code()).as_deref(), Some("23505") | Some("2067"))
+}
+
+/// ラベル色の既定値(グレー)
+const DEFAULT_COLOR: &str = "#808080";
+
+/// ラベル作成用データ(名前は前後の空白を除いてから検証する)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct CreateLabel {
+    #[serde(deserialize_with = "deserialize_trimmed")]
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 50, message = "Over name length"))]
+    #[validate(custom = "validate_no_control_chars")]
+    name: String,
+    #[serde(default = "default_color")]
+    #[validate(custom = "validate_color")]
+    color: String,
+}
+
+/// 省略時のラベル色
+fn default_color() -> String {
+    DEFAULT_COLOR.to_string()
+}
+
+/// 前後の空白を除いて文字列を読む
+fn deserialize_trimmed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Ok(value.trim().to_string())
+}
+
+/// ラベル名の最大文字数(CreateLabel・UpdateLabel の検証と同じ)
+pub(crate) const MAX_LABEL_NAME_LEN: usize = 50;
+
+/// 制御文字を含まないこと
+pub(crate) fn validate_no_control_chars(value: &str) -> Result<(), ValidationError> {
+    if value.chars().any(char::is_control) {
+        let mut error = ValidationError::new("control_chars");
+        error.message = Some("Can not contain control characters".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// 色が #rrggbb 形式であること
+pub(crate) fn validate_color(value: &str) -> Result<(), ValidationError> {
+    let hex = value.strip_prefix('#').unwrap_or_default();
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        let mut error = ValidationError::new("color");
+        error.message = Some("Must be a hex color like #aabbcc".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// ラベル更新用データ(省略した項目は変更しない)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct UpdateLabel {
+    #[serde(default, deserialize_with = "deserialize_trimmed_option")]
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 50, message = "Over name length"))]
+    #[validate(custom = "validate_no_control_chars")]
+    name: Option<String>,
+    #[validate(custom = "validate_color")]
+    color: Option<String>,
+}
+
+/// 前後の空白を除いて文字列を読む(省略可)
+fn deserialize_trimmed_option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.map(|value| value.trim().to_string()))
 }
 
 
@@ -38,28 +170,234 @@ impl LabelRepositoryForDb {
     pub fn new(pool: PgPool) -> Self {
         Self { pool }
     }
+    /// 同名(大文字小文字を区別しない)のラベルがあれば Duplicate、なければ元のエラーにする
+    /// 事前の確認だけでは同時に作成・更新された時に漏れるので、一意制約違反を見てから引き直す
+    async fn duplicate_error(&self, name: &str, error: sqlx::Error) -> anyhow::Error {
+        if !is_unique_violation(&error) {
+            return error.into();
+        }
+        match sqlx::query_scalar::<_, LabelId>(
+            r#" select id from labels where lower(name) = lower($1) "#
+        ).bind(name)
+            .fetch_optional(&self.pool)
+            .await
+        {
+            Ok(Some(id)) => RepositoryError::Duplicate(id).into(),
+            Ok(None) => error.into(),
+            Err(e) => e.into(),
+        }
+    }
+    /// テーブル・列が揃っているか確認する(起動時に使う)
+    pub async fn check_schema(&self) -> anyhow::Result<()> {
+        sqlx::query(r#" select id, name from labels limit 0 "#)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(())
+    }
 }
 #[async_trait]
 impl LabelRepository for LabelRepositoryForDb {
     /// 新規作成
-    async fn create(&self, name: String) -> anyhow::Result<Label> {
-        let optional_label = sqlx::query_as::<_, Label>(
+    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label> {
+        let name = payload.name;
+        let label = match sqlx::query_as::<_, Label>(
+            r#" insert into labels ( name, color ) values ($1, $2) returning * "#,
+        )
+            .bind(name.clone())
+            .bind(payload.color)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(label) => label,
+            Err(e) => return Err(self.duplicate_error(&name, e).await),
+        };
+
+        Ok(label)
+    }
+    /// 全件取得
+    async fn all(&self) -> anyhow::Result<Vec<Label>> {
+        let labels = sqlx::query_as::<_, Label>(
+            r#" select * from labels order by labels.id asc "#,
+        ).fetch_all(&self.pool).await?;
 
-            r#" select * from labels where name = $1 "#
-        ).bind(name.clone())
+        Ok(labels)
+    }
+    /// 付いているTODOの件数と合わせて全件取得(使われていないラベルは0件)
+    async fn all_with_counts(&self) -> anyhow::Result<Vec<LabelWithCount>> {
+        let labels = sqlx::query_as::<_, LabelWithCount>(
+            r#" select labels.id, labels.name, labels.color, count(todo_labels.todo_id) as todo_count from labels left join todo_labels on todo_labels.label_id = labels.id group by labels.id, labels.name, labels.color order by labels.id asc "#,
+        ).fetch_all(&self.pool).await?;
+
+        Ok(labels)
+    }
+    /// どのTODOにも付いていないラベルを取得
+    async fn unused(&self) -> anyhow::Result<Vec<Label>> {
+        let labels = sqlx::query_as::<_, Label>(
+            r#" select * from labels where not exists (select 1 from todo_labels where todo_labels.label_id = labels.id) order by labels.id asc "#,
+        ).fetch_all(&self.pool).await?;
+
+        Ok(labels)
+    }
+    /// TODOに付いたラベルを取得
+    async fn find_by_todo(&self, todo_id: TodoId) -> anyhow::Result<Vec<Label>> {
+        let labels = sqlx::query_as::<_, Label>(
+            r#" select labels.* from labels inner join todo_labels on todo_labels.label_id = labels.id where todo_labels.todo_id = $1 order by labels.id asc "#,
+        ).bind(todo_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(labels)
+    }
+    /// TODOにラベルを付ける(新たに付けたらtrue、付いていればfalse)
+    async fn add_label(&self, todo_id: TodoId, label_id: LabelId) -> anyhow::Result<bool> {
+        sqlx::query_as::<_, Label>(
+            r#" select * from labels where id = $1 "#
+        ).bind(label_id)
             .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(label_id.into()))?;
+        let inserted = sqlx::query(
+            r#" insert into todo_labels ( todo_id, label_id ) values ($1, $2) on conflict (todo_id, label_id) do nothing "#,
+        ).bind(todo_id)
+            .bind(label_id)
+            .execute(&self.pool)
             .await?;
 
-        if let Some(label) = optional_label {
-            return Err(RepositoryError::Duplicate(label.id).into());
-        }
+        Ok(inserted.rows_affected() > 0)
+    }
+    /// TODOからラベルを外す(外したらtrue、付いていなければfalse)
+    async fn remove_label(&self, todo_id: TodoId, label_id: LabelId) -> anyhow::Result<bool> {
+        let deleted = sqlx::query(
+            r#" delete from todo_labels where todo_id = $1 and label_id = $2 "#,
+        ).bind(todo_id)
+            .bind(label_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+    /// 名前の前方一致検索(大文字小文字を区別しない、名前順)
+    async fn search(&self, prefix: &str, limit: i64) -> anyhow::Result<Vec<Label>> {
+        let labels = sqlx::query_as::<_, Label>(
+            r#" select * from labels where lower(name) like lower($1) || '%' escape '\' order by name asc, id asc limit $2 "#,
+        ).bind(escape_like(prefix))
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
 
-        let label = sqlx::query_as::<_, Label>(
-            r#" insert into labels ( name ) values ($1) returning * "#,
+        Ok(labels)
+    }
+    /// 更新(他のラベルと同名にはできない。大文字小文字は区別しない)
+    async fn update(&self, id: LabelId, payload: UpdateLabel) -> anyhow::Result<Label> {
+        let old_label = sqlx::query_as::<_, Label>(
+            r#" select * from labels where id = $1 "#
+        ).bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(id.into()))?;
+        let name = payload.name.unwrap_or(old_label.name);
+        let label = match sqlx::query_as::<_, Label>(
+            r#" update labels set name = $1, color = $2 where id = $3 returning * "#,
         )
             .bind(name.clone())
+            .bind(payload.color.unwrap_or(old_label.color))
+            .bind(id)
             .fetch_one(&self.pool)
+            .await
+        {
+            Ok(label) => label,
+            Err(e) => return Err(self.duplicate_error(&name, e).await),
+        };
+
+        Ok(label)
+    }
+    /// 削除(TODOへの付与も同じトランザクションで消す)
+    async fn delete(&self, id: LabelId) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#" delete from todo_labels where label_id = $1 "#,
+        ).bind(id).execute(&mut tx).await?;
+        let deleted = sqlx::query(
+            r#" delete from labels where id = $1 "#,
+        ).bind(id).execute(&mut tx).await?;
+        if deleted.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id.into()).into());
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+    /// 一括削除(存在しないIDは無視し、削除した件数を返す。TODOへの付与も同じトランザクションで消す)
+    async fn delete_many(&self, ids: &[LabelId]) -> anyhow::Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#" delete from todo_labels where label_id = any($1) "#,
+        ).bind(ids).execute(&mut tx).await?;
+        let deleted = sqlx::query(
+            r#" delete from labels where id = any($1) "#,
+        ).bind(ids).execute(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(deleted.rows_affected())
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//-------------------------------------------------------------------------------------------------
+//-------------------------------------------------------------------------------------------------
+/// SQLiteリポジトリ(IDは整数のみ対応)
+#[cfg(not(feature = "uuid-id"))]
+#[derive(Debug, Clone)]
+pub struct LabelRepositoryForSqlite {
+    pool: SqlitePool,
+}
+#[cfg(not(feature = "uuid-id"))]
+impl LabelRepositoryForSqlite {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+    /// 同名(大文字小文字を区別しない)のラベルがあれば Duplicate、なければ元のエラーにする
+    /// 事前の確認だけでは同時に作成・更新された時に漏れるので、一意制約違反を見てから引き直す
+    async fn duplicate_error(&self, name: &str, error: sqlx::Error) -> anyhow::Error {
+        if !is_unique_violation(&error) {
+            return error.into();
+        }
+        match sqlx::query_scalar::<_, LabelId>(
+            r#" select id from labels where lower(name) = lower(?) "#
+        ).bind(name)
+            .fetch_optional(&self.pool)
+            .await
+        {
+            Ok(Some(id)) => RepositoryError::Duplicate(id).into(),
+            Ok(None) => error.into(),
+            Err(e) => e.into(),
+        }
+    }
+    /// テーブル・列が揃っているか確認する(起動時に使う)
+    pub async fn check_schema(&self) -> anyhow::Result<()> {
+        sqlx::query(r#" select id, name from labels limit 0 "#)
+            .fetch_all(&self.pool)
             .await?;
+        Ok(())
+    }
+}
+#[cfg(not(feature = "uuid-id"))]
+#[async_trait]
+impl LabelRepository for LabelRepositoryForSqlite {
+    /// 新規作成
+    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label> {
+        let name = payload.name;
+        let label = match sqlx::query_as::<_, Label>(
+            r#" insert into labels ( name, color ) values (?, ?) returning * "#,
+        )
+            .bind(name.clone())
+            .bind(payload.color)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(label) => label,
+            Err(e) => return Err(self.duplicate_error(&name, e).await),
+        };
 
         Ok(label)
     }
@@ -71,17 +409,135 @@ impl LabelRepository for LabelRepositoryForDb {
 
         Ok(labels)
     }
-    /// 削除
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+    /// 付いているTODOの件数と合わせて全件取得(使われていないラベルは0件)
+    async fn all_with_counts(&self) -> anyhow::Result<Vec<LabelWithCount>> {
+        let labels = sqlx::query_as::<_, LabelWithCount>(
+            r#" select labels.id, labels.name, labels.color, count(todo_labels.todo_id) as todo_count from labels left join todo_labels on todo_labels.label_id = labels.id group by labels.id, labels.name, labels.color order by labels.id asc "#,
+        ).fetch_all(&self.pool).await?;
+
+        Ok(labels)
+    }
+    /// どのTODOにも付いていないラベルを取得
+    async fn unused(&self) -> anyhow::Result<Vec<Label>> {
+        let labels = sqlx::query_as::<_, Label>(
+            r#" select * from labels where not exists (select 1 from todo_labels where todo_labels.label_id = labels.id) order by labels.id asc "#,
+        ).fetch_all(&self.pool).await?;
+
+        Ok(labels)
+    }
+    /// TODOに付いたラベルを取得
+    async fn find_by_todo(&self, todo_id: TodoId) -> anyhow::Result<Vec<Label>> {
+        let labels = sqlx::query_as::<_, Label>(
+            r#" select labels.* from labels inner join todo_labels on todo_labels.label_id = labels.id where todo_labels.todo_id = ? order by labels.id asc "#,
+        ).bind(todo_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(labels)
+    }
+    /// TODOにラベルを付ける(新たに付けたらtrue、付いていればfalse)
+    async fn add_label(&self, todo_id: TodoId, label_id: LabelId) -> anyhow::Result<bool> {
+        sqlx::query_as::<_, Label>(
+            r#" select * from labels where id = ? "#
+        ).bind(label_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(label_id.into()))?;
+        let inserted = sqlx::query(
+            r#" insert into todo_labels ( todo_id, label_id ) values (?, ?) on conflict (todo_id, label_id) do nothing "#,
+        ).bind(todo_id)
+            .bind(label_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(inserted.rows_affected() > 0)
+    }
+    /// TODOからラベルを外す(外したらtrue、付いていなければfalse)
+    async fn remove_label(&self, todo_id: TodoId, label_id: LabelId) -> anyhow::Result<bool> {
+        let deleted = sqlx::query(
+            r#" delete from todo_labels where todo_id = ? and label_id = ? "#,
+        ).bind(todo_id)
+            .bind(label_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+    /// 名前の前方一致検索(大文字小文字を区別しない、名前順)
+    async fn search(&self, prefix: &str, limit: i64) -> anyhow::Result<Vec<Label>> {
+        let labels = sqlx::query_as::<_, Label>(
+            r#" select * from labels where lower(name) like lower(?) || '%' escape '\' order by name asc, id asc limit ? "#,
+        ).bind(escape_like(prefix))
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(labels)
+    }
+    /// 更新(他のラベルと同名にはできない。大文字小文字は区別しない)
+    async fn update(&self, id: LabelId, payload: UpdateLabel) -> anyhow::Result<Label> {
+        let old_label = sqlx::query_as::<_, Label>(
+            r#" select * from labels where id = ? "#
+        ).bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(id.into()))?;
+        let name = payload.name.unwrap_or(old_label.name);
+        let label = match sqlx::query_as::<_, Label>(
+            r#" update labels set name = ?, color = ? where id = ? returning * "#,
+        )
+            .bind(name.clone())
+            .bind(payload.color.unwrap_or(old_label.color))
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(label) => label,
+            Err(e) => return Err(self.duplicate_error(&name, e).await),
+        };
+
+        Ok(label)
+    }
+    /// 削除(TODOへの付与も同じトランザクションで消す)
+    async fn delete(&self, id: LabelId) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
         sqlx::query(
-            r#" delete from labels where id = $1 "#,
-        ).bind(id).execute(&self.pool).await.map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+            r#" delete from todo_labels where label_id = ? "#,
+        ).bind(id).execute(&mut tx).await?;
+        let deleted = sqlx::query(
+            r#" delete from labels where id = ? "#,
+        ).bind(id).execute(&mut tx).await?;
+        if deleted.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id.into()).into());
+        }
+        tx.commit().await?;
 
         Ok(())
     }
+    /// 一括削除(存在しないIDは無視し、削除した件数を返す。TODOへの付与も同じトランザクションで消す)
+    async fn delete_many(&self, ids: &[LabelId]) -> anyhow::Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        // SQLiteは配列を渡せないのでIDの数だけプレースホルダを並べる
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let mut tx = self.pool.begin().await?;
+        let sql = format!(" delete from todo_labels where label_id in ({}) ", placeholders);
+        let mut query = sqlx::query(&sql);
+        for id in ids {
+            query = query.bind(id);
+        }
+        query.execute(&mut tx).await?;
+        let sql = format!(" delete from labels where id in ({}) ", placeholders);
+        let mut query = sqlx::query(&sql);
+        for id in ids {
+            query = query.bind(id);
+        }
+        let deleted = query.execute(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(deleted.rows_affected())
+    }
 }
 
 //-------------------------------------------------------------------------------------------------
@@ -108,7 +564,7 @@ mod test {
         let label_text = "test_label";
 
         // C
-        let label = repository.create(label_text.to_string()).await.expect("[create] returned Err");
+        let label = repository.create(CreateLabel::new(label_text.to_string())).await.expect("[create] returned Err");
         assert_eq!(label.name, label_text);
 
         // all
@@ -116,9 +572,126 @@ mod test {
         let label = labels.last().unwrap();
         assert_eq!(label.name, label_text);
 
+        // u
+        let payload = UpdateLabel { name: None, color: Some("#aabbcc".to_string()) };
+        let label = repository.update(label.id, payload).await.expect("[update] returned Err");
+        assert_eq!(label.name, label_text);
+        assert_eq!(label.color, "#aabbcc");
+
         // d
         repository.delete(label.id).await.expect("[delete] returned Err");
     }
+
+    /// 同じ名前を同時に作成しても1件だけ作られ、残りは既存ラベルのIDで Duplicate になる
+    #[tokio::test]
+    async fn create_duplicate_concurrently() {
+        dotenv().ok();
+        let database_url = &env::var(DB_URL_ENV).expect(&format!("undefined [{}]", DB_URL_ENV));
+        let pool = PgPool::connect(database_url).await.expect(&format!("fail connect database, url is [{}]", database_url));
+
+        let repository = LabelRepositoryForDb::new(pool);
+        let name = format!("concurrent_label_{}", std::process::id());
+        let create = || repository.create(CreateLabel::new(name.clone()));
+        let (first, second, third) = tokio::join!(create(), create(), create());
+        let results = [first, second, third];
+
+        let created: Vec<&Label> = results.iter().filter_map(|result| result.as_ref().ok()).collect();
+        assert_eq!(created.len(), 1);
+        let label_id = created[0].id;
+        for result in &results {
+            if let Err(error) = result {
+                assert!(matches!(error.downcast_ref::<RepositoryError>(), Some(RepositoryError::Duplicate(id)) if *id == label_id));
+            }
+        }
+        repository.delete(label_id).await.expect("[delete] returned Err");
+    }
+}
+
+/// SQLite用リポジトリのためのテスト(インメモリDBを使う)
+#[cfg(test)]
+#[cfg(not(feature = "uuid-id"))]
+mod sqlite_test {
+    use super::*;
+
+    /// 削除するとTODOへの付与も消える
+    #[tokio::test]
+    async fn delete_with_todo_labels() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite").run(&pool).await.expect("fail run migrations");
+        let repository = LabelRepositoryForSqlite::new(pool.clone());
+
+        let label = repository.create(CreateLabel::new("label".to_string())).await.expect("[create] returned Err");
+        sqlx::query(r#" insert into todos (text) values ('todo') "#).execute(&pool).await.unwrap();
+        assert!(repository.add_label(TodoId::from(1), label.id).await.expect("[add_label] returned Err"));
+        assert!(!repository.add_label(TodoId::from(1), label.id).await.expect("[add_label] returned Err"));
+        assert_eq!(repository.find_by_todo(TodoId::from(1)).await.expect("[find_by_todo] returned Err"), vec![label.clone()]);
+        assert!(repository.remove_label(TodoId::from(1), label.id).await.expect("[remove_label] returned Err"));
+        assert!(!repository.remove_label(TodoId::from(1), label.id).await.expect("[remove_label] returned Err"));
+        assert!(repository.find_by_todo(TodoId::from(1)).await.expect("[find_by_todo] returned Err").is_empty());
+        assert!(repository.add_label(TodoId::from(1), label.id).await.expect("[add_label] returned Err"));
+
+        repository.delete(label.id).await.expect("[delete] returned Err");
+        let todo_labels: i64 = sqlx::query_scalar(r#" select count(*) from todo_labels "#).fetch_one(&pool).await.unwrap();
+        assert_eq!(todo_labels, 0);
+        let counts = repository.all_with_counts().await.expect("[all_with_counts] returned Err");
+        assert!(counts.is_empty());
+        assert!(repository.delete(label.id).await.is_err());
+    }
+
+    /// 一括削除(存在しないIDは無視する)
+    #[tokio::test]
+    async fn delete_many_with_todo_labels() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite").run(&pool).await.expect("fail run migrations");
+        let repository = LabelRepositoryForSqlite::new(pool.clone());
+
+        sqlx::query(r#" insert into todos (text) values ('todo') "#).execute(&pool).await.unwrap();
+        for name in ["a", "b", "c"] {
+            let label = repository.create(CreateLabel::new(name.to_string())).await.expect("[create] returned Err");
+            repository.add_label(TodoId::from(1), label.id).await.expect("[add_label] returned Err");
+        }
+
+        let deleted = repository.delete_many(&[LabelId::from(1), LabelId::from(2), LabelId::from(9)]).await.expect("[delete_many] returned Err");
+        assert_eq!(deleted, 2);
+        let labels = repository.find_by_todo(TodoId::from(1)).await.expect("[find_by_todo] returned Err");
+        assert_eq!(labels.iter().map(|label| label.name.as_str()).collect::<Vec<_>>(), vec!["c"]);
+        let counts = repository.all_with_counts().await.expect("[all_with_counts] returned Err");
+        assert_eq!(counts.iter().map(|label| (label.id, label.todo_count)).collect::<Vec<_>>(), vec![(LabelId::from(3), 1)]);
+        assert_eq!(repository.delete_many(&[]).await.expect("[delete_many] returned Err"), 0);
+    }
+    /// どのTODOにも付いていないラベルだけを返す
+    #[tokio::test]
+    async fn unused_labels() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite").run(&pool).await.expect("fail run migrations");
+        let repository = LabelRepositoryForSqlite::new(pool.clone());
+
+        sqlx::query(r#" insert into todos (text) values ('todo') "#).execute(&pool).await.unwrap();
+        let used = repository.create(CreateLabel::new("used".to_string())).await.expect("[create] returned Err");
+        let unused = repository.create(CreateLabel::new("unused".to_string())).await.expect("[create] returned Err");
+        repository.add_label(TodoId::from(1), used.id).await.expect("[add_label] returned Err");
+        assert_eq!(repository.unused().await.expect("[unused] returned Err"), vec![unused.clone()]);
+
+        repository.add_label(TodoId::from(1), unused.id).await.expect("[add_label] returned Err");
+        assert!(repository.unused().await.expect("[unused] returned Err").is_empty());
+    }
+    /// 名前の重複は大文字小文字を区別しない
+    #[tokio::test]
+    async fn create_case_insensitive_duplicate() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite").run(&pool).await.expect("fail run migrations");
+        let repository = LabelRepositoryForSqlite::new(pool.clone());
+
+        let label = repository.create(CreateLabel::new("Work".to_string())).await.expect("[create] returned Err");
+        let error = repository.create(CreateLabel::new("work".to_string())).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<RepositoryError>(), Some(RepositoryError::Duplicate(id)) if *id == label.id));
+
+        let other = repository.create(CreateLabel::new("home".to_string())).await.expect("[create] returned Err");
+        let payload = UpdateLabel { name: Some("WORK".to_string()), color: None };
+        assert!(repository.update(other.id, payload).await.is_err());
+        let payload = UpdateLabel { name: Some("WORK".to_string()), color: None };
+        assert_eq!(repository.update(label.id, payload).await.expect("[update] returned Err").name, "WORK");
+    }
 }
 
 //-------------------------------------------------------------------------------------------------
@@ -129,21 +702,40 @@ mod test {
 #[cfg(test)]
 pub mod test_utils {
     use super::*;
+    use axum::async_trait;
     use std::{collections::HashMap, sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard}};
     use crate::repositories::RepositoryError;
+    use crate::repositories::todo::test_utils::{TodoLabelStore, TodoRepositoryForMemory};
 
-    type LabelData = HashMap<i32, Label>;
+    impl CreateLabel {
+        /// new object
+        pub fn new(name: String) -> Self {
+            Self { name, color: default_color() }
+        }
+    }
+
+    type LabelData = HashMap<LabelId, Label>;
 
     /// オンメモリリポジトリ
     #[derive(Debug, Clone)]
     pub struct LabelRepositoryForMemory {
         store: Arc<RwLock<LabelData>>,
+        todo_labels: TodoLabelStore,
     }
     impl LabelRepositoryForMemory {
         /// new object
         pub fn new() -> Self {
             LabelRepositoryForMemory {
                 store: Arc::default(),
+                todo_labels: Arc::default(),
+            }
+        }
+        /// TODOへのラベルの付与を TodoRepositoryForMemory と共有する
+        /// ラベルでの絞り込みやラベル付きの一覧にも付け外しが反映される
+        pub fn with_todos(todos: &TodoRepositoryForMemory) -> Self {
+            LabelRepositoryForMemory {
+                store: Arc::default(),
+                todo_labels: todos.label_store(),
             }
         }
         /// スレッドセーフにstoreを取得(write)
@@ -151,12 +743,16 @@ pub mod test_utils {
         /// スレッドセーフにstoreを取得(read)
         fn read_store_ref(&self) -> RwLockReadGuard<LabelData> { self.store.read().unwrap() }
     }
-    impl LabelRepositoryForMemory {
+    #[async_trait]
+    impl LabelRepository for LabelRepositoryForMemory {
         /// 新規作成
-        async fn create(&self, name: String) -> anyhow::Result<Label> {
+        async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label> {
             let mut store = self.write_store_ref();
-            let id = (store.len() + 1) as i32;
-            let label = Label{id, name };
+            if let Some(label) = store.values().find(|label| label.name.to_lowercase() == payload.name.to_lowercase()) {
+                return Err(RepositoryError::Duplicate(label.id).into());
+            }
+            let id = LabelId::from((store.len() + 1) as i32);
+            let label = Label{id, name: payload.name, color: payload.color };
             store.insert(id, label.clone());
             Ok(label)
         }
@@ -165,14 +761,113 @@ pub mod test_utils {
             let store: RwLockReadGuard<LabelData> = self.read_store_ref();
             Ok(Vec::from_iter(store.values().map(|label| label.clone())))
         }
+        /// 付いているTODOの件数と合わせて全件取得
+        async fn all_with_counts(&self) -> anyhow::Result<Vec<LabelWithCount>> {
+            let store = self.read_store_ref();
+            let todo_labels = self.todo_labels.read().unwrap();
+            let mut labels: Vec<LabelWithCount> = store.values()
+                .map(|label| LabelWithCount {
+                    id: label.id,
+                    name: label.name.clone(),
+                    color: label.color.clone(),
+                    todo_count: todo_labels.values().filter(|labels| labels.iter().any(|attached| attached.id == label.id)).count() as i64,
+                })
+                .collect();
+            labels.sort_by_key(|label| label.id);
+            Ok(labels)
+        }
+        /// どのTODOにも付いていないラベルを取得
+        async fn unused(&self) -> anyhow::Result<Vec<Label>> {
+            let store = self.read_store_ref();
+            let todo_labels = self.todo_labels.read().unwrap();
+            let mut labels: Vec<Label> = store.values()
+                .filter(|label| !todo_labels.values().flatten().any(|attached| attached.id == label.id))
+                .cloned()
+                .collect();
+            labels.sort_by_key(|label| label.id);
+            Ok(labels)
+        }
+        /// TODOに付いたラベルを取得
+        async fn find_by_todo(&self, todo_id: TodoId) -> anyhow::Result<Vec<Label>> {
+            let store = self.read_store_ref();
+            let todo_labels = self.todo_labels.read().unwrap();
+            let mut labels: Vec<Label> = todo_labels.get(&todo_id).into_iter().flatten()
+                .filter_map(|label| store.get(&label.id).cloned())
+                .collect();
+            labels.sort_by_key(|label| label.id);
+            Ok(labels)
+        }
+        /// TODOにラベルを付ける(新たに付けたらtrue、付いていればfalse)
+        async fn add_label(&self, todo_id: TodoId, label_id: LabelId) -> anyhow::Result<bool> {
+            let label = self.read_store_ref().get(&label_id).cloned().ok_or(RepositoryError::NotFound(label_id.into()))?;
+            let mut todo_labels = self.todo_labels.write().unwrap();
+            let labels = todo_labels.entry(todo_id).or_default();
+            if labels.iter().any(|attached| attached.id == label_id) {
+                return Ok(false);
+            }
+            labels.push(label);
+            Ok(true)
+        }
+        /// TODOからラベルを外す(外したらtrue、付いていなければfalse)
+        async fn remove_label(&self, todo_id: TodoId, label_id: LabelId) -> anyhow::Result<bool> {
+            let mut todo_labels = self.todo_labels.write().unwrap();
+            let labels = match todo_labels.get_mut(&todo_id) {
+                Some(labels) => labels,
+                None => return Ok(false),
+            };
+            let len = labels.len();
+            labels.retain(|attached| attached.id != label_id);
+            Ok(labels.len() < len)
+        }
+        /// 名前の前方一致検索
+        async fn search(&self, prefix: &str, limit: i64) -> anyhow::Result<Vec<Label>> {
+            let store = self.read_store_ref();
+            let prefix = prefix.to_lowercase();
+            let mut labels: Vec<Label> = store.values()
+                .filter(|label| label.name.to_lowercase().starts_with(&prefix))
+                .cloned()
+                .collect();
+            labels.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+            labels.truncate(limit as usize);
+            Ok(labels)
+        }
+        /// 更新
+        async fn update(&self, id: LabelId, payload: UpdateLabel) -> anyhow::Result<Label> {
+            let mut store = self.write_store_ref();
+            let old_label = store.get(&id).cloned().ok_or(RepositoryError::NotFound(id.into()))?;
+            let name = payload.name.unwrap_or(old_label.name);
+            if let Some(label) = store.values().find(|label| label.name.to_lowercase() == name.to_lowercase() && label.id != id) {
+                return Err(RepositoryError::Duplicate(label.id).into());
+            }
+            let label = Label{id, name, color: payload.color.unwrap_or(old_label.color) };
+            store.insert(id, label.clone());
+            // 付与済みの複製も書き換える
+            for attached in self.todo_labels.write().unwrap().values_mut().flatten().filter(|attached| attached.id == id) {
+                *attached = label.clone();
+            }
+            Ok(label)
+        }
         /// 削除
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        async fn delete(&self, id: LabelId) -> anyhow::Result<()> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            store.remove(&id).ok_or(RepositoryError::NotFound(id.into()))?;
+            for labels in self.todo_labels.write().unwrap().values_mut() {
+                labels.retain(|attached| attached.id != id);
+            }
             Ok(())
         }
+        /// 一括削除
+        async fn delete_many(&self, ids: &[LabelId]) -> anyhow::Result<u64> {
+            let mut store = self.write_store_ref();
+            let deleted = ids.iter().filter(|id| store.remove(id).is_some()).count();
+            for labels in self.todo_labels.write().unwrap().values_mut() {
+                labels.retain(|attached| !ids.contains(&attached.id));
+            }
+            Ok(deleted as u64)
+        }
     }
     /// CRUD シナリオ
+    #[cfg(not(feature = "uuid-id"))]
     #[tokio::test]
     async fn crud_scenario() {
 
@@ -180,7 +875,7 @@ pub mod test_utils {
         let label_text = "test_label";
 
         // C
-        let label = repository.create(label_text.to_string()).await.expect("[create] returned Err");
+        let label = repository.create(CreateLabel::new(label_text.to_string())).await.expect("[create] returned Err");
         assert_eq!(label.name, label_text);
 
         // all
@@ -188,7 +883,33 @@ pub mod test_utils {
         let label = labels.last().unwrap();
         assert_eq!(label.name, label_text);
 
+        // u
+        let payload = UpdateLabel { name: None, color: Some("#aabbcc".to_string()) };
+        let label = repository.update(label.id, payload).await.expect("[update] returned Err");
+        assert_eq!(label.name, label_text);
+        assert_eq!(label.color, "#aabbcc");
+
+        // find_by_todo
+        assert!(repository.find_by_todo(TodoId::from(1)).await.expect("[find_by_todo] returned Err").is_empty());
+        assert!(repository.add_label(TodoId::from(1), label.id).await.expect("[add_label] returned Err"));
+        assert!(!repository.add_label(TodoId::from(1), label.id).await.expect("[add_label] returned Err"));
+        assert!(repository.add_label(TodoId::from(1), LabelId::from(label.id.0 + 1)).await.is_err());
+        let labels = repository.find_by_todo(TodoId::from(1)).await.expect("[find_by_todo] returned Err");
+        assert_eq!(labels, vec![label.clone()]);
+        assert!(repository.remove_label(TodoId::from(1), label.id).await.expect("[remove_label] returned Err"));
+        assert!(!repository.remove_label(TodoId::from(1), label.id).await.expect("[remove_label] returned Err"));
+        assert!(repository.find_by_todo(TodoId::from(1)).await.expect("[find_by_todo] returned Err").is_empty());
+
         // d
         repository.delete(label.id).await.expect("[delete] returned Err");
     }
+
+    /// 名前の重複は大文字小文字を区別しない
+    #[tokio::test]
+    async fn create_case_insensitive_duplicate() {
+        let repository = LabelRepositoryForMemory::new();
+        let label = repository.create(CreateLabel::new("Work".to_string())).await.expect("[create] returned Err");
+        let error = repository.create(CreateLabel::new("work".to_string())).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<RepositoryError>(), Some(RepositoryError::Duplicate(id)) if *id == label.id));
+    }
 }
@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// 件数と有効期限を指定したLRUキャッシュ(件数0なら何も保持しない)
+/// 満杯のときは期限切れを消し、それでも空かなければ最も長く使われていないものを消す
+/// 値を読んでから保持するまでの間に消された古い値を残さないよう、キーごとの世代を確かめてから保持する
+pub struct LruCache<K, V> {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries<K, V>>,
+}
+
+/// キャッシュの中身
+struct Entries<K, V> {
+    map: HashMap<K, Entry<V>>,
+    /// 使った順を表す通し番号
+    tick: u64,
+    /// キーごとの世代(remove で進める)
+    generations: HashMap<K, u64>,
+    /// 全体の世代(clear と、generations を捨てたときに進める)
+    epoch: u64,
+}
+
+/// 値を読み始めたときのキーの世代(insert に渡す)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Generation {
+    epoch: u64,
+    key: u64,
+}
+
+/// キャッシュした値
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+    used: u64,
+}
+
+impl<K, V> fmt::Debug for LruCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self.entries.lock().map(|entries| entries.map.len());
+        f.debug_struct("LruCache")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .field("len", &len.unwrap_or_default())
+            .finish()
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruCache<K, V> {
+    /// new
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: Mutex::new(Entries {
+                map: HashMap::new(),
+                tick: 0,
+                generations: HashMap::new(),
+                epoch: 0,
+            }),
+        }
+    }
+
+    /// 値を保持するか(件数0なら無効)
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// 期限内の値を取得する
+    pub fn get(&self, key: &K) -> Option<V> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+        match entries.map.get_mut(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                entry.used = tick;
+                Some(entry.value.clone())
+            }
+            Some(_) => {
+                entries.map.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// key の今の世代(値を読み始める前に取り、insert に渡す)
+    pub fn generation(&self, key: &K) -> Generation {
+        self.entries.lock().unwrap().generation(key)
+    }
+
+    /// 値を保持する(generation を取ってから key が消されていれば、古い値なので保持しない)
+    pub fn insert(&self, key: K, value: V, generation: Generation) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation(&key) != generation {
+            return;
+        }
+        if !entries.map.contains_key(&key) && entries.map.len() >= self.capacity {
+            let now = Instant::now();
+            entries.map.retain(|_, entry| entry.expires_at > now);
+        }
+        if !entries.map.contains_key(&key) && entries.map.len() >= self.capacity {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.map.remove(&oldest);
+            }
+        }
+        entries.tick += 1;
+        let entry = Entry {
+            value,
+            expires_at: Instant::now() + self.ttl,
+            used: entries.tick,
+        };
+        entries.map.insert(key, entry);
+    }
+
+    /// 値を消し、key の世代を進める
+    /// 世代は件数を超えたら捨てて全体の世代を進める(読み途中の値は保持されなくなるだけ)
+    pub fn remove(&self, key: &K) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.map.remove(key);
+        *entries.generations.entry(key.clone()).or_default() += 1;
+        if entries.generations.len() > self.capacity {
+            entries.generations.clear();
+            entries.epoch += 1;
+        }
+    }
+
+    /// 全て消し、全体の世代を進める
+    pub fn clear(&self) {
+        if self.is_enabled() {
+            let mut entries = self.entries.lock().unwrap();
+            entries.map.clear();
+            entries.generations.clear();
+            entries.epoch += 1;
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Entries<K, V> {
+    /// key の今の世代
+    fn generation(&self, key: &K) -> Generation {
+        Generation {
+            epoch: self.epoch,
+            key: self.generations.get(key).copied().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 満杯なら最も長く使われていないものを消す
+    #[test]
+    fn should_evict_least_recently_used() {
+        let cache = LruCache::new(2, Duration::from_secs(60));
+        cache.insert(1, "one", cache.generation(&1));
+        cache.insert(2, "two", cache.generation(&2));
+        assert_eq!(cache.get(&1), Some("one"));
+        cache.insert(3, "three", cache.generation(&3));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("one"));
+        assert_eq!(cache.get(&3), Some("three"));
+
+        cache.remove(&1);
+        assert_eq!(cache.get(&1), None);
+        cache.clear();
+        assert_eq!(cache.get(&3), None);
+    }
+
+    /// 期限を過ぎた値は返さない
+    #[test]
+    fn should_expire_after_ttl() {
+        let cache = LruCache::new(2, Duration::from_millis(10));
+        cache.insert(1, "one", cache.generation(&1));
+        assert_eq!(cache.get(&1), Some("one"));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(cache.get(&1), None);
+    }
+
+    /// 件数0なら何も保持しない
+    #[test]
+    fn should_not_hold_values_when_disabled() {
+        let cache = LruCache::new(0, Duration::from_secs(60));
+        assert!(!cache.is_enabled());
+        cache.insert(1, "one", cache.generation(&1));
+        assert_eq!(cache.get(&1), None);
+    }
+
+    /// 読み始めてから消された値は保持しない
+    #[test]
+    fn should_skip_insert_removed_while_reading() {
+        let cache = LruCache::new(2, Duration::from_secs(60));
+        let generation = cache.generation(&1);
+        cache.remove(&1);
+        cache.insert(1, "stale", generation);
+        assert_eq!(cache.get(&1), None);
+
+        let generation = cache.generation(&1);
+        cache.clear();
+        cache.insert(1, "stale", generation);
+        assert_eq!(cache.get(&1), None);
+
+        // 他のキーが消されても影響しない
+        let generation = cache.generation(&1);
+        cache.remove(&2);
+        cache.insert(1, "fresh", generation);
+        assert_eq!(cache.get(&1), Some("fresh"));
+    }
+}
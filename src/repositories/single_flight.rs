@@ -0,0 +1,156 @@
+use std::{collections::HashMap, fmt, future::Future, hash::Hash, sync::Mutex};
+use tokio::sync::broadcast;
+
+/// 同じキーの処理が実行中なら新たに実行せず、その結果を待って共有する(singleflight)
+/// 結果は実行中の呼び出しで共有するだけで残さないので、失敗しても次の呼び出しでは実行し直す
+pub struct SingleFlight<K, V> {
+    in_flight: Mutex<HashMap<K, broadcast::Sender<V>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            in_flight: Mutex::default(),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for SingleFlight<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let in_flight = self.in_flight.lock().map(|in_flight| in_flight.len());
+        f.debug_struct("SingleFlight")
+            .field("in_flight", &in_flight.unwrap_or_default())
+            .finish()
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    /// key の処理が実行中でなければ run を実行し、実行中ならその結果を待つ
+    pub async fn run<F, Fut>(&self, key: K, run: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let receiver = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(sender) => Some(sender.subscribe()),
+                None => {
+                    in_flight.insert(key.clone(), broadcast::channel(1).0);
+                    None
+                }
+            }
+        };
+        if let Some(mut receiver) = receiver {
+            // 実行していた側が取り消されて結果が届かなければ自分で実行する
+            return match receiver.recv().await {
+                Ok(value) => value,
+                Err(_) => run().await,
+            };
+        }
+
+        let mut guard = InFlightGuard {
+            flight: self,
+            key: Some(key),
+        };
+        let value = run().await;
+        if let Some(sender) = guard.finish() {
+            // 待っている呼び出しが無ければ送れないだけなので無視する
+            let _ = sender.send(value.clone());
+        }
+        value
+    }
+
+    /// 実行中の一覧から外す
+    fn remove(&self, key: &K) -> Option<broadcast::Sender<V>> {
+        self.in_flight.lock().unwrap().remove(key)
+    }
+}
+
+/// 実行した側が途中で取り消されても実行中の一覧から外す
+struct InFlightGuard<'a, K: Eq + Hash + Clone, V: Clone> {
+    flight: &'a SingleFlight<K, V>,
+    key: Option<K>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> InFlightGuard<'_, K, V> {
+    /// 実行が終わったので一覧から外し、待っている呼び出しへの送り先を返す
+    fn finish(&mut self) -> Option<broadcast::Sender<V>> {
+        self.key.take().and_then(|key| self.flight.remove(&key))
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Drop for InFlightGuard<'_, K, V> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    /// 同時に呼ばれた同じキーの処理は1回だけ実行して結果を共有する
+    #[tokio::test]
+    async fn should_coalesce_concurrent_runs() {
+        let flight = Arc::new(SingleFlight::<i32, Result<String, String>>::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let calls: Vec<_> = (0..10)
+            .map(|_| {
+                let flight = flight.clone();
+                let runs = runs.clone();
+                tokio::spawn(async move {
+                    flight
+                        .run(1, || async move {
+                            runs.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok("todo".to_string())
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for call in calls {
+            assert_eq!(call.await.unwrap(), Ok("todo".to_string()));
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    /// 失敗は残さず、次の呼び出しでは実行し直す
+    #[tokio::test]
+    async fn should_not_cache_errors() {
+        let flight = SingleFlight::<i32, Result<i32, String>>::default();
+        let result = flight.run(1, || async { Err("boom".to_string()) }).await;
+        assert_eq!(result, Err("boom".to_string()));
+        let result = flight.run(1, || async { Ok(1) }).await;
+        assert_eq!(result, Ok(1));
+    }
+
+    /// 実行していた側が取り消されたら、待っていた側が自分で実行する
+    #[tokio::test]
+    async fn should_run_again_when_leader_is_cancelled() {
+        let flight = Arc::new(SingleFlight::<i32, i32>::default());
+        let leader = {
+            let flight = flight.clone();
+            tokio::spawn(async move { flight.run(1, std::future::pending).await })
+        };
+        while flight.in_flight.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let follower = {
+            let flight = flight.clone();
+            tokio::spawn(async move { flight.run(1, || async { 2 }).await })
+        };
+        tokio::task::yield_now().await;
+        leader.abort();
+        assert_eq!(follower.await.unwrap(), 2);
+        assert!(flight.in_flight.lock().unwrap().is_empty());
+    }
+}
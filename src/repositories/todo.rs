@@ -1,215 +1,4413 @@
+use super::label::{
+    escape_like, validate_color, validate_no_control_chars, Label, LabelId, MAX_LABEL_NAME_LEN,
+};
+use super::{lru_cache::LruCache, on_connect, single_flight::SingleFlight, RepositoryError};
 use axum::async_trait;
-use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
-use validator::Validate;
-use super::RepositoryError;
+use chrono::{
+    DateTime, Duration as ChronoDuration, FixedOffset, Months, NaiveDate, NaiveDateTime, TimeZone,
+    Utc,
+};
+use chrono_tz::Tz;
+use metrics::increment_counter;
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    migrate::Migrator,
+    postgres::{PgArgumentBuffer, PgHasArrayType, PgPoolOptions, PgTypeInfo, PgValueRef},
+    Decode, Encode, Executor, FromRow, PgPool, Postgres, Transaction, Type,
+};
+#[cfg(not(feature = "uuid-id"))]
+use sqlx::{
+    sqlite::{
+        SqliteArgumentValue, SqliteConnectOptions, SqlitePoolOptions, SqliteTypeInfo,
+        SqliteValueRef,
+    },
+    Sqlite, SqlitePool,
+};
+use std::{
+    collections::HashMap,
+    env, fmt,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+/// TODOのIDの値の型(uuid-id機能を有効にするとUUIDになる)
+#[cfg(not(feature = "uuid-id"))]
+pub type RawTodoId = i32;
+/// TODOのIDの値の型(uuid-id機能を有効にするとUUIDになる)
+#[cfg(feature = "uuid-id")]
+pub type RawTodoId = uuid::Uuid;
+
+/// TODOのID(ラベルのIDと取り違えないよう型を分ける。JSON・DBでは値そのものとして扱う)
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, Type,
+)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct TodoId(RawTodoId);
+
+impl TodoId {
+    /// 新しいIDを採番する(uuid-id機能ではIDをアプリ側で採番する)
+    #[cfg(feature = "uuid-id")]
+    pub fn new_v4() -> Self {
+        Self(RawTodoId::new_v4())
+    }
+}
+
+impl From<RawTodoId> for TodoId {
+    fn from(id: RawTodoId) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for TodoId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for TodoId {
+    type Err = <RawTodoId as FromStr>::Err;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value.parse().map(Self)
+    }
+}
+
+/// id = any($1) で配列として渡せるようにする
+impl PgHasArrayType for TodoId {
+    fn array_type_info() -> PgTypeInfo {
+        <RawTodoId as PgHasArrayType>::array_type_info()
+    }
+}
+
+/// TODOを1件ずつ返すストリーム(書き出しで全件をメモリに載せないために使う)
+pub type TodoStream = Pin<Box<dyn Stream<Item = anyhow::Result<Todo>> + Send>>;
+
+/// ストリームで先読みしておく件数
+const STREAM_BUFFER: usize = 64;
+
+/// produce が送ったTODOを返すストリーム
+/// 読み出し側が遅ければ送る側も待つので、先読みは STREAM_BUFFER 件まで。読み出し側が切断すれば送信は失敗する
+fn channel_stream<F, Fut>(produce: F) -> TodoStream
+where
+    F: FnOnce(mpsc::Sender<anyhow::Result<Todo>>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    tokio::spawn(produce(sender));
+    Box::pin(ReceiverStream::new(receiver))
+}
 
 /// TODOリポジトリ
 #[async_trait]
+/// stats以外はユーザーIDで絞り込み、他ユーザーのTODOは存在しないものとして扱う
 pub trait TodoRepository: Clone + Send + Sync + 'static {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo>;
-    async fn find(&self, id: i32) -> anyhow::Result<Todo>;
-    async fn all(&self) -> anyhow::Result<Vec<Todo>>;
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    async fn create(&self, user_id: &str, payload: CreateTodo) -> anyhow::Result<Todo>;
+    async fn create_many(
+        &self,
+        user_id: &str,
+        payloads: Vec<CreateTodo>,
+    ) -> anyhow::Result<Vec<Todo>>;
+    async fn create_idempotent(
+        &self,
+        user_id: &str,
+        payload: CreateTodo,
+        key: String,
+        ttl: Duration,
+    ) -> anyhow::Result<(Todo, bool)>;
+    async fn find(&self, user_id: &str, id: TodoId) -> anyhow::Result<Todo>;
+    async fn find_opt(&self, user_id: &str, id: TodoId) -> anyhow::Result<Option<Todo>>;
+    async fn find_many(&self, user_id: &str, ids: &[TodoId]) -> anyhow::Result<Vec<Todo>>;
+    async fn all(&self, user_id: &str) -> anyhow::Result<Vec<Todo>>;
+    async fn stream(&self, user_id: &str) -> anyhow::Result<TodoStream>;
+    async fn all_with_labels(&self, user_id: &str) -> anyhow::Result<Vec<TodoWithLabels>>;
+    async fn filter(&self, user_id: &str, filter: &TodoFilter) -> anyhow::Result<Vec<Todo>>;
+    async fn count(&self, user_id: &str) -> anyhow::Result<i64>;
+    async fn page(&self, user_id: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<Todo>>;
+    async fn after(
+        &self,
+        user_id: &str,
+        cursor: Option<TodoId>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Todo>>;
+    async fn update(&self, user_id: &str, id: TodoId, payload: UpdateTodo) -> anyhow::Result<Todo>;
+    async fn append_text(&self, user_id: &str, id: TodoId, text: &str) -> anyhow::Result<Todo>;
+    async fn replace_text(
+        &self,
+        user_id: &str,
+        find: &str,
+        replace: &str,
+    ) -> anyhow::Result<ReplaceTextResult>;
+    async fn update_many(
+        &self,
+        user_id: &str,
+        updates: Vec<(TodoId, UpdateTodo)>,
+    ) -> anyhow::Result<Vec<Option<Todo>>>;
+    /// 変更したTODOを (変更前, 変更後) の組で返す
+    async fn set_completed_many(
+        &self,
+        user_id: &str,
+        ids: &[TodoId],
+        completed: bool,
+    ) -> anyhow::Result<Vec<(Todo, Todo)>>;
+    async fn delete(&self, user_id: &str, id: TodoId) -> anyhow::Result<()>;
+    async fn restore(&self, user_id: &str, id: TodoId) -> anyhow::Result<Todo>;
+    async fn move_to(&self, user_id: &str, id: TodoId, position: i32) -> anyhow::Result<Todo>;
+    async fn children(&self, user_id: &str, id: TodoId) -> anyhow::Result<Vec<Todo>>;
+    async fn history(&self, user_id: &str, id: TodoId) -> anyhow::Result<Vec<TodoChange>>;
+    async fn due_between(
+        &self,
+        user_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Todo>>;
+    async fn recent(&self, user_id: &str, limit: i64) -> anyhow::Result<Vec<Todo>>;
+    async fn stats(&self) -> anyhow::Result<TodoStats>;
+    async fn health(&self) -> anyhow::Result<Health>;
+    async fn import_dataset(
+        &self,
+        user_id: &str,
+        dataset: Dataset,
+        replace: bool,
+    ) -> anyhow::Result<ImportCounts>;
 }
 
+/// ユーザー未指定時のユーザーID(ユーザー導入前のデータもこのユーザーになる)
+pub const DEFAULT_USER: &str = "default";
+
 /// TODOデータ
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
 pub struct Todo {
-    id: i32,
+    id: TodoId,
+    user_id: String,
     text: String,
     completed: bool,
+    parent_id: Option<TodoId>,
+    due_date: Option<DateTime<Utc>>,
+    priority: Option<i32>,
+    recurrence: Option<String>,
+    /// 兄弟TODO(親が同じTODO)の中での並び順
+    position: i32,
+    updated_at: DateTime<Utc>,
+    /// タグ(ラベルより手軽な文字列の付箋。取り込み時は省略可)
+    #[serde(default)]
+    tags: Tags,
+    /// 完了した日時(未完了なら None。取り込み時は省略可)
+    #[serde(default)]
+    completed_at: Option<DateTime<Utc>>,
+}
+
+impl Todo {
+    /// ID
+    pub fn id(&self) -> TodoId {
+        self.id
+    }
+
+    /// 所有ユーザーのID
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    /// 最終更新日時(作成・更新時に記録する)
+    pub fn updated_at(&self) -> DateTime<Utc> {
+        self.updated_at
+    }
+
+    /// 完了状態を completed にしたときの完了日時
+    /// 未完了から完了になれば現在日時、完了のままなら元の日時、未完了にすれば None
+    pub fn completed_at_for(&self, completed: bool) -> Option<DateTime<Utc>> {
+        match (self.completed, completed) {
+            (_, false) => None,
+            (false, true) => Some(Utc::now()),
+            (true, true) => self.completed_at,
+        }
+    }
+
+    /// 作成した場合のTODO(IDは未採番のため既定値)
+    pub fn draft(user_id: &str, payload: CreateTodo) -> Self {
+        let completed = payload.completed.unwrap_or(false);
+        Self {
+            id: TodoId::default(),
+            user_id: user_id.to_string(),
+            text: payload.text,
+            completed,
+            parent_id: payload.parent_id,
+            due_date: payload.due_date,
+            priority: payload.priority,
+            recurrence: payload.recurrence,
+            position: 0,
+            updated_at: Utc::now(),
+            tags: Tags(payload.tags),
+            completed_at: completed.then(Utc::now),
+        }
+    }
+
+    /// 更新内容を反映したTODO
+    pub fn apply(self, payload: UpdateTodo) -> Self {
+        let completed = payload.completed.unwrap_or(self.completed);
+        Self {
+            completed_at: self.completed_at_for(completed),
+            text: payload.text.unwrap_or(self.text),
+            completed,
+            due_date: payload.due_date.unwrap_or(self.due_date),
+            priority: payload.priority.unwrap_or(self.priority),
+            recurrence: payload.recurrence.unwrap_or(self.recurrence),
+            tags: payload.tags.map(Tags).unwrap_or(self.tags),
+            updated_at: Utc::now(),
+            ..self
+        }
+    }
+
+    /// 更新前のTODOから変わった項目名(更新日時は含めない)
+    pub fn changed_fields(&self, old: &Todo) -> Vec<&'static str> {
+        [
+            ("text", self.text != old.text),
+            ("completed", self.completed != old.completed),
+            ("parent_id", self.parent_id != old.parent_id),
+            ("due_date", self.due_date != old.due_date),
+            ("priority", self.priority != old.priority),
+            ("recurrence", self.recurrence != old.recurrence),
+            ("position", self.position != old.position),
+            ("tags", self.tags != old.tags),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(field, _)| field)
+        .collect()
+    }
+
+    /// 完了した繰り返しTODOの次の回(期限を繰り返しの間隔だけ進める。期限が無ければ現在から)
+    pub fn next_occurrence(&self) -> Option<CreateTodo> {
+        if !self.completed {
+            return None;
+        }
+        let recurrence: Recurrence = self.recurrence.as_deref()?.parse().ok()?;
+        let due_date = recurrence.advance(self.due_date.unwrap_or_else(Utc::now))?;
+        Some(CreateTodo {
+            text: self.text.clone(),
+            completed: None,
+            parent_id: self.parent_id,
+            due_date: Some(due_date),
+            priority: self.priority,
+            recurrence: self.recurrence.clone(),
+            tags: self.tags.0.clone(),
+        })
+    }
+
+    /// 複製する場合の作成内容(本文に "Copy of " を付け、未完了にする)
+    /// 付けると最大文字数を超える場合は本文をそのまま使う
+    pub fn duplicate(&self) -> CreateTodo {
+        let text = format!("{}{}", DUPLICATE_PREFIX, self.text);
+        let text = if text.chars().count() <= max_text_len() {
+            text
+        } else {
+            self.text.clone()
+        };
+        CreateTodo {
+            text,
+            completed: None,
+            parent_id: self.parent_id,
+            due_date: self.due_date,
+            priority: self.priority,
+            recurrence: self.recurrence.clone(),
+            tags: self.tags.0.clone(),
+        }
+    }
+}
+
+/// 複製したTODOの本文に付ける接頭辞
+const DUPLICATE_PREFIX: &str = "Copy of ";
+
+/// TODOのタグ
+/// PostgreSQLでは text[] の列、SQLiteでは配列のJSON文字列の列に保存する
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Tags(Vec<String>);
+
+impl Type<Postgres> for Tags {
+    fn type_info() -> PgTypeInfo {
+        <Vec<String> as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <Vec<String> as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for Tags {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(Tags(<Vec<String> as Decode<Postgres>>::decode(value)?))
+    }
+}
+
+impl<'q> Encode<'q, Postgres> for Tags {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        <Vec<String> as Encode<Postgres>>::encode_by_ref(&self.0, buf)
+    }
+}
+
+#[cfg(not(feature = "uuid-id"))]
+impl Type<Sqlite> for Tags {
+    fn type_info() -> SqliteTypeInfo {
+        <String as Type<Sqlite>>::type_info()
+    }
+}
+
+#[cfg(not(feature = "uuid-id"))]
+impl<'r> Decode<'r, Sqlite> for Tags {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        let json = <&str as Decode<Sqlite>>::decode(value)?;
+        Ok(Tags(serde_json::from_str(json)?))
+    }
+}
+
+#[cfg(not(feature = "uuid-id"))]
+impl<'q> Encode<'q, Sqlite> for Tags {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> IsNull {
+        let json = serde_json::to_string(&self.0).unwrap_or_else(|_| "[]".to_string());
+        <String as Encode<Sqlite>>::encode(json, buf)
+    }
+}
+
+/// タグ1つの最大文字数
+const MAX_TAG_LEN: usize = 32;
+
+/// タグがそれぞれ空でなく最大文字数以下であること
+fn validate_tags(tags: &[String]) -> Result<(), ValidationError> {
+    if tags
+        .iter()
+        .any(|tag| tag.trim().is_empty() || tag.chars().count() > MAX_TAG_LEN)
+    {
+        let mut error = ValidationError::new("tags");
+        error.message = Some("Tags must be non-empty and at most 32 characters".into());
+        error.add_param("max".into(), &MAX_TAG_LEN);
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// 繰り返しの間隔(daily・weekly・monthly)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recurrence {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Recurrence {
+    /// 1回分進めた日時(月末日は翌月の末日に丸める)
+    fn advance(self, date: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Daily => date.checked_add_signed(ChronoDuration::days(1)),
+            Self::Weekly => date.checked_add_signed(ChronoDuration::weeks(1)),
+            Self::Monthly => date.checked_add_months(Months::new(1)),
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = ValidationError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "daily" => Ok(Self::Daily),
+            "weekly" => Ok(Self::Weekly),
+            "monthly" => Ok(Self::Monthly),
+            _ => {
+                let mut error = ValidationError::new("recurrence");
+                error.message = Some("Must be daily, weekly or monthly".into());
+                Err(error)
+            }
+        }
+    }
+}
+
+/// 繰り返しの指定が正しいこと
+fn validate_recurrence(value: &str) -> Result<(), ValidationError> {
+    value.parse::<Recurrence>().map(|_| ())
+}
+
+/// TODO本文の最大文字数(環境変数 MAX_TODO_TEXT_LEN、既定100)
+pub fn max_text_len() -> usize {
+    static MAX_TEXT_LEN: OnceLock<usize> = OnceLock::new();
+    *MAX_TEXT_LEN.get_or_init(|| {
+        env::var("MAX_TODO_TEXT_LEN")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(100)
+    })
+}
+
+/// 日付の区切りや期限の解釈に使うタイムゾーン(環境変数 APP_TZ、既定UTC)
+pub fn app_tz() -> Tz {
+    env::var("APP_TZ")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+/// 期限の日時を読んでUTCにする
+/// オフセット付きのRFC3339・ISO8601はその時刻をUTCに直す
+/// オフセットの無い日時は tz の時刻、日付だけ(YYYY-MM-DD)は tz での0時とみなす
+pub fn parse_due_date(value: &str, tz: Tz) -> Option<DateTime<Utc>> {
+    if let Ok(date_time) = value.parse::<DateTime<FixedOffset>>() {
+        return Some(date_time.with_timezone(&Utc));
+    }
+    let naive = [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })?;
+    // 夏時間の切り替えで重なる時刻は早い方にする(存在しない時刻は読めない)
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .map(|date_time| date_time.with_timezone(&Utc))
+}
+
+/// 本文が最大文字数以下であること
+fn validate_text_len(value: &str) -> Result<(), ValidationError> {
+    if value.chars().count() > max_text_len() {
+        let mut error = ValidationError::new("length");
+        error.message = Some("Over text length".into());
+        error.add_param("max".into(), &max_text_len());
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// 本文に追記した結果(最大文字数を超えるならエラー)
+fn appended_text(old: &str, text: &str) -> Result<String, RepositoryError> {
+    let appended = format!("{}{}", old, text);
+    if appended.chars().count() > max_text_len() {
+        return Err(RepositoryError::TextTooLong(max_text_len()));
+    }
+    Ok(appended)
+}
+
+/// ラベル付きのTODO
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoWithLabels {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub labels: Vec<Label>,
+}
+
+/// TODOに左結合したラベルの列(ラベルが無ければすべてNULL)
+#[derive(Debug, FromRow)]
+struct JoinedLabel {
+    label_id: Option<LabelId>,
+    label_name: Option<String>,
+    label_color: Option<String>,
+}
+
+impl JoinedLabel {
+    fn into_label(self) -> Option<Label> {
+        Some(Label {
+            id: self.label_id?,
+            name: self.label_name?,
+            color: self.label_color?,
+        })
+    }
+}
+
+/// TODOとラベルの組をTODOごとにまとめる(同じTODOの行が連続している前提)
+fn group_labels(rows: Vec<(Todo, Option<Label>)>) -> Vec<TodoWithLabels> {
+    let mut todos: Vec<TodoWithLabels> = Vec::new();
+    for (todo, label) in rows {
+        match todos.last_mut() {
+            Some(last) if last.todo.id == todo.id => last.labels.extend(label),
+            _ => todos.push(TodoWithLabels {
+                todo,
+                labels: label.into_iter().collect(),
+            }),
+        }
+    }
+    todos
+}
+
+/// TODOとラベルの対応
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoLabel {
+    pub todo_id: TodoId,
+    pub label_id: LabelId,
+}
+
+/// バックアップ用のデータ一式(TODO・ラベル・TODOとラベルの対応)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Dataset {
+    pub todos: Vec<Todo>,
+    pub labels: Vec<Label>,
+    pub associations: Vec<TodoLabel>,
+}
+
+impl Validate for Dataset {
+    /// IDの重複・含まれないTODOやラベルへの参照・本文などの値を検証する
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let invalid = |message: String| {
+            let mut error = ValidationError::new("dataset");
+            error.message = Some(message.into());
+            error
+        };
+
+        let mut todo_ids = Vec::with_capacity(self.todos.len());
+        for todo in &self.todos {
+            if todo_ids.contains(&todo.id) {
+                errors.add("todos", invalid(format!("Duplicate todo id {}", todo.id)));
+            }
+            todo_ids.push(todo.id);
+            if todo.text.is_empty() {
+                errors.add("todos", invalid(format!("Empty text in todo {}", todo.id)));
+            }
+            if let Err(mut error) = validate_text_len(&todo.text) {
+                error.message = Some(format!("Over text length in todo {}", todo.id).into());
+                errors.add("todos", error);
+            }
+            if let Some(Err(error)) = todo.recurrence.as_deref().map(validate_recurrence) {
+                errors.add("todos", error);
+            }
+            if let Err(error) = validate_tags(&todo.tags.0) {
+                errors.add("todos", error);
+            }
+        }
+        for todo in &self.todos {
+            if todo
+                .parent_id
+                .is_some_and(|parent_id| !todo_ids.contains(&parent_id))
+            {
+                errors.add(
+                    "todos",
+                    invalid(format!("Unknown parent of todo {}", todo.id)),
+                );
+            }
+        }
+
+        let mut label_ids = Vec::with_capacity(self.labels.len());
+        let mut names = Vec::with_capacity(self.labels.len());
+        for label in &self.labels {
+            if label_ids.contains(&label.id) {
+                errors.add(
+                    "labels",
+                    invalid(format!("Duplicate label id {}", label.id)),
+                );
+            }
+            label_ids.push(label.id);
+            // POST /labels と同じく、前後の空白を除いた名前を大文字小文字を区別せずに比べる
+            let name = label.name.trim();
+            let lower = name.to_lowercase();
+            if name.is_empty()
+                || name.chars().count() > MAX_LABEL_NAME_LEN
+                || names.contains(&lower)
+            {
+                errors.add(
+                    "labels",
+                    invalid(format!("Invalid label name {:?}", label.name)),
+                );
+            }
+            if let Err(error) = validate_no_control_chars(name) {
+                errors.add("labels", error);
+            }
+            if let Err(error) = validate_color(&label.color) {
+                errors.add("labels", error);
+            }
+            names.push(lower);
+        }
+
+        for association in &self.associations {
+            if !todo_ids.contains(&association.todo_id)
+                || !label_ids.contains(&association.label_id)
+            {
+                let message = format!(
+                    "Unknown todo {} or label {}",
+                    association.todo_id, association.label_id
+                );
+                errors.add("associations", invalid(message));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// 本文の一括置換の結果
+#[derive(Debug, Serialize, Default, Clone, PartialEq, Eq)]
+pub struct ReplaceTextResult {
+    /// 置換したTODOの件数
+    pub updated: u64,
+    /// 置換すると最大文字数を超えるため変更しなかったTODO
+    pub skipped: Vec<TodoId>,
+    /// 置換したTODOの変更前と変更後(イベント通知用。レスポンスには含めない)
+    #[serde(skip)]
+    pub changes: Vec<(Todo, Todo)>,
+}
+
+/// 変更の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeType {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+    Moved,
+}
+
+impl ChangeType {
+    /// 変更履歴に記録する名前
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Deleted => "deleted",
+            Self::Restored => "restored",
+            Self::Moved => "moved",
+        }
+    }
+
+    /// 変更後の本文(削除なら無し)
+    fn new_text(self, todo: &Todo) -> Option<&str> {
+        (self != Self::Deleted).then_some(todo.text.as_str())
+    }
+}
+
+/// TODOの変更履歴の1件(本文は変更前後、完了状態は変更後。削除なら削除時の値)
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, FromRow)]
+pub struct TodoChange {
+    id: i64,
+    todo_id: TodoId,
+    change_type: String,
+    old_text: Option<String>,
+    new_text: Option<String>,
+    completed: bool,
+    changed_at: DateTime<Utc>,
+}
+
+/// データ一式の取り込み結果
+#[derive(Debug, Serialize, Default, Clone, PartialEq, Eq)]
+pub struct ImportCounts {
+    /// 取り込んだTODO
+    pub todos: usize,
+    /// 新たに作成したラベル(同名のラベルがあればそれを使う)
+    pub labels: usize,
+    /// 付けたラベル
+    pub associations: u64,
+    /// 置き換えで消したTODO
+    pub replaced: u64,
+}
+
+/// TODOの件数
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, FromRow)]
+pub struct TodoStats {
+    pub total: i64,
+    pub completed: i64,
+}
+
+/// 接続状態(/health で返す)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Health {
+    /// リクエストを受けられるか(DBへの疎通が成功し、マイグレーションが適用済み)
+    pub ok: bool,
+    /// 埋め込んだマイグレーションが全て適用済みか(DBのみ)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub migrated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_connections: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_connections: Option<usize>,
+    /// TODOの件数(メモリのみ)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todos: Option<usize>,
+}
+
+/// 埋め込んだマイグレーション(ヘルスチェックで適用済みか確かめる)
+#[cfg(not(feature = "uuid-id"))]
+static MIGRATOR: Migrator = sqlx::migrate!();
+#[cfg(feature = "uuid-id")]
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations-uuid");
+#[cfg(not(feature = "uuid-id"))]
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations-sqlite");
+
+/// migrator のマイグレーションが全て applied(適用済みのバージョン)に含まれること
+fn all_migrations_applied(migrator: &Migrator, applied: &[i64]) -> bool {
+    migrator
+        .iter()
+        .all(|migration| applied.contains(&migration.version))
+}
+
+/// TODO作成用データ
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct CreateTodo {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(custom = "validate_text_len")]
+    text: String,
+    /// 完了済みとして作成する(省略時は未完了)
+    completed: Option<bool>,
+    parent_id: Option<TodoId>,
+    /// 期限(オフセットの無い日時・日付は APP_TZ の時刻として読む。parse_due_date を参照)
+    #[serde(default, deserialize_with = "deserialize_due_date")]
+    due_date: Option<DateTime<Utc>>,
+    priority: Option<i32>,
+    /// 繰り返し(完了にすると次の回を作成する)
+    #[validate(custom = "validate_recurrence")]
+    recurrence: Option<String>,
+    #[serde(default)]
+    #[validate(custom = "validate_tags")]
+    tags: Vec<String>,
+}
+
+impl CreateTodo {
+    /// 親TODOのID
+    pub fn parent_id(&self) -> Option<TodoId> {
+        self.parent_id
+    }
+}
+
+/// TODO更新用データ
+/// due_date・priority・recurrence はキー省略で変更なし、null 指定で値を消す
+/// tags は指定した配列で置き換える(キー省略で変更なし)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct UpdateTodo {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(custom = "validate_text_len")]
+    text: Option<String>,
+    /// JSONの真偽値のほか、フォーム送信向けに文字列 "true" / "false"(大文字小文字は問わない)も受け付ける
+    #[serde(default, deserialize_with = "deserialize_bool_or_string")]
+    completed: Option<bool>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable_due_date",
+        skip_serializing_if = "Option::is_none"
+    )]
+    due_date: Option<Option<DateTime<Utc>>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    priority: Option<Option<i32>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[validate(custom = "validate_recurrence")]
+    recurrence: Option<Option<String>>,
+    #[validate(custom = "validate_tags")]
+    tags: Option<Vec<String>>,
+}
+
+impl UpdateTodo {
+    /// 変更する項目が1つも無いこと
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none()
+            && self.completed.is_none()
+            && self.due_date.is_none()
+            && self.priority.is_none()
+            && self.recurrence.is_none()
+            && self.tags.is_none()
+    }
+
+    /// 繰り返しの指定だけを消す更新
+    pub fn end_recurrence() -> Self {
+        Self {
+            text: None,
+            completed: None,
+            due_date: None,
+            priority: None,
+            recurrence: Some(None),
+            tags: None,
+        }
+    }
+}
+
+/// TODO一覧の絞り込み条件(指定した条件はすべて満たすものを返す)
+#[derive(Debug, Default, Deserialize, Validate)]
+#[validate(schema(function = "validate_due_range"))]
+pub struct TodoFilter {
+    completed: Option<bool>,
+    /// 本文に含む文字列(大文字小文字は区別しない)
+    q: Option<String>,
+    /// 付いているラベル
+    label_id: Option<LabelId>,
+    /// 期限がこの日時より前
+    due_before: Option<DateTime<Utc>>,
+    /// 期限がこの日時以降
+    due_after: Option<DateTime<Utc>>,
+    /// 付いているタグ
+    tag: Option<String>,
+    /// この日時以降に完了した
+    completed_since: Option<DateTime<Utc>>,
+}
+
+impl TodoFilter {
+    /// 条件が1つも無いこと
+    pub fn is_empty(&self) -> bool {
+        self.completed.is_none()
+            && self.q.is_none()
+            && self.label_id.is_none()
+            && self.due_before.is_none()
+            && self.due_after.is_none()
+            && self.tag.is_none()
+            && self.completed_since.is_none()
+    }
+
+    /// where句(user_id と指定された条件を and でつなぐ)
+    /// placeholder はバインド位置(1始まり)からプレースホルダを作る。バインドは user_id, completed, q, label_id, due_before, due_after, tag, completed_since の順
+    /// タグの条件はDBごとに異なるので tag_condition で渡す({} がプレースホルダになる)
+    fn where_clause(&self, placeholder: impl Fn(usize) -> String, tag_condition: &str) -> String {
+        let conditions = [
+            (true, "user_id = {}"),
+            (self.completed.is_some(), "completed = {}"),
+            (self.q.is_some(), r"lower(text) like lower({}) escape '\'"),
+            (
+                self.label_id.is_some(),
+                "id in (select todo_id from todo_labels where label_id = {})",
+            ),
+            (self.due_before.is_some(), "due_date < {}"),
+            (self.due_after.is_some(), "due_date >= {}"),
+            (self.tag.is_some(), tag_condition),
+            (self.completed_since.is_some(), "completed_at >= {}"),
+        ];
+        conditions
+            .iter()
+            .filter(|(specified, _)| *specified)
+            .enumerate()
+            .map(|(i, (_, condition))| condition.replace("{}", &placeholder(i + 1)))
+            .collect::<Vec<_>>()
+            .join(" and ")
+    }
+
+    /// 本文検索のLIKEパターン
+    fn text_pattern(&self) -> Option<String> {
+        self.q.as_ref().map(|q| format!("%{}%", escape_like(q)))
+    }
+}
+
+/// 期限の範囲が逆転していないこと
+fn validate_due_range(filter: &TodoFilter) -> Result<(), ValidationError> {
+    match (filter.due_after, filter.due_before) {
+        (Some(after), Some(before)) if after > before => {
+            let mut error = ValidationError::new("due_range");
+            error.message = Some("due_after must not be later than due_before".into());
+            Err(error)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// null を許す項目を読む
+/// キーが無ければ None(変更しない)、null なら Some(None)(値を消す)、値があれば Some(Some(値))
+fn deserialize_nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// 期限を読む(読めなければ項目名を付けたエラー)
+fn deserialize_due_date<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)
+        .map_err(|e| serde::de::Error::custom(format!("invalid due_date: {}", e)))?;
+    value
+        .map(|value| {
+            parse_due_date(&value, app_tz()).ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "invalid due_date {:?}, expected RFC3339 or YYYY-MM-DD",
+                    value
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// null を許す期限を読む(deserialize_nullable と同じくキーが無ければ None)
+fn deserialize_nullable_due_date<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<DateTime<Utc>>>, D::Error> {
+    deserialize_due_date(deserializer).map(Some)
+}
+
+/// 真偽値か "true" / "false" の文字列を読む(それ以外の文字列はエラー)
+fn deserialize_bool_or_string<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<bool>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    match Option::<BoolOrString>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(BoolOrString::Bool(value)) => Ok(Some(value)),
+        Some(BoolOrString::String(value)) if value.eq_ignore_ascii_case("true") => Ok(Some(true)),
+        Some(BoolOrString::String(value)) if value.eq_ignore_ascii_case("false") => Ok(Some(false)),
+        Some(BoolOrString::String(value)) => Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(&value),
+            &"a boolean or \"true\" / \"false\"",
+        )),
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//-------------------------------------------------------------------------------------------------
+//-------------------------------------------------------------------------------------------------
+/// TODO登録SQL(兄弟TODOの末尾に並べる)
+#[cfg(not(feature = "uuid-id"))]
+const INSERT_TODO_SQL: &str = r#"
+    insert into todos
+        (user_id, text, completed, parent_id, due_date, priority, recurrence, tags, completed_at,
+        position)
+    values ($1, $2, $7, $3, $4, $5, $6, $8, case when $7 then now() end, (
+        select coalesce(max(position) + 1, 0) from todos
+        where user_id = $1 and parent_id is not distinct from $3
+    ))
+    returning *
+    "#;
+/// TODO登録SQL(uuid-id機能ではIDをアプリ側で採番する)
+#[cfg(feature = "uuid-id")]
+const INSERT_TODO_SQL: &str = r#"
+    insert into todos
+        (id, user_id, text, completed, parent_id, due_date, priority, recurrence, tags,
+        completed_at, position)
+    values ($9, $1, $2, $7, $3, $4, $5, $6, $8, case when $7 then now() end, (
+        select coalesce(max(position) + 1, 0) from todos
+        where user_id = $1 and parent_id is not distinct from $3
+    ))
+    returning *
+    "#;
+
+/// 取り込み用のTODO登録SQL(親は全件登録した後に付け直す)
+#[cfg(not(feature = "uuid-id"))]
+const IMPORT_TODO_SQL: &str = r#"
+    insert into todos
+        (user_id, text, completed, due_date, priority, recurrence, position, updated_at, tags,
+        completed_at)
+    values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+    returning id
+    "#;
+/// 取り込み用のTODO登録SQL(uuid-id機能ではIDをアプリ側で採番する)
+#[cfg(feature = "uuid-id")]
+const IMPORT_TODO_SQL: &str = r#"
+    insert into todos
+        (id, user_id, text, completed, due_date, priority, recurrence, position, updated_at, tags,
+        completed_at)
+    values ($11, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+    returning id
+    "#;
+
+/// 削除取り消しの設定
+#[derive(Debug, Clone, Copy)]
+pub struct UndoConfig {
+    /// 保持する削除済みTODOの件数
+    capacity: usize,
+    /// 取り消しできる期間
+    ttl: Duration,
+}
+
+impl UndoConfig {
+    /// 環境変数 UNDO_BUFFER_SIZE(既定20件)・UNDO_TTL_SECS(既定300秒)から読む
+    pub fn from_env() -> Self {
+        let capacity = env::var("UNDO_BUFFER_SIZE")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(20);
+        Self {
+            capacity,
+            ttl: env_secs("UNDO_TTL_SECS", 300),
+        }
+    }
+}
+
+/// find の結果のキャッシュの件数と有効期限
+/// 環境変数 FIND_CACHE_CAPACITY(既定0=キャッシュしない)・FIND_CACHE_TTL_MS(既定1000ミリ秒)で指定する
+/// このプロセスでの更新・削除ではすぐに消すが、他のプロセスからの更新は TTL の間は反映されない
+fn find_cache_from_env() -> (usize, Duration) {
+    let capacity = env::var("FIND_CACHE_CAPACITY")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0);
+    let ttl = env::var("FIND_CACHE_TTL_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1000);
+    (capacity, Duration::from_millis(ttl))
+}
+
+/// PostgreSQLリポジトリ
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForDb {
+    pool: PgPool,
+    /// find の結果のキャッシュ(TODOのIDごと)
+    cache: Arc<LruCache<TodoId, Todo>>,
+    /// 同じTODOの同時の取得を1回のクエリにまとめる
+    finds: Arc<SingleFlight<(String, TodoId), Result<Todo, RepositoryError>>>,
+    undo: UndoConfig,
+    /// ユーザーごとのTODO件数の上限
+    quota: Option<i64>,
+    /// 一覧の並び順
+    sort: TodoSort,
+}
+
+impl TodoRepositoryForDb {
+    /// new
+    pub fn new(pool: PgPool) -> Self {
+        let (capacity, ttl) = find_cache_from_env();
+        Self {
+            pool,
+            cache: Arc::new(LruCache::new(capacity, ttl)),
+            finds: Arc::default(),
+            undo: UndoConfig::from_env(),
+            quota: todo_quota(),
+            sort: TodoSort::from_env(),
+        }
+    }
+
+    /// 接続プール(他のリポジトリと共有する)
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// テーブル・列が揃っているか確認する(起動時に使う)
+    pub async fn check_schema(&self) -> anyhow::Result<()> {
+        sqlx::query(r#"select id, text, completed from todos limit 0"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+        Ok(())
+    }
+
+    /// 接続プールを構築して接続する
+    /// タイムアウトは環境変数 DB_CONNECT_TIMEOUT_SECS(既定30秒)・DB_IDLE_TIMEOUT_SECS(既定600秒)で指定する
+    /// 切れた接続はプールから取り出す前の確認で捨て、新しく接続し直す
+    pub async fn connect(
+        database_url: &str,
+        max_connections: u32,
+    ) -> Result<Self, RepositoryError> {
+        let connect_timeout = env_secs("DB_CONNECT_TIMEOUT_SECS", 30);
+        let idle_timeout = env_secs("DB_IDLE_TIMEOUT_SECS", 600);
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect_timeout(connect_timeout)
+            .idle_timeout(idle_timeout)
+            .test_before_acquire(true)
+            .after_connect(|_| {
+                Box::pin(async {
+                    on_connect();
+                    Ok(())
+                })
+            })
+            .connect(database_url)
+            .await
+            .map_err(|e| RepositoryError::Connection(e.to_string()))?;
+
+        Ok(Self::new(pool))
+    }
+
+    /// キャッシュを見ずにDBから1件取得してキャッシュする
+    /// 同じTODOの取得が実行中ならクエリを発行せずその結果を使う
+    /// 取得中に更新・削除でキャッシュが消されたら、読んだ値は古いかもしれないのでキャッシュしない
+    async fn fetch(&self, user_id: &str, id: TodoId) -> anyhow::Result<Todo> {
+        let todo = self
+            .finds
+            .run((user_id.to_string(), id), || async {
+                let generation = self.cache.generation(&id);
+                let todo =
+                    sqlx::query_as::<_, Todo>(r#"select * from todos where id=$1 and user_id=$2"#)
+                        .bind(id)
+                        .bind(user_id)
+                        .fetch_one(&self.pool)
+                        .await
+                        .map_err(|e| map_sqlx_error(e, id))?;
+                self.cache.insert(id, todo.clone(), generation);
+                Ok(todo)
+            })
+            .await?;
+
+        Ok(todo)
+    }
+}
+
+/// TODOを1件登録する(プール・トランザクションのどちらでも実行できる)
+async fn insert_todo<'e, E>(
+    executor: E,
+    user_id: &str,
+    payload: CreateTodo,
+) -> Result<Todo, sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let query = sqlx::query_as::<_, Todo>(INSERT_TODO_SQL)
+        .bind(user_id)
+        .bind(payload.text)
+        .bind(payload.parent_id)
+        .bind(payload.due_date)
+        .bind(payload.priority)
+        .bind(payload.recurrence)
+        .bind(payload.completed.unwrap_or(false))
+        .bind(Tags(payload.tags));
+    #[cfg(feature = "uuid-id")]
+    let query = query.bind(TodoId::new_v4());
+    query.fetch_one(executor).await
 }
 
-/// TODO作成用データ
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
-pub struct CreateTodo {
-    #[validate(length(min = 1, message = "Can not be empty"))]
-    #[validate(length(max = 100, message = "Over text length"))]
-    text: String,
-}
+/// 変更履歴を1件記録する(old_text は変更前の本文、todo は変更後のTODO)
+async fn record_change<'e, E>(
+    executor: E,
+    change_type: ChangeType,
+    old_text: Option<&str>,
+    todo: &Todo,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    sqlx::query(
+        r#"
+        insert into todo_history (todo_id, user_id, change_type, old_text, new_text, completed)
+        values ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(todo.id)
+    .bind(&todo.user_id)
+    .bind(change_type.as_str())
+    .bind(old_text)
+    .bind(change_type.new_text(todo))
+    .bind(todo.completed)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// 指定したIDの並びに揃える(見つからなかったIDは飛ばす)
+fn order_by_ids(todos: Vec<Todo>, ids: &[TodoId]) -> Vec<Todo> {
+    let mut todos: HashMap<TodoId, Todo> = todos.into_iter().map(|todo| (todo.id, todo)).collect();
+    ids.iter().filter_map(|id| todos.remove(id)).collect()
+}
+
+/// 変更後のTODOを変更前のTODOと組にする(変更前が無いものは飛ばす)
+fn pair_with_old(old_todos: Vec<Todo>, todos: Vec<Todo>) -> Vec<(Todo, Todo)> {
+    let mut old_todos: HashMap<TodoId, Todo> =
+        old_todos.into_iter().map(|todo| (todo.id, todo)).collect();
+    todos
+        .into_iter()
+        .filter_map(|todo| Some((old_todos.remove(&todo.id)?, todo)))
+        .collect()
+}
+
+/// ユーザーごとのTODO件数の上限(環境変数 MAX_TODOS_PER_USER、未設定なら無制限)
+fn todo_quota() -> Option<i64> {
+    env::var("MAX_TODOS_PER_USER")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|limit| *limit >= 0)
+}
+
+/// 一覧(all・filter・all_with_labels・page)の既定の並び順
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TodoSort {
+    /// 並び順(position)、同じならID順
+    #[default]
+    Position,
+    /// ID順(作成順)
+    Id,
+}
+
+impl TodoSort {
+    /// 環境変数 TODO_SORT(position・id、既定 position)から読む
+    pub fn from_env() -> Self {
+        match env::var("TODO_SORT").as_deref() {
+            Ok("id") => Self::Id,
+            _ => Self::Position,
+        }
+    }
+
+    /// order by 句の列(結合クエリでも使えるようテーブル名で修飾する)
+    fn order_by(self) -> &'static str {
+        match self {
+            Self::Position => "todos.position, todos.id",
+            Self::Id => "todos.id",
+        }
+    }
+}
+
+/// incoming 件登録してもTODO件数が上限を超えないか確認する
+/// 同じユーザーの同時登録で上限を超えないよう、トランザクション内でユーザーごとのロックを取ってから数える
+async fn check_quota(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: &str,
+    quota: Option<i64>,
+    incoming: usize,
+) -> anyhow::Result<()> {
+    let limit = match quota {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    sqlx::query(r#"select pg_advisory_xact_lock(hashtext($1))"#)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(RepositoryError::from)?;
+    let count: i64 = sqlx::query_scalar(r#"select count(*) from todos where user_id = $1"#)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(RepositoryError::from)?;
+    if count + incoming as i64 > limit {
+        return Err(RepositoryError::QuotaExceeded(limit).into());
+    }
+    Ok(())
+}
+
+/// sqlx のエラーをリポジトリのエラーにする(行が無ければ id のTODOが見つからないものとする)
+fn map_sqlx_error(e: sqlx::Error, id: TodoId) -> RepositoryError {
+    match e {
+        sqlx::Error::RowNotFound => RepositoryError::NotFound(id.into()),
+        e => RepositoryError::from(e),
+    }
+}
+
+/// 環境変数から秒数を読む(未設定・不正値は既定値)
+fn env_secs(key: &str, default: u64) -> Duration {
+    let secs = env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default);
+    Duration::from_secs(secs)
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForDb {
+    /// 作成
+    async fn create(&self, user_id: &str, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        check_quota(&mut tx, user_id, self.quota, 1).await?;
+        let todo = insert_todo(&mut tx, user_id, payload)
+            .await
+            .map_err(RepositoryError::from)?;
+        record_change(&mut tx, ChangeType::Created, None, &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todo)
+    }
+
+    /// 一括作成(1トランザクションで登録する)
+    async fn create_many(
+        &self,
+        user_id: &str,
+        payloads: Vec<CreateTodo>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        check_quota(&mut tx, user_id, self.quota, payloads.len()).await?;
+        let mut todos = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            let todo = insert_todo(&mut tx, user_id, payload)
+                .await
+                .map_err(RepositoryError::from)?;
+            record_change(&mut tx, ChangeType::Created, None, &todo)
+                .await
+                .map_err(RepositoryError::from)?;
+            todos.push(todo);
+        }
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 冪等キー付き作成(有効期間内に同じユーザー・キーがあれば作成済みのTODOを返す)
+    async fn create_idempotent(
+        &self,
+        user_id: &str,
+        payload: CreateTodo,
+        key: String,
+        ttl: Duration,
+    ) -> anyhow::Result<(Todo, bool)> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        // 同じキーでの同時の再送が両方とも未作成と判断しないよう、キーごとのロックを取ってから探す
+        sqlx::query(r#"select pg_advisory_xact_lock(hashtext($1 || ':' || $2))"#)
+            .bind(user_id)
+            .bind(&key)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        let created = sqlx::query_as::<_, Todo>(
+            r#"
+            select todos.* from idempotency_keys
+            inner join todos on todos.id = idempotency_keys.todo_id
+            where idempotency_keys.user_id = $1 and idempotency_keys.key = $2
+            and idempotency_keys.created_at > now() - make_interval(secs => $3)
+            "#,
+        )
+        .bind(user_id)
+        .bind(&key)
+        .bind(ttl.as_secs_f64())
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        if let Some(todo) = created {
+            return Ok((todo, false));
+        }
+
+        // 期限切れのキーを消してから登録する
+        sqlx::query(r#"delete from idempotency_keys where user_id = $1 and key = $2"#)
+            .bind(user_id)
+            .bind(&key)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        check_quota(&mut tx, user_id, self.quota, 1).await?;
+        let todo = insert_todo(&mut tx, user_id, payload)
+            .await
+            .map_err(RepositoryError::from)?;
+        record_change(&mut tx, ChangeType::Created, None, &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        sqlx::query(r#"insert into idempotency_keys (user_id, key, todo_id) values ($1, $2, $3)"#)
+            .bind(user_id)
+            .bind(&key)
+            .bind(todo.id)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok((todo, true))
+    }
+
+    /// idをもとに1件取得(主キーなので必ず1件のみ取れる)
+    /// キャッシュが有効ならキャッシュを先に見て、無ければDBから取得してキャッシュする
+    async fn find(&self, user_id: &str, id: TodoId) -> anyhow::Result<Todo> {
+        if let Some(todo) = self.cache.get(&id).filter(|todo| todo.user_id == user_id) {
+            increment_counter!("todo_find_cache_hits_total");
+            return Ok(todo);
+        }
+        if self.cache.is_enabled() {
+            increment_counter!("todo_find_cache_misses_total");
+        }
+        let todo = self.fetch(user_id, id).await?;
+
+        Ok(todo)
+    }
+
+    /// IDで取得(見つからなければ None。エラーはDBの失敗のみ)
+    /// find と同じくキャッシュを先に見て、無ければ実行中の取得があればその結果を使う
+    async fn find_opt(&self, user_id: &str, id: TodoId) -> anyhow::Result<Option<Todo>> {
+        match self.find(user_id, id).await {
+            Ok(todo) => Ok(Some(todo)),
+            Err(error) => match error.downcast_ref::<RepositoryError>() {
+                Some(RepositoryError::NotFound(_)) => Ok(None),
+                _ => Err(error),
+            },
+        }
+    }
+
+    /// 複数のIDで取得(指定したIDの順に並べ、見つからないIDは飛ばす)
+    async fn find_many(&self, user_id: &str, ids: &[TodoId]) -> anyhow::Result<Vec<Todo>> {
+        let todos =
+            sqlx::query_as::<_, Todo>(r#"select * from todos where user_id = $1 and id = any($2)"#)
+                .bind(user_id)
+                .bind(ids)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(RepositoryError::from)?;
+
+        Ok(order_by_ids(todos, ids))
+    }
+
+    /// 全件取得(並び順は TODO_SORT、どちらもIDで一意に決まる)
+    async fn all(&self, user_id: &str) -> anyhow::Result<Vec<Todo>> {
+        let sql = format!(
+            "select * from todos where user_id=$1 order by {}",
+            self.sort.order_by()
+        );
+        let todos = sqlx::query_as::<_, Todo>(&sql)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 全件を1件ずつ取得(id順。全件をメモリに載せずに行を読みながら返す)
+    async fn stream(&self, user_id: &str) -> anyhow::Result<TodoStream> {
+        let pool = self.pool.clone();
+        let user_id = user_id.to_string();
+        Ok(channel_stream(move |sender| async move {
+            let mut rows =
+                sqlx::query_as::<_, Todo>(r#"select * from todos where user_id=$1 order by id"#)
+                    .bind(user_id)
+                    .fetch(&pool);
+            while let Some(row) = rows.next().await {
+                if sender.send(row.map_err(Into::into)).await.is_err() {
+                    break;
+                }
+            }
+        }))
+    }
+
+    /// 条件で絞り込んで取得(指定された条件だけをwhere句に加える)
+    async fn filter(&self, user_id: &str, filter: &TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        let sql = format!(
+            "select * from todos where {} order by {}",
+            filter.where_clause(|i| format!("${}", i), "{} = any(tags)"),
+            self.sort.order_by()
+        );
+        let mut query = sqlx::query_as::<_, Todo>(&sql).bind(user_id);
+        if let Some(completed) = filter.completed {
+            query = query.bind(completed);
+        }
+        if let Some(pattern) = filter.text_pattern() {
+            query = query.bind(pattern);
+        }
+        if let Some(label_id) = filter.label_id {
+            query = query.bind(label_id);
+        }
+        if let Some(due_before) = filter.due_before {
+            query = query.bind(due_before);
+        }
+        if let Some(due_after) = filter.due_after {
+            query = query.bind(due_after);
+        }
+        if let Some(tag) = &filter.tag {
+            query = query.bind(tag);
+        }
+        if let Some(completed_since) = filter.completed_since {
+            query = query.bind(completed_since);
+        }
+        let todos = query
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// ラベル付きで全件取得
+    /// TODOごとにラベルを問い合わせるとN+1回のクエリになるので、1回の結合クエリで取得してTODOごとにまとめる
+    async fn all_with_labels(&self, user_id: &str) -> anyhow::Result<Vec<TodoWithLabels>> {
+        let sql = format!(
+            r#"
+            select todos.*, labels.id as label_id, labels.name as label_name,
+                labels.color as label_color
+            from todos
+            left join todo_labels on todo_labels.todo_id = todos.id
+            left join labels on labels.id = todo_labels.label_id
+            where todos.user_id = $1
+            order by {}, labels.id
+            "#,
+            self.sort.order_by()
+        );
+        let rows = sqlx::query(&sql)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?
+            .iter()
+            .map(|row| {
+                Ok((
+                    Todo::from_row(row)?,
+                    JoinedLabel::from_row(row)?.into_label(),
+                ))
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()
+            .map_err(RepositoryError::from)?;
+
+        Ok(group_labels(rows))
+    }
+
+    /// 件数取得
+    async fn count(&self, user_id: &str) -> anyhow::Result<i64> {
+        let count = sqlx::query_scalar(r#"select count(*) from todos where user_id=$1"#)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(count)
+    }
+
+    /// ページ単位で取得(並び順は all と同じ TODO_SORT)
+    async fn page(&self, user_id: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<Todo>> {
+        let sql = format!(
+            "select * from todos where user_id=$1 order by {} limit $2 offset $3",
+            self.sort.order_by()
+        );
+        let todos = sqlx::query_as::<_, Todo>(&sql)
+            .bind(user_id)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// カーソル(ID)より後をid順に取得(カーソル未指定なら先頭から)
+    /// カーソルはIDの大小で続きを決めるので、TODO_SORT に関わらずid順で返す
+    async fn after(
+        &self,
+        user_id: &str,
+        cursor: Option<TodoId>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let query = match cursor {
+            Some(cursor) => sqlx::query_as::<_, Todo>(
+                r#"select * from todos where user_id=$1 and id > $2 order by id limit $3"#,
+            )
+            .bind(user_id)
+            .bind(cursor)
+            .bind(limit),
+            None => sqlx::query_as::<_, Todo>(
+                r#"select * from todos where user_id=$1 order by id limit $2"#,
+            )
+            .bind(user_id)
+            .bind(limit),
+        };
+        let todos = query
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 更新
+    async fn update(&self, user_id: &str, id: TodoId, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        // キャッシュの古い値を書き戻さないようDBから読み、変更履歴と同じトランザクションで書き戻す
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let old_todo = sqlx::query_as::<_, Todo>(
+            r#"select * from todos where id=$1 and user_id=$2 for update"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?
+        .ok_or(RepositoryError::NotFound(id.into()))?;
+        let old_text = old_todo.text.clone();
+        let completed = payload.completed.unwrap_or(old_todo.completed);
+        let completed_at = old_todo.completed_at_for(completed);
+        let todo = sqlx::query_as(
+            r#"
+            update todos
+            set text = $1, completed = $2, due_date = $3, priority = $4, recurrence = $5,
+                tags = $6, completed_at = $9, updated_at = now()
+            where id=$7 and user_id=$8
+            returning *
+            "#,
+        )
+        .bind(payload.text.unwrap_or(old_todo.text))
+        .bind(completed)
+        .bind(payload.due_date.unwrap_or(old_todo.due_date))
+        .bind(payload.priority.unwrap_or(old_todo.priority))
+        .bind(payload.recurrence.unwrap_or(old_todo.recurrence))
+        .bind(payload.tags.map(Tags).unwrap_or(old_todo.tags))
+        .bind(id)
+        .bind(user_id)
+        .bind(completed_at)
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| map_sqlx_error(e, id))?;
+        record_change(&mut tx, ChangeType::Updated, Some(&old_text), &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+        self.cache.remove(&id);
+
+        Ok(todo)
+    }
+
+    /// 本文の末尾に追記(行をロックして読み、同じトランザクションで書き戻す)
+    async fn append_text(&self, user_id: &str, id: TodoId, text: &str) -> anyhow::Result<Todo> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let old_todo = sqlx::query_as::<_, Todo>(
+            r#"select * from todos where id=$1 and user_id=$2 for update"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?
+        .ok_or(RepositoryError::NotFound(id.into()))?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"update todos set text = $1, updated_at = now() where id=$2 returning *"#,
+        )
+        .bind(appended_text(&old_todo.text, text)?)
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| map_sqlx_error(e, id))?;
+        record_change(&mut tx, ChangeType::Updated, Some(&old_todo.text), &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+        self.cache.remove(&id);
+
+        Ok(todo)
+    }
+
+    /// 本文の一括置換(find を含む本文を置換する。最大文字数を超えるものは変更せずに返す)
+    /// LIKE だと % や _ をエスケープする必要があるので、replace と同じく文字列のまま探す strpos を使う
+    async fn replace_text(
+        &self,
+        user_id: &str,
+        find: &str,
+        replace: &str,
+    ) -> anyhow::Result<ReplaceTextResult> {
+        let max_len = max_text_len() as i64;
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let skipped = sqlx::query_scalar::<_, TodoId>(
+            r#"
+            select id from todos
+            where user_id = $1 and strpos(text, $2) > 0 and char_length(replace(text, $2, $3)) > $4
+            order by id
+            "#,
+        )
+        .bind(user_id)
+        .bind(find)
+        .bind(replace)
+        .bind(max_len)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        let old_todos = sqlx::query_as::<_, Todo>(
+            r#"
+            select * from todos
+            where user_id = $1 and strpos(text, $2) > 0 and char_length(replace(text, $2, $3)) <= $4
+            for update
+            "#,
+        )
+        .bind(user_id)
+        .bind(find)
+        .bind(replace)
+        .bind(max_len)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        sqlx::query(
+            r#"
+            insert into todo_history (todo_id, user_id, change_type, old_text, new_text, completed)
+            select id, user_id, 'updated', text, replace(text, $2, $3), completed from todos
+            where user_id = $1 and strpos(text, $2) > 0 and char_length(replace(text, $2, $3)) <= $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(find)
+        .bind(replace)
+        .bind(max_len)
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        let todos = sqlx::query_as::<_, Todo>(
+            r#"
+            update todos set text = replace(text, $2, $3), updated_at = now()
+            where user_id = $1 and strpos(text, $2) > 0 and char_length(replace(text, $2, $3)) <= $4
+            returning *
+            "#,
+        )
+        .bind(user_id)
+        .bind(find)
+        .bind(replace)
+        .bind(max_len)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+        self.cache.clear();
+
+        Ok(ReplaceTextResult {
+            updated: todos.len() as u64,
+            skipped,
+            changes: pair_with_old(old_todos, todos),
+        })
+    }
+
+    /// 一括更新(1トランザクションで更新する。見つからないIDは None にして残りを続ける)
+    async fn update_many(
+        &self,
+        user_id: &str,
+        updates: Vec<(TodoId, UpdateTodo)>,
+    ) -> anyhow::Result<Vec<Option<Todo>>> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let mut todos = Vec::with_capacity(updates.len());
+        for (id, payload) in updates {
+            let old_todo = sqlx::query_as::<_, Todo>(
+                r#"select * from todos where id=$1 and user_id=$2 for update"#,
+            )
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+            let old_todo = match old_todo {
+                Some(old_todo) => old_todo,
+                None => {
+                    todos.push(None);
+                    continue;
+                }
+            };
+            let old_text = old_todo.text.clone();
+            let completed = payload.completed.unwrap_or(old_todo.completed);
+            let completed_at = old_todo.completed_at_for(completed);
+            let todo = sqlx::query_as::<_, Todo>(
+                r#"
+                update todos
+                set text = $1, completed = $2, due_date = $3, priority = $4, recurrence = $5,
+                    tags = $6, completed_at = $9, updated_at = now()
+                where id=$7 and user_id=$8
+                returning *
+                "#,
+            )
+            .bind(payload.text.unwrap_or(old_todo.text))
+            .bind(completed)
+            .bind(payload.due_date.unwrap_or(old_todo.due_date))
+            .bind(payload.priority.unwrap_or(old_todo.priority))
+            .bind(payload.recurrence.unwrap_or(old_todo.recurrence))
+            .bind(payload.tags.map(Tags).unwrap_or(old_todo.tags))
+            .bind(id)
+            .bind(user_id)
+            .bind(completed_at)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(|e| map_sqlx_error(e, id))?;
+            record_change(&mut tx, ChangeType::Updated, Some(&old_text), &todo)
+                .await
+                .map_err(RepositoryError::from)?;
+            todos.push(Some(todo));
+        }
+        tx.commit().await.map_err(RepositoryError::from)?;
+        for todo in todos.iter().flatten() {
+            self.cache.remove(&todo.id);
+        }
+
+        Ok(todos)
+    }
+
+    /// 完了状態の一括更新(存在しないIDは無視し、更新した件数を返す)
+    async fn set_completed_many(
+        &self,
+        user_id: &str,
+        ids: &[TodoId],
+        completed: bool,
+    ) -> anyhow::Result<Vec<(Todo, Todo)>> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let old_todos = sqlx::query_as::<_, Todo>(
+            r#"select * from todos where user_id = $1 and id = any($2) for update"#,
+        )
+        .bind(user_id)
+        .bind(ids)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        sqlx::query(
+            r#"
+            insert into todo_history (todo_id, user_id, change_type, old_text, new_text, completed)
+            select id, user_id, 'updated', text, text, $1 from todos
+            where user_id = $2 and id = any($3)
+            "#,
+        )
+        .bind(completed)
+        .bind(user_id)
+        .bind(ids)
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        let todos = sqlx::query_as::<_, Todo>(
+            r#"
+            update todos
+            set completed = $1, updated_at = now(),
+                completed_at = case when not $1 then null when completed then completed_at else now() end
+            where user_id = $2 and id = any($3)
+            returning *
+            "#,
+        )
+        .bind(completed)
+        .bind(user_id)
+        .bind(ids)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+        for id in ids {
+            self.cache.remove(id);
+        }
+
+        Ok(pair_with_old(old_todos, todos))
+    }
+
+    /// 削除(子TODOは外部キーの ON DELETE CASCADE で削除される)
+    /// 取り消し用に削除したTODOを deleted_todos に残す。子TODOも含めてラベルの付与は消す
+    async fn delete(&self, user_id: &str, id: TodoId) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        sqlx::query(r#"delete from deleted_todos where id=$1"#)
+            .bind(id)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        sqlx::query(
+            r#"
+            with recursive tree as (
+                select id from todos where id=$1 and user_id=$2
+                union all
+                select todos.id from todos inner join tree on todos.parent_id = tree.id
+            )
+            delete from todo_labels where todo_id in (select id from tree)
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        sqlx::query(
+            r#"
+            with recursive tree as (
+                select id from todos where id=$1 and user_id=$2
+                union all
+                select todos.id from todos inner join tree on todos.parent_id = tree.id
+            )
+            insert into todo_history (todo_id, user_id, change_type, old_text, completed)
+            select id, user_id, 'deleted', text, completed from todos
+            where id in (select id from tree)
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        let deleted = sqlx::query(
+            r#"
+            with deleted as (delete from todos where id=$1 and user_id=$2 returning *)
+            insert into deleted_todos
+                (id, user_id, text, completed, parent_id, due_date, priority, recurrence, position,
+                updated_at, tags, completed_at)
+            select id, user_id, text, completed, parent_id, due_date, priority, recurrence, position,
+                updated_at, tags, completed_at
+            from deleted
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await.map_err(RepositoryError::from)?;
+        if deleted.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id.into()).into());
+        }
+        // 期限切れ・ユーザーごとの保持件数を超えたものを消す
+        sqlx::query(
+            r#"
+            delete from deleted_todos
+            where deleted_at <= now() - make_interval(secs => $1)
+            or (user_id = $3 and id not in (
+                select id from deleted_todos where user_id = $3
+                order by deleted_at desc limit $2
+            ))
+            "#,
+        )
+        .bind(self.undo.ttl.as_secs_f64())
+        .bind(self.undo.capacity as i64)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+        // 子孫TODOも消えるのでまとめて消す
+        self.cache.clear();
+
+        Ok(())
+    }
+
+    /// 削除の取り消し(元のIDで復元する。親が無くなっていれば親なしにする)
+    async fn restore(&self, user_id: &str, id: TodoId) -> anyhow::Result<Todo> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let deleted = sqlx::query_as::<_, Todo>(
+            r#"
+            delete from deleted_todos
+            where id=$1 and user_id=$2 and deleted_at > now() - make_interval(secs => $3)
+            returning *
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(self.undo.ttl.as_secs_f64())
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?
+        .ok_or(RepositoryError::NotFound(id.into()))?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+            insert into todos
+                (id, user_id, text, completed, parent_id, due_date, priority, recurrence, position,
+                updated_at, tags, completed_at)
+            values ($1, $2, $3, $4, (select id from todos where id=$5 and user_id=$2), $6, $7, $8, $9,
+                $10, $11, $12)
+            returning *
+            "#,
+        )
+        .bind(deleted.id)
+        .bind(deleted.user_id)
+        .bind(deleted.text)
+        .bind(deleted.completed)
+        .bind(deleted.parent_id)
+        .bind(deleted.due_date)
+        .bind(deleted.priority)
+        .bind(deleted.recurrence)
+        .bind(deleted.position)
+        .bind(deleted.updated_at)
+        .bind(deleted.tags)
+        .bind(deleted.completed_at)
+        .fetch_one(&mut tx)
+        .await.map_err(RepositoryError::from)?;
+        record_change(&mut tx, ChangeType::Restored, None, &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todo)
+    }
+
+    /// 兄弟TODOの中で指定の位置へ移動する(位置は0始まり、範囲外は末尾。兄弟の位置は詰め直す)
+    async fn move_to(&self, user_id: &str, id: TodoId, position: i32) -> anyhow::Result<Todo> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"select * from todos where id=$1 and user_id=$2 for update"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?
+        .ok_or(RepositoryError::NotFound(id.into()))?;
+        let mut siblings: Vec<TodoId> = sqlx::query_scalar(
+            r#"
+            select id from todos
+            where user_id=$1 and parent_id is not distinct from $2 and id<>$3
+            order by position, id
+            for update
+            "#,
+        )
+        .bind(user_id)
+        .bind(todo.parent_id)
+        .bind(id)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        let index = (position.max(0) as usize).min(siblings.len());
+        siblings.insert(index, id);
+        for (position, sibling) in siblings.into_iter().enumerate() {
+            sqlx::query(r#"update todos set position=$1 where id=$2 and position<>$1"#)
+                .bind(position as i32)
+                .bind(sibling)
+                .execute(&mut tx)
+                .await
+                .map_err(RepositoryError::from)?;
+        }
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"update todos set updated_at = now() where id=$1 returning *"#,
+        )
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| map_sqlx_error(e, id))?;
+        record_change(&mut tx, ChangeType::Moved, Some(&todo.text), &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+        // 兄弟TODOの並び順も変わるのでまとめて消す
+        self.cache.clear();
+
+        Ok(todo)
+    }
+
+    /// 子TODO取得
+    async fn children(&self, user_id: &str, id: TodoId) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, Todo>(
+            r#"select * from todos where parent_id=$1 and user_id=$2 order by position, id"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 変更履歴(古い順。履歴が無く、TODOも無ければ NotFound)
+    async fn history(&self, user_id: &str, id: TodoId) -> anyhow::Result<Vec<TodoChange>> {
+        let changes = sqlx::query_as::<_, TodoChange>(
+            r#"select * from todo_history where todo_id=$1 and user_id=$2 order by id"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+        if changes.is_empty() && self.find_opt(user_id, id).await?.is_none() {
+            return Err(RepositoryError::NotFound(id.into()).into());
+        }
+
+        Ok(changes)
+    }
+
+    /// 期限が指定範囲内の未完了TODO取得(期限順)
+    async fn due_between(
+        &self,
+        user_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, Todo>(
+            r#"
+            select * from todos
+            where user_id = $1 and completed = false and due_date >= $2 and due_date < $3
+            order by due_date, id
+            "#,
+        )
+        .bind(user_id)
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 最近更新したものから取得
+    async fn recent(&self, user_id: &str, limit: i64) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, Todo>(
+            r#"select * from todos where user_id=$1 order by updated_at desc, id desc limit $2"#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 件数取得
+    async fn stats(&self) -> anyhow::Result<TodoStats> {
+        let stats = sqlx::query_as::<_, TodoStats>(
+            r#"
+            select count(*) as total, count(*) filter (where completed) as completed
+            from todos
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+
+        Ok(stats)
+    }
+
+    /// 接続状態(select 1 の成否・所要時間と接続プールの状態)
+    async fn health(&self) -> anyhow::Result<Health> {
+        let start = Instant::now();
+        let reachable = sqlx::query("select 1").execute(&self.pool).await.is_ok();
+        let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+        // 管理テーブルが無ければ未適用とみなす
+        let applied: Vec<i64> =
+            sqlx::query_scalar(r#"select version from _sqlx_migrations where success"#)
+                .fetch_all(&self.pool)
+                .await
+                .unwrap_or_default();
+        let migrated = all_migrations_applied(&MIGRATOR, &applied);
+        let idle = self.pool.num_idle();
+        Ok(Health {
+            ok: reachable && migrated,
+            migrated: Some(migrated),
+            latency_ms: Some(latency_ms),
+            idle_connections: Some(idle),
+            active_connections: Some((self.pool.size() as usize).saturating_sub(idle)),
+            todos: None,
+        })
+    }
+
+    /// データ一式の取り込み(1トランザクションで行い、replace なら先にユーザーのTODOを消す)
+    /// TODOは新しいIDで登録し、ラベルは同名(大文字小文字を区別しない)のものがあればそれを使う
+    async fn import_dataset(
+        &self,
+        user_id: &str,
+        dataset: Dataset,
+        replace: bool,
+    ) -> anyhow::Result<ImportCounts> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let mut counts = ImportCounts::default();
+        if replace {
+            sqlx::query(
+                r#"delete from todo_labels where todo_id in (select id from todos where user_id = $1)"#,
+            )
+            .bind(user_id)
+            .execute(&mut tx)
+            .await.map_err(RepositoryError::from)?;
+            sqlx::query(
+                r#"
+                insert into todo_history (todo_id, user_id, change_type, old_text, completed)
+                select id, user_id, 'deleted', text, completed from todos where user_id = $1
+                "#,
+            )
+            .bind(user_id)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+            counts.replaced = sqlx::query(r#"delete from todos where user_id = $1"#)
+                .bind(user_id)
+                .execute(&mut tx)
+                .await
+                .map_err(RepositoryError::from)?
+                .rows_affected();
+        }
+        check_quota(&mut tx, user_id, self.quota, dataset.todos.len()).await?;
+
+        let mut label_ids = HashMap::new();
+        for label in &dataset.labels {
+            let existing: Option<LabelId> =
+                sqlx::query_scalar(r#"select id from labels where lower(name) = lower($1)"#)
+                    .bind(&label.name)
+                    .fetch_optional(&mut tx)
+                    .await
+                    .map_err(RepositoryError::from)?;
+            let id = match existing {
+                Some(id) => id,
+                None => {
+                    counts.labels += 1;
+                    sqlx::query_scalar(
+                        r#"insert into labels (name, color) values ($1, $2) returning id"#,
+                    )
+                    .bind(&label.name)
+                    .bind(&label.color)
+                    .fetch_one(&mut tx)
+                    .await
+                    .map_err(RepositoryError::from)?
+                }
+            };
+            label_ids.insert(label.id, id);
+        }
+
+        let mut todo_ids = HashMap::new();
+        for todo in &dataset.todos {
+            let query = sqlx::query_scalar(IMPORT_TODO_SQL)
+                .bind(user_id)
+                .bind(&todo.text)
+                .bind(todo.completed)
+                .bind(todo.due_date)
+                .bind(todo.priority)
+                .bind(&todo.recurrence)
+                .bind(todo.position)
+                .bind(todo.updated_at)
+                .bind(&todo.tags)
+                .bind(todo.completed_at);
+            #[cfg(feature = "uuid-id")]
+            let query = query.bind(TodoId::new_v4());
+            let id: TodoId = query
+                .fetch_one(&mut tx)
+                .await
+                .map_err(RepositoryError::from)?;
+            todo_ids.insert(todo.id, id);
+        }
+        let ids: Vec<TodoId> = todo_ids.values().copied().collect();
+        sqlx::query(
+            r#"
+            insert into todo_history (todo_id, user_id, change_type, new_text, completed)
+            select id, user_id, 'created', text, completed from todos where id = any($1)
+            "#,
+        )
+        .bind(&ids)
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        for todo in &dataset.todos {
+            if let Some(parent_id) = todo.parent_id {
+                sqlx::query(r#"update todos set parent_id = $1 where id = $2"#)
+                    .bind(todo_ids[&parent_id])
+                    .bind(todo_ids[&todo.id])
+                    .execute(&mut tx)
+                    .await
+                    .map_err(RepositoryError::from)?;
+            }
+        }
+        for association in &dataset.associations {
+            counts.associations += sqlx::query(
+                r#"insert into todo_labels (todo_id, label_id) values ($1, $2) on conflict do nothing"#,
+            )
+            .bind(todo_ids[&association.todo_id])
+            .bind(label_ids[&association.label_id])
+            .execute(&mut tx)
+            .await.map_err(RepositoryError::from)?
+            .rows_affected();
+        }
+        tx.commit().await.map_err(RepositoryError::from)?;
+        if replace {
+            self.cache.clear();
+        }
+        counts.todos = todo_ids.len();
+
+        Ok(counts)
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//-------------------------------------------------------------------------------------------------
+//-------------------------------------------------------------------------------------------------
+/// SQLiteリポジトリ(DATABASE_URL が sqlite: で始まる場合に使う。IDは整数のみ対応)
+#[cfg(not(feature = "uuid-id"))]
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForSqlite {
+    pool: SqlitePool,
+    undo: UndoConfig,
+    /// ユーザーごとのTODO件数の上限
+    quota: Option<i64>,
+    /// 一覧の並び順
+    sort: TodoSort,
+}
+
+#[cfg(not(feature = "uuid-id"))]
+impl TodoRepositoryForSqlite {
+    /// new
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            pool,
+            undo: UndoConfig::from_env(),
+            quota: todo_quota(),
+            sort: TodoSort::from_env(),
+        }
+    }
+
+    /// 接続プール(他のリポジトリと共有する)
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// テーブル・列が揃っているか確認する(起動時に使う)
+    pub async fn check_schema(&self) -> anyhow::Result<()> {
+        sqlx::query(r#"select id, text, completed from todos limit 0"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+        Ok(())
+    }
+
+    /// 接続プールを構築して接続する(DBファイルが無ければ作る)
+    pub async fn connect(
+        database_url: &str,
+        max_connections: u32,
+    ) -> Result<Self, RepositoryError> {
+        let options = database_url
+            .parse::<SqliteConnectOptions>()
+            .map_err(|e| RepositoryError::Connection(e.to_string()))?
+            .create_if_missing(true)
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_timeout(env_secs("DB_CONNECT_TIMEOUT_SECS", 30))
+            .connect_with(options)
+            .await
+            .map_err(|e| RepositoryError::Connection(e.to_string()))?;
+
+        Ok(Self::new(pool))
+    }
+}
+
+/// TODOを1件登録する(SQLite用、兄弟TODOの末尾に並べる)
+/// incoming 件登録してもTODO件数が上限を超えないか確認する(SQLiteは書き込みが直列なのでロックは取らない)
+#[cfg(not(feature = "uuid-id"))]
+async fn check_quota_sqlite(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: &str,
+    quota: Option<i64>,
+    incoming: usize,
+) -> anyhow::Result<()> {
+    let limit = match quota {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    let count: i64 = sqlx::query_scalar(r#"select count(*) from todos where user_id = ?"#)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(RepositoryError::from)?;
+    if count + incoming as i64 > limit {
+        return Err(RepositoryError::QuotaExceeded(limit).into());
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "uuid-id"))]
+async fn insert_todo_sqlite<'e, E>(
+    executor: E,
+    user_id: &str,
+    payload: CreateTodo,
+) -> Result<Todo, sqlx::Error>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_as::<_, Todo>(
+        r#"
+        insert into todos
+            (user_id, text, completed, parent_id, due_date, priority, recurrence, position,
+            updated_at, tags, completed_at)
+        values (?1, ?2, ?8, ?3, ?4, ?5, ?6, (
+            select coalesce(max(position) + 1, 0) from todos where user_id = ?1 and parent_id is ?3
+        ), ?7, ?9, case when ?8 then ?7 end)
+        returning *
+        "#,
+    )
+    .bind(user_id)
+    .bind(payload.text)
+    .bind(payload.parent_id)
+    .bind(payload.due_date)
+    .bind(payload.priority)
+    .bind(payload.recurrence)
+    .bind(Utc::now())
+    .bind(payload.completed.unwrap_or(false))
+    .bind(Tags(payload.tags))
+    .fetch_one(executor)
+    .await
+}
+
+/// 変更履歴を1件記録する(old_text は変更前の本文、todo は変更後のTODO)
+#[cfg(not(feature = "uuid-id"))]
+async fn record_change_sqlite<'e, E>(
+    executor: E,
+    change_type: ChangeType,
+    old_text: Option<&str>,
+    todo: &Todo,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query(
+        r#"
+        insert into todo_history
+            (todo_id, user_id, change_type, old_text, new_text, completed, changed_at)
+        values (?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(todo.id)
+    .bind(&todo.user_id)
+    .bind(change_type.as_str())
+    .bind(old_text)
+    .bind(change_type.new_text(todo))
+    .bind(todo.completed)
+    .bind(Utc::now())
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[cfg(not(feature = "uuid-id"))]
+#[async_trait]
+impl TodoRepository for TodoRepositoryForSqlite {
+    /// 作成
+    async fn create(&self, user_id: &str, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        check_quota_sqlite(&mut tx, user_id, self.quota, 1).await?;
+        let todo = insert_todo_sqlite(&mut tx, user_id, payload)
+            .await
+            .map_err(RepositoryError::from)?;
+        record_change_sqlite(&mut tx, ChangeType::Created, None, &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todo)
+    }
+
+    /// 一括作成(1トランザクションで登録する)
+    async fn create_many(
+        &self,
+        user_id: &str,
+        payloads: Vec<CreateTodo>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        check_quota_sqlite(&mut tx, user_id, self.quota, payloads.len()).await?;
+        let mut todos = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            let todo = insert_todo_sqlite(&mut tx, user_id, payload)
+                .await
+                .map_err(RepositoryError::from)?;
+            record_change_sqlite(&mut tx, ChangeType::Created, None, &todo)
+                .await
+                .map_err(RepositoryError::from)?;
+            todos.push(todo);
+        }
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 冪等キー付き作成(有効期間内に同じユーザー・キーがあれば作成済みのTODOを返す)
+    async fn create_idempotent(
+        &self,
+        user_id: &str,
+        payload: CreateTodo,
+        key: String,
+        ttl: Duration,
+    ) -> anyhow::Result<(Todo, bool)> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let created = sqlx::query_as::<_, Todo>(
+            r#"
+            select todos.* from idempotency_keys
+            inner join todos on todos.id = idempotency_keys.todo_id
+            where idempotency_keys.user_id = ? and idempotency_keys.key = ?
+            and idempotency_keys.created_at > strftime('%s', 'now') - ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(&key)
+        .bind(ttl.as_secs() as i64)
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        if let Some(todo) = created {
+            return Ok((todo, false));
+        }
+
+        // 期限切れのキーを消してから登録する
+        sqlx::query(r#"delete from idempotency_keys where user_id = ? and key = ?"#)
+            .bind(user_id)
+            .bind(&key)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        check_quota_sqlite(&mut tx, user_id, self.quota, 1).await?;
+        let todo = insert_todo_sqlite(&mut tx, user_id, payload)
+            .await
+            .map_err(RepositoryError::from)?;
+        record_change_sqlite(&mut tx, ChangeType::Created, None, &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        sqlx::query(r#"insert into idempotency_keys (user_id, key, todo_id) values (?, ?, ?)"#)
+            .bind(user_id)
+            .bind(&key)
+            .bind(todo.id)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok((todo, true))
+    }
+
+    /// idをもとに1件取得
+    async fn find(&self, user_id: &str, id: TodoId) -> anyhow::Result<Todo> {
+        let todo = sqlx::query_as::<_, Todo>(r#"select * from todos where id = ? and user_id = ?"#)
+            .bind(id)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| map_sqlx_error(e, id))?;
+
+        Ok(todo)
+    }
+
+    /// IDで取得(見つからなければ None。エラーはDBの失敗のみ)
+    async fn find_opt(&self, user_id: &str, id: TodoId) -> anyhow::Result<Option<Todo>> {
+        let todo = sqlx::query_as::<_, Todo>(r#"select * from todos where id = ? and user_id = ?"#)
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(todo)
+    }
+
+    /// 複数のIDで取得(指定したIDの順に並べ、見つからないIDは飛ばす)
+    async fn find_many(&self, user_id: &str, ids: &[TodoId]) -> anyhow::Result<Vec<Todo>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        // SQLiteは配列を渡せないのでIDの数だけプレースホルダを並べる
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let sql = format!(
+            "select * from todos where user_id = ? and id in ({})",
+            placeholders
+        );
+        let mut query = sqlx::query_as::<_, Todo>(&sql).bind(user_id);
+        for id in ids {
+            query = query.bind(id);
+        }
+        let todos = query
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(order_by_ids(todos, ids))
+    }
+
+    /// 全件取得(並び順は TODO_SORT、どちらもIDで一意に決まる)
+    async fn all(&self, user_id: &str) -> anyhow::Result<Vec<Todo>> {
+        let sql = format!(
+            "select * from todos where user_id = ? order by {}",
+            self.sort.order_by()
+        );
+        let todos = sqlx::query_as::<_, Todo>(&sql)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 全件を1件ずつ取得(id順。全件をメモリに載せずに行を読みながら返す)
+    async fn stream(&self, user_id: &str) -> anyhow::Result<TodoStream> {
+        let pool = self.pool.clone();
+        let user_id = user_id.to_string();
+        Ok(channel_stream(move |sender| async move {
+            let mut rows =
+                sqlx::query_as::<_, Todo>(r#"select * from todos where user_id = ? order by id"#)
+                    .bind(user_id)
+                    .fetch(&pool);
+            while let Some(row) = rows.next().await {
+                if sender.send(row.map_err(Into::into)).await.is_err() {
+                    break;
+                }
+            }
+        }))
+    }
+
+    /// 条件で絞り込んで取得(指定された条件だけをwhere句に加える)
+    async fn filter(&self, user_id: &str, filter: &TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        let sql = format!(
+            "select * from todos where {} order by {}",
+            filter.where_clause(
+                |_| "?".to_string(),
+                "exists (select 1 from json_each(todos.tags) where json_each.value = {})",
+            ),
+            self.sort.order_by()
+        );
+        let mut query = sqlx::query_as::<_, Todo>(&sql).bind(user_id);
+        if let Some(completed) = filter.completed {
+            query = query.bind(completed);
+        }
+        if let Some(pattern) = filter.text_pattern() {
+            query = query.bind(pattern);
+        }
+        if let Some(label_id) = filter.label_id {
+            query = query.bind(label_id);
+        }
+        if let Some(due_before) = filter.due_before {
+            query = query.bind(due_before);
+        }
+        if let Some(due_after) = filter.due_after {
+            query = query.bind(due_after);
+        }
+        if let Some(tag) = &filter.tag {
+            query = query.bind(tag);
+        }
+        if let Some(completed_since) = filter.completed_since {
+            query = query.bind(completed_since);
+        }
+        let todos = query
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// ラベル付きで全件取得
+    /// TODOごとにラベルを問い合わせるとN+1回のクエリになるので、1回の結合クエリで取得してTODOごとにまとめる
+    async fn all_with_labels(&self, user_id: &str) -> anyhow::Result<Vec<TodoWithLabels>> {
+        let sql = format!(
+            r#"
+            select todos.*, labels.id as label_id, labels.name as label_name,
+                labels.color as label_color
+            from todos
+            left join todo_labels on todo_labels.todo_id = todos.id
+            left join labels on labels.id = todo_labels.label_id
+            where todos.user_id = ?
+            order by {}, labels.id
+            "#,
+            self.sort.order_by()
+        );
+        let rows = sqlx::query(&sql)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?
+            .iter()
+            .map(|row| {
+                Ok((
+                    Todo::from_row(row)?,
+                    JoinedLabel::from_row(row)?.into_label(),
+                ))
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()
+            .map_err(RepositoryError::from)?;
+
+        Ok(group_labels(rows))
+    }
+
+    /// 件数取得
+    async fn count(&self, user_id: &str) -> anyhow::Result<i64> {
+        let count = sqlx::query_scalar(r#"select count(*) from todos where user_id = ?"#)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(count)
+    }
+
+    /// ページ単位で取得(並び順は all と同じ TODO_SORT)
+    async fn page(&self, user_id: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<Todo>> {
+        let sql = format!(
+            "select * from todos where user_id = ? order by {} limit ? offset ?",
+            self.sort.order_by()
+        );
+        let todos = sqlx::query_as::<_, Todo>(&sql)
+            .bind(user_id)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// カーソル(ID)より後をid順に取得(カーソル未指定なら先頭から)
+    /// カーソルはIDの大小で続きを決めるので、TODO_SORT に関わらずid順で返す
+    async fn after(
+        &self,
+        user_id: &str,
+        cursor: Option<TodoId>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let query = match cursor {
+            Some(cursor) => sqlx::query_as::<_, Todo>(
+                r#"select * from todos where user_id = ? and id > ? order by id limit ?"#,
+            )
+            .bind(user_id)
+            .bind(cursor)
+            .bind(limit),
+            None => sqlx::query_as::<_, Todo>(
+                r#"select * from todos where user_id = ? order by id limit ?"#,
+            )
+            .bind(user_id)
+            .bind(limit),
+        };
+        let todos = query
+            .fetch_all(&self.pool)
+            .await
+            .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 更新
+    async fn update(&self, user_id: &str, id: TodoId, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let old_todo =
+            sqlx::query_as::<_, Todo>(r#"select * from todos where id = ? and user_id = ?"#)
+                .bind(id)
+                .bind(user_id)
+                .fetch_optional(&mut tx)
+                .await
+                .map_err(RepositoryError::from)?
+                .ok_or(RepositoryError::NotFound(id.into()))?;
+        let old_text = old_todo.text.clone();
+        let completed = payload.completed.unwrap_or(old_todo.completed);
+        let completed_at = old_todo.completed_at_for(completed);
+        let todo = sqlx::query_as(
+            r#"
+            update todos
+            set text = ?, completed = ?, due_date = ?, priority = ?, recurrence = ?, tags = ?,
+                completed_at = ?, updated_at = ?
+            where id = ? and user_id = ?
+            returning *
+            "#,
+        )
+        .bind(payload.text.unwrap_or(old_todo.text))
+        .bind(completed)
+        .bind(payload.due_date.unwrap_or(old_todo.due_date))
+        .bind(payload.priority.unwrap_or(old_todo.priority))
+        .bind(payload.recurrence.unwrap_or(old_todo.recurrence))
+        .bind(payload.tags.map(Tags).unwrap_or(old_todo.tags))
+        .bind(completed_at)
+        .bind(Utc::now())
+        .bind(id)
+        .bind(user_id)
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| map_sqlx_error(e, id))?;
+        record_change_sqlite(&mut tx, ChangeType::Updated, Some(&old_text), &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todo)
+    }
+
+    /// 本文の末尾に追記(同じトランザクションで読んで書き戻す)
+    async fn append_text(&self, user_id: &str, id: TodoId, text: &str) -> anyhow::Result<Todo> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let old_todo =
+            sqlx::query_as::<_, Todo>(r#"select * from todos where id = ? and user_id = ?"#)
+                .bind(id)
+                .bind(user_id)
+                .fetch_optional(&mut tx)
+                .await
+                .map_err(RepositoryError::from)?
+                .ok_or(RepositoryError::NotFound(id.into()))?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"update todos set text = ?, updated_at = ? where id = ? returning *"#,
+        )
+        .bind(appended_text(&old_todo.text, text)?)
+        .bind(Utc::now())
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| map_sqlx_error(e, id))?;
+        record_change_sqlite(&mut tx, ChangeType::Updated, Some(&old_todo.text), &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todo)
+    }
+
+    /// 本文の一括置換(find を含む本文を置換する。最大文字数を超えるものは変更せずに返す)
+    /// SQLiteの LIKE は英字の大文字小文字を区別しないので、replace と同じく区別する instr で探す
+    async fn replace_text(
+        &self,
+        user_id: &str,
+        find: &str,
+        replace: &str,
+    ) -> anyhow::Result<ReplaceTextResult> {
+        let max_len = max_text_len() as i64;
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let skipped = sqlx::query_scalar::<_, TodoId>(
+            r#"
+            select id from todos
+            where user_id = ? and instr(text, ?) > 0 and length(replace(text, ?, ?)) > ?
+            order by id
+            "#,
+        )
+        .bind(user_id)
+        .bind(find)
+        .bind(find)
+        .bind(replace)
+        .bind(max_len)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        let old_todos = sqlx::query_as::<_, Todo>(
+            r#"
+            select * from todos
+            where user_id = ? and instr(text, ?) > 0 and length(replace(text, ?, ?)) <= ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(find)
+        .bind(find)
+        .bind(replace)
+        .bind(max_len)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        let now = Utc::now();
+        sqlx::query(
+            r#"
+            insert into todo_history
+                (todo_id, user_id, change_type, old_text, new_text, completed, changed_at)
+            select id, user_id, 'updated', text, replace(text, ?, ?), completed, ? from todos
+            where user_id = ? and instr(text, ?) > 0 and length(replace(text, ?, ?)) <= ?
+            "#,
+        )
+        .bind(find)
+        .bind(replace)
+        .bind(now)
+        .bind(user_id)
+        .bind(find)
+        .bind(find)
+        .bind(replace)
+        .bind(max_len)
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        let todos = sqlx::query_as::<_, Todo>(
+            r#"
+            update todos set text = replace(text, ?, ?), updated_at = ?
+            where user_id = ? and instr(text, ?) > 0 and length(replace(text, ?, ?)) <= ?
+            returning *
+            "#,
+        )
+        .bind(find)
+        .bind(replace)
+        .bind(now)
+        .bind(user_id)
+        .bind(find)
+        .bind(find)
+        .bind(replace)
+        .bind(max_len)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(ReplaceTextResult {
+            updated: todos.len() as u64,
+            skipped,
+            changes: pair_with_old(old_todos, todos),
+        })
+    }
+
+    /// 一括更新(1トランザクションで更新する。見つからないIDは None にして残りを続ける)
+    async fn update_many(
+        &self,
+        user_id: &str,
+        updates: Vec<(TodoId, UpdateTodo)>,
+    ) -> anyhow::Result<Vec<Option<Todo>>> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let mut todos = Vec::with_capacity(updates.len());
+        for (id, payload) in updates {
+            let old_todo =
+                sqlx::query_as::<_, Todo>(r#"select * from todos where id = ? and user_id = ?"#)
+                    .bind(id)
+                    .bind(user_id)
+                    .fetch_optional(&mut tx)
+                    .await
+                    .map_err(RepositoryError::from)?;
+            let old_todo = match old_todo {
+                Some(old_todo) => old_todo,
+                None => {
+                    todos.push(None);
+                    continue;
+                }
+            };
+            let old_text = old_todo.text.clone();
+            let completed = payload.completed.unwrap_or(old_todo.completed);
+            let completed_at = old_todo.completed_at_for(completed);
+            let todo = sqlx::query_as::<_, Todo>(
+                r#"
+                update todos
+                set text = ?, completed = ?, due_date = ?, priority = ?, recurrence = ?, tags = ?,
+                    completed_at = ?, updated_at = ?
+                where id = ? and user_id = ?
+                returning *
+                "#,
+            )
+            .bind(payload.text.unwrap_or(old_todo.text))
+            .bind(completed)
+            .bind(payload.due_date.unwrap_or(old_todo.due_date))
+            .bind(payload.priority.unwrap_or(old_todo.priority))
+            .bind(payload.recurrence.unwrap_or(old_todo.recurrence))
+            .bind(payload.tags.map(Tags).unwrap_or(old_todo.tags))
+            .bind(completed_at)
+            .bind(Utc::now())
+            .bind(id)
+            .bind(user_id)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(|e| map_sqlx_error(e, id))?;
+            record_change_sqlite(&mut tx, ChangeType::Updated, Some(&old_text), &todo)
+                .await
+                .map_err(RepositoryError::from)?;
+            todos.push(Some(todo));
+        }
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 完了状態の一括更新(存在しないIDは無視し、更新した件数を返す)
+    async fn set_completed_many(
+        &self,
+        user_id: &str,
+        ids: &[TodoId],
+        completed: bool,
+    ) -> anyhow::Result<Vec<(Todo, Todo)>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        // SQLiteは配列を渡せないのでIDの数だけプレースホルダを並べる
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        // 完了日時は未完了から完了にしたときだけ現在日時にし、未完了にすれば消す
+        let sql = format!(
+            "update todos set completed = ?, updated_at = ?, \
+            completed_at = case when ? then (case when completed then completed_at else ? end) end \
+            where user_id = ? and id in ({}) returning *",
+            placeholders
+        );
+        let select_sql = format!(
+            "select * from todos where user_id = ? and id in ({})",
+            placeholders
+        );
+        let history_sql = format!(
+            "insert into todo_history \
+            (todo_id, user_id, change_type, old_text, new_text, completed, changed_at) \
+            select id, user_id, 'updated', text, text, ?, ? from todos \
+            where user_id = ? and id in ({})",
+            placeholders
+        );
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let mut query = sqlx::query_as::<_, Todo>(&select_sql).bind(user_id);
+        for id in ids {
+            query = query.bind(id);
+        }
+        let old_todos = query
+            .fetch_all(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        let mut query = sqlx::query(&history_sql)
+            .bind(completed)
+            .bind(now)
+            .bind(user_id);
+        for id in ids {
+            query = query.bind(id);
+        }
+        query
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        let mut query = sqlx::query_as::<_, Todo>(&sql)
+            .bind(completed)
+            .bind(now)
+            .bind(completed)
+            .bind(now)
+            .bind(user_id);
+        for id in ids {
+            query = query.bind(id);
+        }
+        let todos = query
+            .fetch_all(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(pair_with_old(old_todos, todos))
+    }
+
+    /// 削除(子TODOは外部キーの ON DELETE CASCADE で削除される)
+    /// 取り消し用に削除したTODOを deleted_todos に残す。子TODOも含めてラベルの付与は消す
+    async fn delete(&self, user_id: &str, id: TodoId) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        sqlx::query(r#"delete from deleted_todos where id = ?"#)
+            .bind(id)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        sqlx::query(
+            r#"
+            with recursive tree as (
+                select id from todos where id = ?1 and user_id = ?2
+                union all
+                select todos.id from todos inner join tree on todos.parent_id = tree.id
+            )
+            delete from todo_labels where todo_id in (select id from tree)
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        sqlx::query(
+            r#"
+            with recursive tree as (
+                select id from todos where id = ?1 and user_id = ?2
+                union all
+                select todos.id from todos inner join tree on todos.parent_id = tree.id
+            )
+            insert into todo_history
+                (todo_id, user_id, change_type, old_text, completed, changed_at)
+            select id, user_id, 'deleted', text, completed, ?3 from todos
+            where id in (select id from tree)
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(Utc::now())
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        // SQLiteはDELETEをCTEに書けないので、先に写してから消す
+        sqlx::query(
+            r#"
+            insert into deleted_todos
+                (id, user_id, text, completed, parent_id, due_date, priority, recurrence, position,
+                updated_at, tags, completed_at)
+            select id, user_id, text, completed, parent_id, due_date, priority, recurrence, position,
+                updated_at, tags, completed_at
+            from todos
+            where id = ? and user_id = ?
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await.map_err(RepositoryError::from)?;
+        let deleted = sqlx::query(r#"delete from todos where id = ? and user_id = ?"#)
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+        if deleted.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id.into()).into());
+        }
+        // 期限切れ・ユーザーごとの保持件数を超えたものを消す
+        sqlx::query(
+            r#"
+            delete from deleted_todos
+            where deleted_at <= strftime('%s', 'now') - ?1
+            or (user_id = ?3 and id not in (
+                select id from deleted_todos where user_id = ?3
+                order by deleted_at desc limit ?2
+            ))
+            "#,
+        )
+        .bind(self.undo.ttl.as_secs() as i64)
+        .bind(self.undo.capacity as i64)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(())
+    }
+
+    /// 削除の取り消し(元のIDで復元する。親が無くなっていれば親なしにする)
+    async fn restore(&self, user_id: &str, id: TodoId) -> anyhow::Result<Todo> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let deleted = sqlx::query_as::<_, Todo>(
+            r#"
+            delete from deleted_todos
+            where id = ? and user_id = ? and deleted_at > strftime('%s', 'now') - ?
+            returning *
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(self.undo.ttl.as_secs() as i64)
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?
+        .ok_or(RepositoryError::NotFound(id.into()))?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+            insert into todos
+                (id, user_id, text, completed, parent_id, due_date, priority, recurrence, position,
+                updated_at, tags, completed_at)
+            values (?1, ?2, ?3, ?4, (select id from todos where id = ?5 and user_id = ?2), ?6, ?7, ?8,
+                ?9, ?10, ?11, ?12)
+            returning *
+            "#,
+        )
+        .bind(deleted.id)
+        .bind(deleted.user_id)
+        .bind(deleted.text)
+        .bind(deleted.completed)
+        .bind(deleted.parent_id)
+        .bind(deleted.due_date)
+        .bind(deleted.priority)
+        .bind(deleted.recurrence)
+        .bind(deleted.position)
+        .bind(deleted.updated_at)
+        .bind(deleted.tags)
+        .bind(deleted.completed_at)
+        .fetch_one(&mut tx)
+        .await.map_err(RepositoryError::from)?;
+        record_change_sqlite(&mut tx, ChangeType::Restored, None, &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todo)
+    }
+
+    /// 兄弟TODOの中で指定の位置へ移動する(位置は0始まり、範囲外は末尾。兄弟の位置は詰め直す)
+    async fn move_to(&self, user_id: &str, id: TodoId, position: i32) -> anyhow::Result<Todo> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let todo = sqlx::query_as::<_, Todo>(r#"select * from todos where id = ? and user_id = ?"#)
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?
+            .ok_or(RepositoryError::NotFound(id.into()))?;
+        let mut siblings: Vec<TodoId> = sqlx::query_scalar(
+            r#"
+            select id from todos
+            where user_id = ? and parent_id is ? and id <> ?
+            order by position, id
+            "#,
+        )
+        .bind(user_id)
+        .bind(todo.parent_id)
+        .bind(id)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(RepositoryError::from)?;
+        let index = (position.max(0) as usize).min(siblings.len());
+        siblings.insert(index, id);
+        for (position, sibling) in siblings.into_iter().enumerate() {
+            sqlx::query(r#"update todos set position = ?1 where id = ?2 and position <> ?1"#)
+                .bind(position as i32)
+                .bind(sibling)
+                .execute(&mut tx)
+                .await
+                .map_err(RepositoryError::from)?;
+        }
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"update todos set updated_at = ? where id = ? returning *"#,
+        )
+        .bind(Utc::now())
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| map_sqlx_error(e, id))?;
+        record_change_sqlite(&mut tx, ChangeType::Moved, Some(&todo.text), &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+
+        Ok(todo)
+    }
+
+    /// 子TODO取得
+    async fn children(&self, user_id: &str, id: TodoId) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, Todo>(
+            r#"select * from todos where parent_id = ? and user_id = ? order by position, id"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 変更履歴(古い順。履歴が無く、TODOも無ければ NotFound)
+    async fn history(&self, user_id: &str, id: TodoId) -> anyhow::Result<Vec<TodoChange>> {
+        let changes = sqlx::query_as::<_, TodoChange>(
+            r#"select * from todo_history where todo_id = ? and user_id = ? order by id"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+        if changes.is_empty() && self.find_opt(user_id, id).await?.is_none() {
+            return Err(RepositoryError::NotFound(id.into()).into());
+        }
+
+        Ok(changes)
+    }
+
+    /// 期限が指定範囲内の未完了TODO取得(期限順)
+    async fn due_between(
+        &self,
+        user_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, Todo>(
+            r#"
+            select * from todos
+            where user_id = ? and completed = false and due_date >= ? and due_date < ?
+            order by due_date, id
+            "#,
+        )
+        .bind(user_id)
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 最近更新したものから取得
+    async fn recent(&self, user_id: &str, limit: i64) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, Todo>(
+            r#"select * from todos where user_id = ? order by updated_at desc, id desc limit ?"#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+
+        Ok(todos)
+    }
+
+    /// 件数取得
+    async fn stats(&self) -> anyhow::Result<TodoStats> {
+        let stats = sqlx::query_as::<_, TodoStats>(
+            r#"
+            select count(*) as total, coalesce(sum(completed), 0) as completed
+            from todos
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(RepositoryError::from)?;
+
+        Ok(stats)
+    }
+
+    /// 接続状態(select 1 の成否・所要時間と接続プールの状態)
+    async fn health(&self) -> anyhow::Result<Health> {
+        let start = Instant::now();
+        let reachable = sqlx::query("select 1").execute(&self.pool).await.is_ok();
+        let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+        // 管理テーブルが無ければ未適用とみなす
+        let applied: Vec<i64> =
+            sqlx::query_scalar(r#"select version from _sqlx_migrations where success"#)
+                .fetch_all(&self.pool)
+                .await
+                .unwrap_or_default();
+        let migrated = all_migrations_applied(&SQLITE_MIGRATOR, &applied);
+        let idle = self.pool.num_idle();
+        Ok(Health {
+            ok: reachable && migrated,
+            migrated: Some(migrated),
+            latency_ms: Some(latency_ms),
+            idle_connections: Some(idle),
+            active_connections: Some((self.pool.size() as usize).saturating_sub(idle)),
+            todos: None,
+        })
+    }
+
+    /// データ一式の取り込み(1トランザクションで行い、replace なら先にユーザーのTODOを消す)
+    /// TODOは新しいIDで登録し、ラベルは同名(大文字小文字を区別しない)のものがあればそれを使う
+    async fn import_dataset(
+        &self,
+        user_id: &str,
+        dataset: Dataset,
+        replace: bool,
+    ) -> anyhow::Result<ImportCounts> {
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let mut counts = ImportCounts::default();
+        if replace {
+            sqlx::query(
+                r#"delete from todo_labels where todo_id in (select id from todos where user_id = ?)"#,
+            )
+            .bind(user_id)
+            .execute(&mut tx)
+            .await.map_err(RepositoryError::from)?;
+            sqlx::query(
+                r#"
+                insert into todo_history
+                    (todo_id, user_id, change_type, old_text, completed, changed_at)
+                select id, user_id, 'deleted', text, completed, ? from todos where user_id = ?
+                "#,
+            )
+            .bind(Utc::now())
+            .bind(user_id)
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+            counts.replaced = sqlx::query(r#"delete from todos where user_id = ?"#)
+                .bind(user_id)
+                .execute(&mut tx)
+                .await
+                .map_err(RepositoryError::from)?
+                .rows_affected();
+        }
+        check_quota_sqlite(&mut tx, user_id, self.quota, dataset.todos.len()).await?;
+
+        let mut label_ids = HashMap::new();
+        for label in &dataset.labels {
+            let existing: Option<LabelId> =
+                sqlx::query_scalar(r#"select id from labels where lower(name) = lower(?)"#)
+                    .bind(&label.name)
+                    .fetch_optional(&mut tx)
+                    .await
+                    .map_err(RepositoryError::from)?;
+            let id = match existing {
+                Some(id) => id,
+                None => {
+                    counts.labels += 1;
+                    sqlx::query_scalar(
+                        r#"insert into labels (name, color) values (?, ?) returning id"#,
+                    )
+                    .bind(&label.name)
+                    .bind(&label.color)
+                    .fetch_one(&mut tx)
+                    .await
+                    .map_err(RepositoryError::from)?
+                }
+            };
+            label_ids.insert(label.id, id);
+        }
+
+        let mut todo_ids = HashMap::new();
+        for todo in &dataset.todos {
+            let id: TodoId = sqlx::query_scalar(
+                r#"
+                insert into todos
+                    (user_id, text, completed, due_date, priority, recurrence, position,
+                    updated_at, tags, completed_at)
+                values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                returning id
+                "#,
+            )
+            .bind(user_id)
+            .bind(&todo.text)
+            .bind(todo.completed)
+            .bind(todo.due_date)
+            .bind(todo.priority)
+            .bind(&todo.recurrence)
+            .bind(todo.position)
+            .bind(todo.updated_at)
+            .bind(&todo.tags)
+            .bind(todo.completed_at)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+            sqlx::query(
+                r#"
+                insert into todo_history
+                    (todo_id, user_id, change_type, new_text, completed, changed_at)
+                values (?, ?, 'created', ?, ?, ?)
+                "#,
+            )
+            .bind(id)
+            .bind(user_id)
+            .bind(&todo.text)
+            .bind(todo.completed)
+            .bind(Utc::now())
+            .execute(&mut tx)
+            .await
+            .map_err(RepositoryError::from)?;
+            todo_ids.insert(todo.id, id);
+        }
+        for todo in &dataset.todos {
+            if let Some(parent_id) = todo.parent_id {
+                sqlx::query(r#"update todos set parent_id = ? where id = ?"#)
+                    .bind(todo_ids[&parent_id])
+                    .bind(todo_ids[&todo.id])
+                    .execute(&mut tx)
+                    .await
+                    .map_err(RepositoryError::from)?;
+            }
+        }
+        for association in &dataset.associations {
+            counts.associations += sqlx::query(
+                r#"insert into todo_labels (todo_id, label_id) values (?, ?) on conflict do nothing"#,
+            )
+            .bind(todo_ids[&association.todo_id])
+            .bind(label_ids[&association.label_id])
+            .execute(&mut tx)
+            .await.map_err(RepositoryError::from)?
+            .rows_affected();
+        }
+        tx.commit().await.map_err(RepositoryError::from)?;
+        counts.todos = todo_ids.len();
+
+        Ok(counts)
+    }
+}
+
+/// DB用リポジトリのためのテスト
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    /// シナリオテスト(DBが起動している必要がある)
+    #[tokio::test]
+    async fn todo_crud_scenario() {
+        // point 1
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL");
+        let pool = PgPool::connect(database_url)
+            .await
+            .expect(&format!("fail connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let todo_text = "[crud_scenario] text";
+
+        // create
+        let created = repository
+            .create(DEFAULT_USER, CreateTodo::new(todo_text.to_string()))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(created.text, todo_text);
+        assert!(!created.completed);
+
+        // find
+        let todo = repository
+            .find(DEFAULT_USER, created.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(created, todo);
+
+        // all
+        let todos = repository
+            .all(DEFAULT_USER)
+            .await
+            .expect("[all] returned Err");
+        let mut is_ok = false;
+        for todo in todos {
+            if created == todo {
+                is_ok = true;
+            }
+        }
+        assert!(is_ok);
+
+        // update
+        let updated_text = "[crud_scenario] updated text";
+        let todo = repository
+            .update(
+                DEFAULT_USER,
+                todo.id,
+                UpdateTodo {
+                    text: Some(updated_text.to_string()),
+                    completed: Some(true),
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    tags: None,
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(updated_text, todo.text);
+        assert!(todo.completed);
+
+        // delete
+        let _ = repository
+            .delete(DEFAULT_USER, todo.id)
+            .await
+            .expect("[delete] returned Err");
+        // deleteで消えていること
+        let res = repository.find(DEFAULT_USER, created.id).await;
+        assert!(res.is_err());
+
+        let todo_rows = sqlx::query(
+            r#"
+               select * from todos where id=$1
+            "#,
+        )
+        .bind(todo.id)
+        .fetch_all(&pool)
+        .await
+        .expect("[delete] todo_labels fetch error");
+        assert_eq!(todo_rows.len(), 0);
+    }
+
+    /// 置換する文字列の % や _ はワイルドカードにしない(DBが起動している必要がある)
+    #[tokio::test]
+    async fn replace_text_with_wildcards() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL");
+        let pool = PgPool::connect(database_url)
+            .await
+            .expect(&format!("fail connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let user_id = "replace_text_with_wildcards";
+        sqlx::query(r#"delete from todos where user_id = $1"#)
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("fail clean up todos");
+
+        let matched = repository
+            .create(user_id, CreateTodo::new("100% done".to_string()))
+            .await
+            .expect("[create] returned Err");
+        let unmatched = repository
+            .create(user_id, CreateTodo::new("1000 done".to_string()))
+            .await
+            .expect("[create] returned Err");
+        let result = repository
+            .replace_text(user_id, "100%", "all")
+            .await
+            .expect("[replace_text] returned Err");
+        assert_eq!(result.updated, 1);
+        let todo = repository.find(user_id, matched.id).await.unwrap();
+        assert_eq!(todo.text, "all done");
+        assert_eq!(result.changes, vec![(matched.clone(), todo)]);
+        let todo = repository.find(user_id, unmatched.id).await.unwrap();
+        assert_eq!(todo.text, "1000 done");
+    }
+
+    /// キャッシュした find の結果は更新・削除で消える(DBが起動している必要がある)
+    #[tokio::test]
+    async fn find_cache_invalidation() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL");
+        let pool = PgPool::connect(database_url)
+            .await
+            .expect(&format!("fail connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb {
+            cache: Arc::new(LruCache::new(10, Duration::from_secs(60))),
+            ..TodoRepositoryForDb::new(pool.clone())
+        };
+
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("[find_cache] text".to_string()),
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(
+            repository.find(DEFAULT_USER, created.id).await.unwrap(),
+            created
+        );
+        assert!(repository.find("other", created.id).await.is_err());
+
+        let payload = UpdateTodo {
+            text: Some("[find_cache] updated".to_string()),
+            completed: None,
+            due_date: None,
+            priority: None,
+            recurrence: None,
+            tags: None,
+        };
+        let updated = repository
+            .update(DEFAULT_USER, created.id, payload)
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(
+            repository.find(DEFAULT_USER, created.id).await.unwrap(),
+            updated
+        );
+
+        repository
+            .delete(DEFAULT_USER, created.id)
+            .await
+            .expect("[delete] returned Err");
+        assert!(repository.find(DEFAULT_USER, created.id).await.is_err());
+    }
+
+    /// find_opt もキャッシュを使い、更新・削除でキャッシュが消える(DBが起動している必要がある)
+    #[tokio::test]
+    async fn find_opt_uses_cache() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL");
+        let pool = PgPool::connect(database_url)
+            .await
+            .expect(&format!("fail connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb {
+            cache: Arc::new(LruCache::new(10, Duration::from_secs(60))),
+            ..TodoRepositoryForDb::new(pool.clone())
+        };
+
+        let created = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo::new("[find_opt_cache] text".to_string()),
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(
+            repository.find_opt(DEFAULT_USER, created.id).await.unwrap(),
+            Some(created.clone())
+        );
+
+        // リポジトリを通さずに書き換えても、2回目はキャッシュから返る
+        sqlx::query(r#"update todos set text = '[find_opt_cache] direct' where id = $1"#)
+            .bind(created.id)
+            .execute(&pool)
+            .await
+            .expect("fail update todo");
+        assert_eq!(
+            repository.find_opt(DEFAULT_USER, created.id).await.unwrap(),
+            Some(created.clone())
+        );
+        assert_eq!(
+            repository.find_opt("other", created.id).await.unwrap(),
+            None
+        );
+
+        let payload = UpdateTodo {
+            text: Some("[find_opt_cache] updated".to_string()),
+            completed: None,
+            due_date: None,
+            priority: None,
+            recurrence: None,
+            tags: None,
+        };
+        let updated = repository
+            .update(DEFAULT_USER, created.id, payload)
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(
+            repository.find_opt(DEFAULT_USER, created.id).await.unwrap(),
+            Some(updated)
+        );
+
+        repository
+            .delete(DEFAULT_USER, created.id)
+            .await
+            .expect("[delete] returned Err");
+        assert_eq!(
+            repository.find_opt(DEFAULT_USER, created.id).await.unwrap(),
+            None
+        );
+    }
+
+    /// 一括作成・取り込みでも、登録後の件数が上限を超えるなら何も登録しない(DBが起動している必要がある)
+    #[tokio::test]
+    async fn quota_covers_bulk_creation() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL");
+        let pool = PgPool::connect(database_url)
+            .await
+            .expect(&format!("fail connect database, url is [{}]", database_url));
+        let user_id = "quota_covers_bulk_creation";
+        sqlx::query(r#"delete from todos where user_id = $1"#)
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("fail delete todos");
+        let payloads = || {
+            vec![
+                CreateTodo::new("first".to_string()),
+                CreateTodo::new("second".to_string()),
+            ]
+        };
+        let created = TodoRepositoryForDb::new(pool.clone())
+            .create_many(user_id, payloads())
+            .await
+            .expect("[create_many] returned Err");
+        let repository = TodoRepositoryForDb {
+            quota: Some(3),
+            ..TodoRepositoryForDb::new(pool.clone())
+        };
+
+        let error = repository
+            .create_many(user_id, payloads())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::QuotaExceeded(3))
+        ));
+        let dataset = Dataset {
+            todos: created,
+            labels: vec![],
+            associations: vec![],
+        };
+        let error = repository
+            .import_dataset(user_id, dataset.clone(), false)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::QuotaExceeded(3))
+        ));
+        assert_eq!(repository.count(user_id).await.unwrap(), 2);
+
+        // 置き換えなら今あるTODOは数えない
+        let counts = repository
+            .import_dataset(user_id, dataset, true)
+            .await
+            .expect("[import_dataset] returned Err");
+        assert_eq!(counts.replaced, 2);
+        assert_eq!(repository.count(user_id).await.unwrap(), 2);
+    }
+
+    /// 同じ冪等キーで同時に作成しても1件だけ作成し、どちらにも同じTODOを返す(DBが起動している必要がある)
+    #[tokio::test]
+    async fn create_idempotent_concurrently() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL");
+        let pool = PgPool::connect(database_url)
+            .await
+            .expect(&format!("fail connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let user_id = "create_idempotent_concurrently";
+        sqlx::query(r#"delete from idempotency_keys where user_id = $1"#)
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("fail delete idempotency keys");
+
+        let create = || {
+            repository.create_idempotent(
+                user_id,
+                CreateTodo::new("[idempotent] text".to_string()),
+                "retry".to_string(),
+                Duration::from_secs(60),
+            )
+        };
+        let (first, second) = tokio::join!(create(), create());
+        let (first, first_created) = first.expect("[create_idempotent] returned Err");
+        let (second, second_created) = second.expect("[create_idempotent] returned Err");
+        assert_eq!(first.id, second.id);
+        assert!(first_created != second_created);
+    }
 
-/// TODO更新用データ
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
-pub struct UpdateTodo {
-    #[validate(length(min = 1, message = "Can not be empty"))]
-    #[validate(length(max = 100, message = "Over text length"))]
-    text: Option<String>,
-    completed: Option<bool>,
-}
+    /// ページ・ラベル付き一覧も TODO_SORT の順、カーソルはid順で返す(DBが起動している必要がある)
+    #[tokio::test]
+    async fn page_follows_sort() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL");
+        let pool = PgPool::connect(database_url)
+            .await
+            .expect(&format!("fail connect database, url is [{}]", database_url));
+        let user_id = "page_follows_sort";
+        sqlx::query(r#"delete from todos where user_id = $1"#)
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("fail delete todos");
+        let repository = TodoRepositoryForDb {
+            sort: TodoSort::Position,
+            ..TodoRepositoryForDb::new(pool.clone())
+        };
+        let first = repository
+            .create(user_id, CreateTodo::new("first".to_string()))
+            .await
+            .expect("[create] returned Err");
+        let second = repository
+            .create(user_id, CreateTodo::new("second".to_string()))
+            .await
+            .expect("[create] returned Err");
+        let second = repository
+            .move_to(user_id, second.id, 0)
+            .await
+            .expect("[move_to] returned Err");
 
-//-------------------------------------------------------------------------------------------------
-//-------------------------------------------------------------------------------------------------
-//-------------------------------------------------------------------------------------------------
-/// PostgreSQLリポジトリ
-#[derive(Debug, Clone)]
-pub struct TodoRepositoryForDb {
-    pool: PgPool,
+        let page = repository.page(user_id, 1, 0).await.unwrap();
+        assert_eq!(page[0].id, second.id);
+        let todos = repository.all_with_labels(user_id).await.unwrap();
+        assert_eq!(
+            todos.iter().map(|todo| todo.todo.id).collect::<Vec<_>>(),
+            vec![second.id, first.id]
+        );
+        let todos = repository.after(user_id, None, 1).await.unwrap();
+        assert_eq!(todos[0].id, first.id);
+    }
 }
 
-impl TodoRepositoryForDb {
-    /// new
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool: pool }
+/// SQLite用リポジトリのためのテスト(インメモリDBを使う)
+#[cfg(test)]
+#[cfg(not(feature = "uuid-id"))]
+mod sqlite_test {
+    use super::*;
+
+    #[tokio::test]
+    async fn check_schema_after_migrations() {
+        let repository = TodoRepositoryForSqlite::connect("sqlite::memory:", 1)
+            .await
+            .expect("fail connect sqlite");
+        assert!(repository.check_schema().await.is_err());
+        let health = repository.health().await.expect("[health] returned Err");
+        assert!(!health.ok);
+        assert_eq!(health.migrated, Some(false));
+        sqlx::migrate!("./migrations-sqlite")
+            .run(repository.pool())
+            .await
+            .expect("fail run migrations");
+        repository
+            .check_schema()
+            .await
+            .expect("[check_schema] returned Err");
+        let health = repository.health().await.expect("[health] returned Err");
+        assert!(health.ok);
+        assert_eq!(health.migrated, Some(true));
+        assert_eq!(health.todos, None);
     }
-}
 
-#[async_trait]
-impl TodoRepository for TodoRepositoryForDb {
-    /// 作成
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
-        let todo = sqlx::query_as::<_, Todo>(
-            r#"
-            insert into todos (text, completed)
-            values ($1, false)
-            returning *
-            "#,
-        )
-        .bind(payload.text.clone())
-        .fetch_one(&self.pool)
-        .await?;
+    #[tokio::test]
+    async fn create_completed() {
+        let repository = TodoRepositoryForSqlite::connect("sqlite::memory:", 1)
+            .await
+            .expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite")
+            .run(repository.pool())
+            .await
+            .expect("fail run migrations");
 
-        Ok(todo)
+        let done = CreateTodo {
+            completed: Some(true),
+            ..CreateTodo::new("done".to_string())
+        };
+        let todo = repository.create(DEFAULT_USER, done).await.unwrap();
+        assert!(todo.completed);
+        let todo = repository
+            .create(DEFAULT_USER, CreateTodo::new("open".to_string()))
+            .await
+            .unwrap();
+        assert!(!todo.completed);
     }
 
-    /// idをもとに1件取得(主キーなので必ず1件のみ取れる)
-    async fn find(&self, id: i32) -> anyhow::Result<Todo> {
-        let todo = sqlx::query_as::<_, Todo>(r#"select * from todos where id=$1"#)
-            .bind(id)
-            .fetch_one(&self.pool)
+    #[tokio::test]
+    async fn import_dataset_replace() {
+        let repository = TodoRepositoryForSqlite::connect("sqlite::memory:", 1)
             .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-                _ => RepositoryError::Unexpected(e.to_string()),
-            })?;
+            .expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite")
+            .run(repository.pool())
+            .await
+            .expect("fail run migrations");
+        repository
+            .create(DEFAULT_USER, CreateTodo::new("old".to_string()))
+            .await
+            .unwrap();
+        sqlx::query(r#"insert into labels (name) values ('Work')"#)
+            .execute(repository.pool())
+            .await
+            .unwrap();
 
-        Ok(todo)
+        let parent = Todo::new(10, "parent".to_string());
+        let child = Todo {
+            parent_id: Some(TodoId::from(10)),
+            ..Todo::new(11, "child".to_string())
+        };
+        let label = |id: i32, name: &str| Label {
+            id: LabelId::from(id),
+            name: name.to_string(),
+            color: "#808080".to_string(),
+        };
+        let dataset = Dataset {
+            todos: vec![parent, child],
+            labels: vec![label(7, "work"), label(8, "home")],
+            associations: vec![
+                TodoLabel {
+                    todo_id: TodoId::from(11),
+                    label_id: LabelId::from(7),
+                },
+                TodoLabel {
+                    todo_id: TodoId::from(11),
+                    label_id: LabelId::from(8),
+                },
+            ],
+        };
+        dataset.validate().expect("[validate] returned Err");
+        let counts = repository
+            .import_dataset(DEFAULT_USER, dataset, true)
+            .await
+            .expect("[import_dataset] returned Err");
+        assert_eq!(
+            counts,
+            ImportCounts {
+                todos: 2,
+                labels: 1,
+                associations: 2,
+                replaced: 1,
+            }
+        );
+
+        let todos = repository.all_with_labels(DEFAULT_USER).await.unwrap();
+        let rows: Vec<_> = todos
+            .iter()
+            .map(|todo| {
+                let labels: Vec<_> = todo
+                    .labels
+                    .iter()
+                    .map(|label| label.name.as_str())
+                    .collect();
+                (todo.todo.text.as_str(), todo.todo.parent_id, labels)
+            })
+            .collect();
+        let parent_id = todos[0].todo.id;
+        assert_eq!(
+            rows,
+            vec![
+                ("parent", None, vec![]),
+                ("child", Some(parent_id), vec!["Work", "home"]),
+            ]
+        );
     }
 
-    /// 全件取得
-    async fn all(&self) -> anyhow::Result<Vec<Todo>> {
-        let todos = sqlx::query_as::<_, Todo>(r#"select * from todos"#)
-            .fetch_all(&self.pool)
-            .await?;
+    #[tokio::test]
+    async fn find_opt_missing() {
+        let repository = TodoRepositoryForSqlite::connect("sqlite::memory:", 1)
+            .await
+            .expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite")
+            .run(repository.pool())
+            .await
+            .expect("fail run migrations");
 
-        Ok(todos)
+        let todo = repository
+            .create(DEFAULT_USER, CreateTodo::new("found".to_string()))
+            .await
+            .unwrap();
+        let found = repository.find_opt(DEFAULT_USER, todo.id).await.unwrap();
+        assert_eq!(found, Some(todo.clone()));
+        assert_eq!(repository.find_opt("other", todo.id).await.unwrap(), None);
+        assert_eq!(
+            repository
+                .find_opt(DEFAULT_USER, TodoId::from(9))
+                .await
+                .unwrap(),
+            None
+        );
     }
 
-    /// 更新
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
-        let old_todo = self.find(id).await?;
-        let todo = sqlx::query_as(
-            r#"
-            update todos set text = $1, completed = $2
-            where id=$3
-            returning *
-            "#,
-        )
-        .bind(payload.text.unwrap_or(old_todo.text))
-        .bind(payload.completed.unwrap_or(old_todo.completed))
-        .bind(id)
-        .fetch_one(&self.pool)
-        .await?;
+    #[tokio::test]
+    async fn append_text() {
+        let repository = TodoRepositoryForSqlite::connect("sqlite::memory:", 1)
+            .await
+            .expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite")
+            .run(repository.pool())
+            .await
+            .expect("fail run migrations");
 
-        Ok(todo)
+        let todo = repository
+            .create(DEFAULT_USER, CreateTodo::new("note".to_string()))
+            .await
+            .unwrap();
+        let appended = repository
+            .append_text(DEFAULT_USER, todo.id, " - follow up")
+            .await
+            .unwrap();
+        assert_eq!(appended.text, "note - follow up");
+        assert!(appended.updated_at >= todo.updated_at);
+
+        // 最大文字数を超えるなら書き換えない
+        let error = repository
+            .append_text(DEFAULT_USER, todo.id, &"a".repeat(max_text_len()))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::TextTooLong(_))
+        ));
+        let found = repository.find(DEFAULT_USER, todo.id).await.unwrap();
+        assert_eq!(found, appended);
+        assert!(repository.append_text("other", todo.id, "!").await.is_err());
     }
 
-    /// 削除
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
-        sqlx::query(r#"delete from todos where id=$1"#)
-            .bind(id)
-            .execute(&self.pool)
+    #[tokio::test]
+    async fn history() {
+        let repository = TodoRepositoryForSqlite::connect("sqlite::memory:", 1)
             .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-                _ => RepositoryError::Unexpected(e.to_string()),
-            })?;
+            .expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite")
+            .run(repository.pool())
+            .await
+            .expect("fail run migrations");
 
-        Ok(())
+        let todo = repository
+            .create(DEFAULT_USER, CreateTodo::new("draft".to_string()))
+            .await
+            .unwrap();
+        let child = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo {
+                    parent_id: Some(todo.id),
+                    ..CreateTodo::new("child".to_string())
+                },
+            )
+            .await
+            .unwrap();
+        repository
+            .update(
+                DEFAULT_USER,
+                todo.id,
+                UpdateTodo {
+                    text: Some("final".to_string()),
+                    completed: None,
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    tags: None,
+                },
+            )
+            .await
+            .unwrap();
+        repository
+            .set_completed_many(DEFAULT_USER, &[todo.id], true)
+            .await
+            .unwrap();
+        // 失敗した変更は記録しない
+        assert!(repository
+            .append_text(DEFAULT_USER, todo.id, &"a".repeat(max_text_len()))
+            .await
+            .is_err());
+        repository.delete(DEFAULT_USER, todo.id).await.unwrap();
+        repository.restore(DEFAULT_USER, todo.id).await.unwrap();
+
+        let changes = repository.history(DEFAULT_USER, todo.id).await.unwrap();
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|change| {
+                (
+                    change.change_type.as_str(),
+                    change.old_text.as_deref(),
+                    change.new_text.as_deref(),
+                    change.completed,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("created", None, Some("draft"), false),
+                ("updated", Some("draft"), Some("final"), false),
+                ("updated", Some("final"), Some("final"), true),
+                ("deleted", Some("final"), None, true),
+                ("restored", None, Some("final"), true),
+            ]
+        );
+        // 子TODOは親と一緒に削除されたことが残る
+        let changes = repository.history(DEFAULT_USER, child.id).await.unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].change_type, "deleted");
+
+        // 存在したことのないTODO・他のユーザーのTODOは NotFound
+        for (user_id, id) in [(DEFAULT_USER, TodoId::from(100)), ("other", todo.id)] {
+            let error = repository.history(user_id, id).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::NotFound(_))
+            ));
+        }
     }
-}
 
-/// DB用リポジトリのためのテスト
-#[cfg(test)]
-#[cfg(feature = "database-test")]
-mod test {
-    use super::*;
-    use dotenv::dotenv;
-    use sqlx::PgPool;
-    use std::env;
+    #[tokio::test]
+    async fn replace_text() {
+        let repository = TodoRepositoryForSqlite::connect("sqlite::memory:", 1)
+            .await
+            .expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite")
+            .run(repository.pool())
+            .await
+            .expect("fail run migrations");
+
+        let long = "foo".to_string() + &"a".repeat(max_text_len() - 3);
+        for text in ["foo and foo", "FOO", "100%_foo", long.as_str()] {
+            repository
+                .create(DEFAULT_USER, CreateTodo::new(text.to_string()))
+                .await
+                .unwrap();
+        }
+        repository
+            .create("other", CreateTodo::new("foo".to_string()))
+            .await
+            .unwrap();
+
+        let result = repository
+            .replace_text(DEFAULT_USER, "foo", "bazz")
+            .await
+            .unwrap();
+        assert_eq!(result.updated, 2);
+        assert_eq!(result.skipped, vec![TodoId::from(4)]);
+        let mut changes: Vec<_> = result
+            .changes
+            .iter()
+            .map(|(old, todo)| (old.text.as_str(), todo.text.as_str()))
+            .collect();
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![("100%_foo", "100%_bazz"), ("foo and foo", "bazz and bazz")]
+        );
+        let texts: Vec<_> = repository
+            .all(DEFAULT_USER)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|todo| todo.text)
+            .collect();
+        assert!(texts.contains(&"bazz and bazz".to_string()));
+        assert!(texts.contains(&"FOO".to_string()));
+        assert!(texts.contains(&"100%_bazz".to_string()));
+        assert!(texts.contains(&long));
+        let other = repository.find("other", TodoId::from(5)).await.unwrap();
+        assert_eq!(other.text, "foo");
+
+        // % や _ もそのままの文字として探す
+        let result = repository
+            .replace_text(DEFAULT_USER, "0%_", "0 percent ")
+            .await
+            .unwrap();
+        assert_eq!(result.updated, 1);
+        let todo = repository
+            .find(DEFAULT_USER, TodoId::from(3))
+            .await
+            .unwrap();
+        assert_eq!(todo.text, "100 percent bazz");
+    }
 
-    /// シナリオテスト(DBが起動している必要がある)
     #[tokio::test]
     async fn todo_crud_scenario() {
-        // point 1
-        dotenv().ok();
-        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL");
-        let pool = PgPool::connect(database_url)
+        let repository = TodoRepositoryForSqlite::connect("sqlite::memory:", 1)
             .await
-            .expect(&format!("fail connect database, url is [{}]", database_url));
-        let repository = TodoRepositoryForDb::new(pool.clone());
-        let todo_text = "[crud_scenario] text";
+            .expect("fail connect sqlite");
+        sqlx::migrate!("./migrations-sqlite")
+            .run(repository.pool())
+            .await
+            .expect("fail run migrations");
 
         // create
-        let created = repository
-            .create(CreateTodo::new(todo_text.to_string()))
+        let parent = repository
+            .create(DEFAULT_USER, CreateTodo::new("parent".to_string()))
             .await
             .expect("[create] returned Err");
-        assert_eq!(created.text, todo_text);
-        assert!(!created.completed);
+        let child = repository
+            .create(
+                DEFAULT_USER,
+                CreateTodo {
+                    parent_id: Some(parent.id),
+                    ..CreateTodo::new("child".to_string())
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(child.parent_id, Some(parent.id));
+
+        // find_many(指定順、見つからないIDは飛ばす)
+        let todos = repository
+            .find_many(DEFAULT_USER, &[child.id, TodoId::from(999), parent.id])
+            .await
+            .unwrap();
+        assert_eq!(
+            todos.iter().map(|todo| todo.id).collect::<Vec<_>>(),
+            vec![child.id, parent.id]
+        );
 
         // find
-        let todo = repository
-            .find(created.id)
+        let todo = repository.find(DEFAULT_USER, parent.id).await.unwrap();
+        assert_eq!(parent, todo);
+        assert!(repository.find("other", parent.id).await.is_err());
+
+        // count
+        assert_eq!(repository.count(DEFAULT_USER).await.unwrap(), 2);
+        assert_eq!(repository.count("other").await.unwrap(), 0);
+
+        // stream
+        let todos: Vec<Todo> = repository
+            .stream(DEFAULT_USER)
             .await
-            .expect("[find] returned Err");
-        assert_eq!(created, todo);
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(todos, vec![parent.clone(), child.clone()]);
 
-        // all
-        let todos = repository.all().await.expect("[all] returned Err");
-        let mut is_ok = false;
-        for todo in todos {
-            if created == todo {
-                is_ok = true;
-            }
-        }
-        assert!(is_ok);
+        // after
+        let todos = repository.after(DEFAULT_USER, None, 1).await.unwrap();
+        assert_eq!(vec![parent.clone()], todos);
+        let todos = repository
+            .after(DEFAULT_USER, Some(parent.id), 10)
+            .await
+            .unwrap();
+        assert_eq!(vec![child.clone()], todos);
+
+        // set_completed_many
+        let updated = repository
+            .set_completed_many(DEFAULT_USER, &[parent.id, TodoId::from(999)], true)
+            .await
+            .expect("[set_completed_many] returned Err");
+        assert_eq!(updated.len(), 1);
+        assert!(!updated[0].0.completed);
+        assert!(updated[0].1.completed);
+        // 完了日時は未完了から完了にしたときだけ記録し、完了のままなら変えない
+        let completed_at = repository
+            .find(DEFAULT_USER, parent.id)
+            .await
+            .unwrap()
+            .completed_at
+            .expect("completed_at is not set");
+        repository
+            .set_completed_many(DEFAULT_USER, &[parent.id], true)
+            .await
+            .unwrap();
+        let todo = repository.find(DEFAULT_USER, parent.id).await.unwrap();
+        assert_eq!(todo.completed_at, Some(completed_at));
+        let filter = TodoFilter {
+            completed_since: Some(completed_at),
+            ..TodoFilter::default()
+        };
+        let todos = repository.filter(DEFAULT_USER, &filter).await.unwrap();
+        assert_eq!(todos, vec![todo]);
+        let updated = repository
+            .set_completed_many(DEFAULT_USER, &[parent.id], false)
+            .await
+            .expect("[set_completed_many] returned Err");
+        assert_eq!(updated.len(), 1);
+        let todo = repository.find(DEFAULT_USER, parent.id).await.unwrap();
+        assert_eq!(todo.completed_at, None);
+
+        // move_to
+        let other = repository
+            .create(DEFAULT_USER, CreateTodo::new("other".to_string()))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(other.position, 1);
+        let moved = repository
+            .move_to(DEFAULT_USER, other.id, 0)
+            .await
+            .expect("[move_to] returned Err");
+        assert_eq!(moved.position, 0);
+        // 兄弟(親なしのTODO)だけがずれる
+        let todo = repository.find(DEFAULT_USER, parent.id).await.unwrap();
+        assert_eq!(todo.position, 1);
+        let todo = repository.find(DEFAULT_USER, child.id).await.unwrap();
+        assert_eq!(todo.position, 0);
+        repository.delete(DEFAULT_USER, other.id).await.unwrap();
+
+        // idempotent
+        let key = "key".to_string();
+        let ttl = Duration::from_secs(60);
+        let (created, is_new) = repository
+            .create_idempotent(
+                DEFAULT_USER,
+                CreateTodo::new("once".to_string()),
+                key.clone(),
+                ttl,
+            )
+            .await
+            .unwrap();
+        assert!(is_new);
+        let (replayed, is_new) = repository
+            .create_idempotent(DEFAULT_USER, CreateTodo::new("once".to_string()), key, ttl)
+            .await
+            .unwrap();
+        assert!(!is_new);
+        assert_eq!(created, replayed);
 
         // update
-        let updated_text = "[crud_scenario] updated text";
         let todo = repository
             .update(
-                todo.id,
+                DEFAULT_USER,
+                parent.id,
                 UpdateTodo {
-                    text: Some(updated_text.to_string()),
+                    text: None,
                     completed: Some(true),
+                    due_date: None,
+                    priority: Some(Some(1)),
+                    recurrence: Some(Some("weekly".to_string())),
+                    tags: Some(vec!["urgent".to_string()]),
                 },
             )
             .await
             .expect("[update] returned Err");
-        assert_eq!(updated_text, todo.text);
         assert!(todo.completed);
+        assert_eq!(todo.priority, Some(1));
+        assert_eq!(todo.recurrence.as_deref(), Some("weekly"));
+        assert_eq!(todo.tags, Tags(vec!["urgent".to_string()]));
+        let filter = TodoFilter {
+            tag: Some("urgent".to_string()),
+            ..TodoFilter::default()
+        };
+        let tagged = repository.filter(DEFAULT_USER, &filter).await.unwrap();
+        assert_eq!(tagged, vec![todo.clone()]);
+        let recent = repository.recent(DEFAULT_USER, 1).await.unwrap();
+        assert_eq!(recent, vec![todo.clone()]);
+        let stats = repository.stats().await.unwrap();
+        assert_eq!(
+            TodoStats {
+                total: 3,
+                completed: 1
+            },
+            stats
+        );
 
-        // delete
-        let _ = repository
-            .delete(todo.id)
+        // all_with_labels(2件に同じラベル、親にはもう1件)
+        sqlx::query(r#"insert into labels (name) values ('label'), ('other')"#)
+            .execute(repository.pool())
             .await
-            .expect("[delete] returned Err");
-        // deleteで消えていること
-        let res = repository.find(created.id).await;
-        assert!(res.is_err());
+            .unwrap();
+        for (todo_id, label_id) in [(parent.id, 1), (child.id, 1), (parent.id, 2)] {
+            sqlx::query(r#"insert into todo_labels (todo_id, label_id) values (?, ?)"#)
+                .bind(todo_id)
+                .bind(label_id)
+                .execute(repository.pool())
+                .await
+                .unwrap();
+        }
+        let todos = repository.all_with_labels(DEFAULT_USER).await.unwrap();
+        assert_eq!(todos.len(), 3);
+        for todo in todos {
+            let names: Vec<&str> = todo
+                .labels
+                .iter()
+                .map(|label| label.name.as_str())
+                .collect();
+            let expected = match todo.todo.id {
+                id if id == parent.id => vec!["label", "other"],
+                id if id == child.id => vec!["label"],
+                _ => vec![],
+            };
+            assert_eq!(names, expected);
+        }
 
-        let todo_rows = sqlx::query(
-            r#"
-               select * from todos where id=$1
-            "#,
-        )
-        .bind(todo.id)
-        .fetch_all(&pool)
-        .await
-        .expect("[delete] todo_labels fetch error");
-        assert_eq!(todo_rows.len(), 0);
+        // filter(指定した条件だけで絞り込む)
+        let filter = TodoFilter {
+            q: Some("CHILD".to_string()),
+            label_id: Some(LabelId::from(1)),
+            ..Default::default()
+        };
+        let todos = repository.filter(DEFAULT_USER, &filter).await.unwrap();
+        assert_eq!(
+            todos.iter().map(|todo| todo.id).collect::<Vec<_>>(),
+            vec![child.id]
+        );
+        let filter = TodoFilter {
+            label_id: Some(LabelId::from(2)),
+            ..Default::default()
+        };
+        let todos = repository.filter(DEFAULT_USER, &filter).await.unwrap();
+        assert_eq!(
+            todos.iter().map(|todo| todo.id).collect::<Vec<_>>(),
+            vec![parent.id]
+        );
+        let filter = TodoFilter {
+            q: Some("%".to_string()),
+            ..Default::default()
+        };
+        assert!(repository
+            .filter(DEFAULT_USER, &filter)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // delete(子TODOも消える)・restore
+        repository.delete(DEFAULT_USER, parent.id).await.unwrap();
+        let todo_labels: i64 = sqlx::query_scalar(r#"select count(*) from todo_labels"#)
+            .fetch_one(repository.pool())
+            .await
+            .unwrap();
+        assert_eq!(todo_labels, 0);
+        assert!(repository.find(DEFAULT_USER, child.id).await.is_err());
+        let restored = repository.restore(DEFAULT_USER, parent.id).await.unwrap();
+        assert_eq!(todo, restored);
+        assert!(repository.restore(DEFAULT_USER, parent.id).await.is_err());
     }
 }
 
@@ -223,35 +4421,179 @@ pub mod test_utils {
     use super::*;
     use anyhow::Context;
     use axum::async_trait;
+    #[cfg(not(feature = "uuid-id"))]
+    use std::sync::atomic::Ordering;
     use std::{
-        collections::HashMap,
-        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+        any::Any,
+        cmp::Reverse,
+        collections::{HashMap, VecDeque},
+        panic::{self, AssertUnwindSafe},
+        sync::{atomic::AtomicI64, Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
+        time::Instant,
     };
 
     impl CreateTodo {
         /// new object
         pub fn new(text: String) -> Self {
-            Self { text }
+            Self {
+                text,
+                completed: None,
+                parent_id: None,
+                due_date: None,
+                priority: None,
+                recurrence: None,
+                tags: Vec::new(),
+            }
+        }
+    }
+
+    impl Todo {
+        /// new object
+        pub fn new(id: impl Into<TodoId>, text: String) -> Self {
+            Self {
+                id: id.into(),
+                user_id: DEFAULT_USER.to_string(),
+                text,
+                completed: false,
+                parent_id: None,
+                due_date: None,
+                priority: None,
+                recurrence: None,
+                position: 0,
+                updated_at: Utc::now(),
+                tags: Tags::default(),
+                completed_at: None,
+            }
+        }
+
+        /// 最終更新日時を差し替える(日時を比較しないテスト用)
+        pub fn with_updated_at(self, updated_at: DateTime<Utc>) -> Self {
+            Self { updated_at, ..self }
+        }
+
+        /// 並び順を差し替える
+        pub fn with_position(self, position: i32) -> Self {
+            Self { position, ..self }
+        }
+    }
+
+    type TodoData = HashMap<TodoId, Todo>;
+    /// (ユーザーID, 冪等キー) ごとの作成済みTODOと作成日時
+    type IdempotencyKeys = HashMap<(String, String), (TodoId, Instant)>;
+
+    /// IDの採番(DBの連番と同じく開始値から間隔ずつ増やし、削除しても同じIDは使わない)
+    /// uuid-id機能ではUUIDを採番するため開始値・間隔は使わない
+    #[derive(Debug)]
+    #[cfg_attr(feature = "uuid-id", allow(dead_code))]
+    struct IdSequence {
+        next: AtomicI64,
+        step: i64,
+    }
+
+    impl IdSequence {
+        fn new(start: i64, step: i64) -> Self {
+            assert!(step > 0, "id step must be positive");
+            Self {
+                next: AtomicI64::new(start),
+                step,
+            }
+        }
+
+        /// 新しいIDを採番する
+        #[cfg(not(feature = "uuid-id"))]
+        fn next(&self) -> TodoId {
+            TodoId::from(self.next.fetch_add(self.step, Ordering::Relaxed) as RawTodoId)
+        }
+
+        /// 新しいIDを採番する
+        #[cfg(feature = "uuid-id")]
+        fn next(&self) -> TodoId {
+            TodoId::new_v4()
+        }
+    }
+
+    impl Default for IdSequence {
+        fn default() -> Self {
+            Self::new(1, 1)
         }
     }
 
-    impl Todo {
-        /// new object
-        pub fn new(id: i32, text: String) -> Self {
-            Self {
-                id,
-                text,
-                completed: false,
-            }
-        }
+    /// 新しいIDを採番して登録する(兄弟TODOの末尾に並べる)
+    fn insert_todo(
+        store: &mut TodoData,
+        ids: &IdSequence,
+        user_id: &str,
+        payload: CreateTodo,
+    ) -> Todo {
+        let id = ids.next();
+        let todo = Todo::draft(user_id, payload);
+        let position = store
+            .values()
+            .filter(|other| other.user_id == todo.user_id && other.parent_id == todo.parent_id)
+            .map(|other| other.position + 1)
+            .max()
+            .unwrap_or(0);
+        let todo = Todo {
+            id,
+            position,
+            ..todo
+        };
+        store.insert(id, todo.clone());
+        todo
     }
 
-    type TodoData = HashMap<i32, Todo>;
+    /// TODOに付いたラベル(LabelRepositoryForMemory と共有できる)
+    pub type TodoLabelStore = Arc<RwLock<HashMap<TodoId, Vec<Label>>>>;
+    /// 変更履歴(ユーザーIDと変更の組を記録順に保持する)
+    type History = Vec<(String, TodoChange)>;
 
     /// オンメモリリポジトリ
     #[derive(Debug, Clone)]
     pub struct TodoRepositoryForMemory {
         store: Arc<RwLock<TodoData>>,
+        idempotency_keys: Arc<RwLock<IdempotencyKeys>>,
+        deleted: Arc<RwLock<VecDeque<(Todo, Instant)>>>,
+        labels: TodoLabelStore,
+        history: Arc<RwLock<History>>,
+        ids: Arc<IdSequence>,
+        undo: UndoConfig,
+        quota: Option<i64>,
+        sort: TodoSort,
+    }
+
+    /// オンメモリリポジトリの状態の複製(テスト支援用)
+    #[derive(Debug, Clone, Default)]
+    pub struct TodoSnapshot {
+        store: TodoData,
+        deleted: VecDeque<(Todo, Instant)>,
+        labels: HashMap<TodoId, Vec<Label>>,
+        history: History,
+    }
+
+    /// 絞り込み条件を満たすこと
+    fn matches_filter(filter: &TodoFilter, todo: &Todo, label_ids: &[LabelId]) -> bool {
+        filter
+            .completed
+            .is_none_or(|completed| todo.completed == completed)
+            && filter
+                .q
+                .as_ref()
+                .is_none_or(|q| todo.text.to_lowercase().contains(&q.to_lowercase()))
+            && filter.label_id.is_none_or(|id| label_ids.contains(&id))
+            && filter
+                .due_before
+                .is_none_or(|before| todo.due_date.is_some_and(|due| due < before))
+            && filter
+                .due_after
+                .is_none_or(|after| todo.due_date.is_some_and(|due| due >= after))
+            && filter
+                .tag
+                .as_ref()
+                .is_none_or(|tag| todo.tags.0.contains(tag))
+            && filter.completed_since.is_none_or(|since| {
+                todo.completed_at
+                    .is_some_and(|completed_at| completed_at >= since)
+            })
     }
 
     impl TodoRepositoryForMemory {
@@ -259,9 +4601,109 @@ pub mod test_utils {
         pub fn new() -> Self {
             TodoRepositoryForMemory {
                 store: Arc::default(),
+                idempotency_keys: Arc::default(),
+                deleted: Arc::default(),
+                labels: Arc::default(),
+                history: Arc::default(),
+                ids: Arc::default(),
+                undo: UndoConfig::from_env(),
+                quota: None,
+                sort: TodoSort::default(),
+            }
+        }
+
+        /// IDの開始値と間隔を指定する(既定は1から1ずつ)
+        /// DBの連番に合わせたIDを再現するためのテスト用で、本番では必ずDBのリポジトリを使うこと
+        #[cfg(not(feature = "uuid-id"))]
+        pub fn with_id_sequence(mut self, start: RawTodoId, step: RawTodoId) -> Self {
+            self.ids = Arc::new(IdSequence::new(start.into(), step.into()));
+            self
+        }
+
+        /// ユーザーごとのTODO件数の上限を指定する
+        pub fn with_quota(mut self, limit: i64) -> Self {
+            self.quota = Some(limit);
+            self
+        }
+
+        /// 一覧の並び順を指定する(既定は position 順)
+        pub fn with_sort(mut self, sort: TodoSort) -> Self {
+            self.sort = sort;
+            self
+        }
+
+        /// incoming 件登録してもTODO件数が上限を超えないか確認する
+        fn check_quota(
+            &self,
+            store: &TodoData,
+            user_id: &str,
+            incoming: usize,
+        ) -> anyhow::Result<()> {
+            match self.quota {
+                Some(limit)
+                    if (store
+                        .values()
+                        .filter(|todo| todo.user_id == user_id)
+                        .count()
+                        + incoming) as i64
+                        > limit =>
+                {
+                    Err(RepositoryError::QuotaExceeded(limit).into())
+                }
+                _ => Ok(()),
+            }
+        }
+
+        /// 現在の状態を複製する(テスト支援用。restore で戻せる)
+        pub fn snapshot(&self) -> TodoSnapshot {
+            TodoSnapshot {
+                store: self.read_store_ref().clone(),
+                deleted: self.deleted.read().unwrap().clone(),
+                labels: self.labels.read().unwrap().clone(),
+                history: self.history.read().unwrap().clone(),
             }
         }
 
+        /// snapshot で複製した状態に戻す(テスト支援用)
+        pub fn restore(&self, snapshot: TodoSnapshot) {
+            *self.write_store_ref() = snapshot.store;
+            *self.deleted.write().unwrap() = snapshot.deleted;
+            *self.labels.write().unwrap() = snapshot.labels;
+            *self.history.write().unwrap() = snapshot.history;
+        }
+
+        /// 全て消す(テスト支援用)
+        pub fn clear(&self) {
+            self.restore(TodoSnapshot::default());
+            self.idempotency_keys.write().unwrap().clear();
+        }
+
+        /// TODOにラベルを付ける
+        pub fn attach_label(&self, todo_id: TodoId, label: Label) {
+            let mut labels = self.labels.write().unwrap();
+            labels.entry(todo_id).or_default().push(label);
+        }
+
+        /// TODOに付いたラベル(LabelRepositoryForMemory::with_todos で共有する)
+        pub fn label_store(&self) -> TodoLabelStore {
+            self.labels.clone()
+        }
+
+        /// 変更履歴を1件記録する(old_text は変更前の本文、todo は変更後のTODO)
+        fn record_change(&self, change_type: ChangeType, old_text: Option<String>, todo: &Todo) {
+            let mut history = self.history.write().unwrap();
+            let change = TodoChange {
+                id: history.len() as i64 + 1,
+                todo_id: todo.id,
+                change_type: change_type.as_str().to_string(),
+                old_text,
+                new_text: change_type.new_text(todo).map(str::to_string),
+                completed: todo.completed,
+                changed_at: Utc::now(),
+            };
+            history.push((todo.user_id.clone(), change));
+        }
+
         /// スレッドセーフにstoreを取得
         fn write_store_ref(&self) -> RwLockWriteGuard<TodoData> {
             self.store.write().unwrap()
@@ -271,163 +4713,824 @@ pub mod test_utils {
         fn read_store_ref(&self) -> RwLockReadGuard<TodoData> {
             self.store.read().unwrap()
         }
+
+        /// 複数の変更を1回の書き込みロックの中でまとめて行う(DBのトランザクション相当)
+        /// f には store の複製を渡し、Ok を返したときだけ置き換える。Err やパニックなら何も変えない(パニックはロックを外してから伝える)
+        /// ロック中は他の読み書きが待つので、f の中でこのリポジトリのメソッドを呼ばないこと(デッドロックする)
+        /// 採番したIDはDBの連番と同じく取り消しても戻らない
+        fn transaction<R>(
+            &self,
+            f: impl FnOnce(&mut TodoData) -> anyhow::Result<R>,
+        ) -> anyhow::Result<R> {
+            let mut store = self.write_store_ref();
+            let mut working = store.clone();
+            match panic::catch_unwind(AssertUnwindSafe(|| f(&mut working))) {
+                Ok(Ok(value)) => {
+                    *store = working;
+                    Ok(value)
+                }
+                Ok(Err(e)) => Err(e),
+                Err(payload) => {
+                    drop(store);
+                    panic::resume_unwind(payload)
+                }
+            }
+        }
     }
 
     /// オンメモリリポジトリ
     #[async_trait]
     impl TodoRepository for TodoRepositoryForMemory {
         /// TODO作成
-        async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        async fn create(&self, user_id: &str, payload: CreateTodo) -> anyhow::Result<Todo> {
             let mut store = self.write_store_ref();
-            let id = (store.len() + 1) as i32;
-            let todo = Todo::new(id, payload.text.clone());
-            store.insert(id, todo.clone());
+            self.check_quota(&store, user_id, 1)?;
+            let todo = insert_todo(&mut store, &self.ids, user_id, payload);
+            self.record_change(ChangeType::Created, None, &todo);
             Ok(todo)
         }
+        /// TODO一括作成(すべて作成するか、何も作成しない)
+        async fn create_many(
+            &self,
+            user_id: &str,
+            payloads: Vec<CreateTodo>,
+        ) -> anyhow::Result<Vec<Todo>> {
+            let todos: Vec<Todo> = self.transaction(|store| {
+                self.check_quota(store, user_id, payloads.len())?;
+                Ok(payloads
+                    .into_iter()
+                    .map(|payload| insert_todo(store, &self.ids, user_id, payload))
+                    .collect())
+            })?;
+            for todo in &todos {
+                self.record_change(ChangeType::Created, None, todo);
+            }
+            Ok(todos)
+        }
+        /// 冪等キー付きTODO作成
+        async fn create_idempotent(
+            &self,
+            user_id: &str,
+            payload: CreateTodo,
+            key: String,
+            ttl: Duration,
+        ) -> anyhow::Result<(Todo, bool)> {
+            let mut keys = self.idempotency_keys.write().unwrap();
+            let mut store = self.write_store_ref();
+            let key = (user_id.to_string(), key);
+            let created = keys
+                .get(&key)
+                .filter(|(_, created_at)| created_at.elapsed() < ttl)
+                .and_then(|(id, _)| store.get(id));
+            if let Some(todo) = created {
+                return Ok((todo.clone(), false));
+            }
+
+            self.check_quota(&store, user_id, 1)?;
+            let todo = insert_todo(&mut store, &self.ids, user_id, payload);
+            self.record_change(ChangeType::Created, None, &todo);
+            keys.insert(key, (todo.id, Instant::now()));
+            Ok((todo, true))
+        }
         /// TODO検索
-        async fn find(&self, id: i32) -> anyhow::Result<Todo> {
+        async fn find(&self, user_id: &str, id: TodoId) -> anyhow::Result<Todo> {
             let store = self.read_store_ref();
             let todo = store
                 .get(&id)
+                .filter(|todo| todo.user_id == user_id)
                 .map(|todo| todo.clone())
-                .ok_or(RepositoryError::NotFound(id))?;
+                .ok_or(RepositoryError::NotFound(id.into()))?;
+            Ok(todo)
+        }
+        /// IDで取得(見つからなければ None)
+        async fn find_opt(&self, user_id: &str, id: TodoId) -> anyhow::Result<Option<Todo>> {
+            let store = self.read_store_ref();
+            let todo = store
+                .get(&id)
+                .filter(|todo| todo.user_id == user_id)
+                .cloned();
             Ok(todo)
         }
+        /// 複数のIDで取得
+        async fn find_many(&self, user_id: &str, ids: &[TodoId]) -> anyhow::Result<Vec<Todo>> {
+            let store = self.read_store_ref();
+            let todos = ids
+                .iter()
+                .filter_map(|id| store.get(id))
+                .filter(|todo| todo.user_id == user_id)
+                .cloned()
+                .collect();
+            Ok(order_by_ids(todos, ids))
+        }
         /// 全権取得
-        async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+        async fn all(&self, user_id: &str) -> anyhow::Result<Vec<Todo>> {
+            let store = self.read_store_ref();
+            let mut todos: Vec<Todo> = store
+                .values()
+                .filter(|todo| todo.user_id == user_id)
+                .cloned()
+                .collect();
+            match self.sort {
+                TodoSort::Position => todos.sort_by_key(|todo| (todo.position, todo.id)),
+                TodoSort::Id => todos.sort_by_key(|todo| todo.id),
+            }
+            Ok(todos)
+        }
+        /// 全件を1件ずつ取得(ロック中に取った複製をid順に返す)
+        async fn stream(&self, user_id: &str) -> anyhow::Result<TodoStream> {
+            let store = self.read_store_ref();
+            let mut todos: Vec<Todo> = store
+                .values()
+                .filter(|todo| todo.user_id == user_id)
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| todo.id);
+            Ok(Box::pin(tokio_stream::iter(todos.into_iter().map(Ok))))
+        }
+        /// 条件で絞り込んで取得
+        async fn filter(&self, user_id: &str, filter: &TodoFilter) -> anyhow::Result<Vec<Todo>> {
+            let todos = self.all(user_id).await?;
+            let labels = self.labels.read().unwrap();
+            Ok(todos
+                .into_iter()
+                .filter(|todo| {
+                    let label_ids: Vec<LabelId> = labels
+                        .get(&todo.id)
+                        .map(|labels| labels.iter().map(|label| label.id).collect())
+                        .unwrap_or_default();
+                    matches_filter(filter, todo, &label_ids)
+                })
+                .collect())
+        }
+        /// ラベル付きで全件取得
+        async fn all_with_labels(&self, user_id: &str) -> anyhow::Result<Vec<TodoWithLabels>> {
+            let todos = self.all(user_id).await?;
+            let labels = self.labels.read().unwrap();
+            Ok(todos
+                .into_iter()
+                .map(|todo| {
+                    let mut labels = labels.get(&todo.id).cloned().unwrap_or_default();
+                    labels.sort_by_key(|label| label.id);
+                    TodoWithLabels { todo, labels }
+                })
+                .collect())
+        }
+        /// 件数取得
+        async fn count(&self, user_id: &str) -> anyhow::Result<i64> {
+            let store = self.read_store_ref();
+            let count = store
+                .values()
+                .filter(|todo| todo.user_id == user_id)
+                .count();
+            Ok(count as i64)
+        }
+        /// ページ単位で取得(並び順は all と同じ)
+        async fn page(&self, user_id: &str, limit: i64, offset: i64) -> anyhow::Result<Vec<Todo>> {
+            let todos = self.all(user_id).await?;
+            Ok(todos
+                .into_iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .collect())
+        }
+        /// カーソル(ID)より後をid順に取得(カーソル未指定なら先頭から)
+        /// カーソルはIDの大小で続きを決めるので、並び順の設定に関わらずid順で返す
+        async fn after(
+            &self,
+            user_id: &str,
+            cursor: Option<TodoId>,
+            limit: i64,
+        ) -> anyhow::Result<Vec<Todo>> {
             let store = self.read_store_ref();
-            Ok(Vec::from_iter(store.values().map(|todo| todo.clone())))
+            let mut todos: Vec<Todo> = store
+                .values()
+                .filter(|todo| todo.user_id == user_id)
+                .filter(|todo| !matches!(cursor, Some(cursor) if todo.id <= cursor))
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| todo.id);
+            todos.truncate(limit as usize);
+            Ok(todos)
         }
         /// 更新
-        async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        async fn update(
+            &self,
+            user_id: &str,
+            id: TodoId,
+            payload: UpdateTodo,
+        ) -> anyhow::Result<Todo> {
             let mut store = self.write_store_ref();
-            let todo = store.get(&id).context(RepositoryError::NotFound(id))?;
-            let text = payload.text.unwrap_or(todo.text.clone());
-            let completed = payload.completed.unwrap_or(todo.completed.clone());
-            let todo = Todo {
-                id,
-                text,
-                completed,
-            };
+            let old_todo = store
+                .get(&id)
+                .filter(|todo| todo.user_id == user_id)
+                .context(RepositoryError::NotFound(id.into()))?
+                .clone();
+            let old_text = old_todo.text.clone();
+            let todo = old_todo.apply(payload);
             store.insert(id, todo.clone());
+            self.record_change(ChangeType::Updated, Some(old_text), &todo);
             Ok(todo)
         }
-        /// 削除
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        /// 本文の末尾に追記(書き込みロックを持ったまま読んで書き戻す)
+        async fn append_text(&self, user_id: &str, id: TodoId, text: &str) -> anyhow::Result<Todo> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
-            Ok(())
+            let todo = store
+                .get_mut(&id)
+                .filter(|todo| todo.user_id == user_id)
+                .context(RepositoryError::NotFound(id.into()))?;
+            let appended = appended_text(&todo.text, text)?;
+            let old_text = std::mem::replace(&mut todo.text, appended);
+            todo.updated_at = Utc::now();
+            self.record_change(ChangeType::Updated, Some(old_text), todo);
+            Ok(todo.clone())
         }
-    }
-    mod test {
-        use super::*;
-        use std::vec;
-
-        /// TODOを保持するための型
-        type TodoData = HashMap<i32, Todo>;
-
-        /// オンメモリリポジトリ
-        #[derive(Debug, Clone)]
-        pub struct TodoRepositoryForMemory {
-            store: Arc<RwLock<TodoData>>,
+        /// 本文の一括置換(最大文字数を超えるものは変更せずに返す)
+        async fn replace_text(
+            &self,
+            user_id: &str,
+            find: &str,
+            replace: &str,
+        ) -> anyhow::Result<ReplaceTextResult> {
+            let result = self.transaction(|store| {
+                let mut result = ReplaceTextResult::default();
+                for todo in store.values_mut() {
+                    if todo.user_id != user_id || !todo.text.contains(find) {
+                        continue;
+                    }
+                    let text = todo.text.replace(find, replace);
+                    if text.chars().count() > max_text_len() {
+                        result.skipped.push(todo.id);
+                        continue;
+                    }
+                    let old = todo.clone();
+                    todo.text = text;
+                    todo.updated_at = Utc::now();
+                    result.changes.push((old, todo.clone()));
+                    result.updated += 1;
+                }
+                result.skipped.sort();
+                Ok(result)
+            })?;
+            for (old, todo) in &result.changes {
+                self.record_change(ChangeType::Updated, Some(old.text.clone()), todo);
+            }
+            Ok(result)
         }
-
-        impl TodoRepositoryForMemory {
-            /// new object
-            pub fn new() -> Self {
-                TodoRepositoryForMemory {
-                    store: Arc::default(),
+        /// 一括更新(見つからないIDは None)
+        async fn update_many(
+            &self,
+            user_id: &str,
+            updates: Vec<(TodoId, UpdateTodo)>,
+        ) -> anyhow::Result<Vec<Option<Todo>>> {
+            let (todos, changes) = self.transaction(|store| {
+                let mut todos = Vec::with_capacity(updates.len());
+                let mut changes = Vec::new();
+                for (id, payload) in updates {
+                    let old_todo = match store.get(&id) {
+                        Some(todo) if todo.user_id == user_id => todo.clone(),
+                        _ => {
+                            todos.push(None);
+                            continue;
+                        }
+                    };
+                    let old_text = old_todo.text.clone();
+                    let todo = old_todo.apply(payload);
+                    store.insert(id, todo.clone());
+                    changes.push((old_text, todo.clone()));
+                    todos.push(Some(todo));
                 }
+                Ok((todos, changes))
+            })?;
+            for (old_text, todo) in &changes {
+                self.record_change(ChangeType::Updated, Some(old_text.clone()), todo);
             }
-
-            /// スレッドセーフにstoreを取得
-            fn write_store_ref(&self) -> RwLockWriteGuard<TodoData> {
-                self.store.write().unwrap()
+            Ok(todos)
+        }
+        /// 完了状態の一括更新(存在しないIDは無視し、更新した件数を返す)
+        async fn set_completed_many(
+            &self,
+            user_id: &str,
+            ids: &[TodoId],
+            completed: bool,
+        ) -> anyhow::Result<Vec<(Todo, Todo)>> {
+            let changes = self.transaction(|store| {
+                let mut changes = Vec::new();
+                for todo in store.values_mut() {
+                    if todo.user_id == user_id && ids.contains(&todo.id) {
+                        let old = todo.clone();
+                        todo.completed_at = todo.completed_at_for(completed);
+                        todo.completed = completed;
+                        todo.updated_at = Utc::now();
+                        changes.push((old, todo.clone()));
+                    }
+                }
+                Ok(changes)
+            })?;
+            for (_, todo) in &changes {
+                self.record_change(ChangeType::Updated, Some(todo.text.clone()), todo);
             }
-
-            /// スレッドセーフにstoreを取得
-            fn read_store_ref(&self) -> RwLockReadGuard<TodoData> {
-                self.store.read().unwrap()
+            Ok(changes)
+        }
+        /// 削除(DBと同じく子孫TODOも削除する)
+        async fn delete(&self, user_id: &str, id: TodoId) -> anyhow::Result<()> {
+            let mut store = self.write_store_ref();
+            if !matches!(store.get(&id), Some(todo) if todo.user_id == user_id) {
+                return Err(RepositoryError::NotFound(id.into()).into());
+            }
+            let todo = store.remove(&id).unwrap();
+            self.record_change(ChangeType::Deleted, Some(todo.text.clone()), &todo);
+            // DBと同じく子孫TODOも含めてラベルの付与は消す
+            let mut labels = self.labels.write().unwrap();
+            labels.remove(&id);
+            // 取り消し用にユーザーごとに直近の削除を保持する
+            let mut deleted = self.deleted.write().unwrap();
+            deleted.retain(|(todo, _)| todo.id != id);
+            deleted.push_back((todo, Instant::now()));
+            while deleted
+                .iter()
+                .filter(|(todo, _)| todo.user_id == user_id)
+                .count()
+                > self.undo.capacity
+            {
+                let oldest = deleted
+                    .iter()
+                    .position(|(todo, _)| todo.user_id == user_id)
+                    .unwrap();
+                deleted.remove(oldest);
+            }
+            let mut parents = vec![id];
+            while let Some(parent_id) = parents.pop() {
+                let children: Vec<TodoId> = store
+                    .values()
+                    .filter(|todo| todo.parent_id == Some(parent_id))
+                    .map(|todo| todo.id)
+                    .collect();
+                for child_id in children {
+                    if let Some(child) = store.remove(&child_id) {
+                        self.record_change(ChangeType::Deleted, Some(child.text.clone()), &child);
+                    }
+                    labels.remove(&child_id);
+                    parents.push(child_id);
+                }
             }
+            Ok(())
         }
-
-        // オンメモリリポジトリ
-        #[async_trait]
-        impl TodoRepository for TodoRepositoryForMemory {
-            /// TODO作成
-            async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
-                let mut store = self.write_store_ref();
-                let id = (store.len() + 1) as i32;
-                let todo = Todo::new(id, payload.text.clone());
-                store.insert(id, todo.clone());
-                Ok(todo)
+        /// 削除の取り消し
+        async fn restore(&self, user_id: &str, id: TodoId) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let mut deleted = self.deleted.write().unwrap();
+            let index = deleted
+                .iter()
+                .position(|(todo, deleted_at)| {
+                    todo.id == id && todo.user_id == user_id && deleted_at.elapsed() < self.undo.ttl
+                })
+                .ok_or(RepositoryError::NotFound(id.into()))?;
+            let (mut todo, _) = deleted.remove(index).unwrap();
+            todo.parent_id = todo.parent_id.filter(
+                |parent_id| matches!(store.get(parent_id), Some(parent) if parent.user_id == user_id),
+            );
+            store.insert(id, todo.clone());
+            self.record_change(ChangeType::Restored, None, &todo);
+            Ok(todo)
+        }
+        /// 兄弟TODOの中で指定の位置へ移動する(位置は0始まり、範囲外は末尾。兄弟の位置は詰め直す)
+        async fn move_to(&self, user_id: &str, id: TodoId, position: i32) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let parent_id = store
+                .get(&id)
+                .filter(|todo| todo.user_id == user_id)
+                .context(RepositoryError::NotFound(id.into()))?
+                .parent_id;
+            let mut siblings: Vec<&Todo> = store
+                .values()
+                .filter(|todo| {
+                    todo.user_id == user_id && todo.parent_id == parent_id && todo.id != id
+                })
+                .collect();
+            siblings.sort_by_key(|todo| (todo.position, todo.id));
+            let mut siblings: Vec<TodoId> = siblings.into_iter().map(|todo| todo.id).collect();
+            let index = (position.max(0) as usize).min(siblings.len());
+            siblings.insert(index, id);
+            for (position, sibling) in siblings.into_iter().enumerate() {
+                if let Some(todo) = store.get_mut(&sibling) {
+                    todo.position = position as i32;
+                }
             }
-            /// TODO検索
-            async fn find(&self, id: i32) -> anyhow::Result<Todo> {
-                let store = self.read_store_ref();
-                let todo = store
-                    .get(&id)
-                    .map(|todo| todo.clone())
-                    .ok_or(RepositoryError::NotFound(id))?;
-                Ok(todo)
+            let todo = store.get_mut(&id).unwrap();
+            todo.updated_at = Utc::now();
+            self.record_change(ChangeType::Moved, Some(todo.text.clone()), todo);
+            Ok(todo.clone())
+        }
+        /// 子TODO取得
+        async fn children(&self, user_id: &str, id: TodoId) -> anyhow::Result<Vec<Todo>> {
+            let store = self.read_store_ref();
+            let mut todos: Vec<Todo> = store
+                .values()
+                .filter(|todo| todo.user_id == user_id && todo.parent_id == Some(id))
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| (todo.position, todo.id));
+            Ok(todos)
+        }
+        /// 変更履歴(記録順。履歴が無く、TODOも無ければ NotFound)
+        async fn history(&self, user_id: &str, id: TodoId) -> anyhow::Result<Vec<TodoChange>> {
+            let changes: Vec<TodoChange> = self
+                .history
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|(owner, change)| owner == user_id && change.todo_id == id)
+                .map(|(_, change)| change.clone())
+                .collect();
+            if changes.is_empty() && self.find_opt(user_id, id).await?.is_none() {
+                return Err(RepositoryError::NotFound(id.into()).into());
+            }
+            Ok(changes)
+        }
+        /// 期限が指定範囲内の未完了TODO取得(期限順)
+        async fn due_between(
+            &self,
+            user_id: &str,
+            start: DateTime<Utc>,
+            end: DateTime<Utc>,
+        ) -> anyhow::Result<Vec<Todo>> {
+            let store = self.read_store_ref();
+            let mut todos: Vec<Todo> = store
+                .values()
+                .filter(|todo| todo.user_id == user_id && !todo.completed)
+                .filter(|todo| matches!(todo.due_date, Some(due) if start <= due && due < end))
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| (todo.due_date, todo.id));
+            Ok(todos)
+        }
+        /// 最近更新したものから取得
+        async fn recent(&self, user_id: &str, limit: i64) -> anyhow::Result<Vec<Todo>> {
+            let mut todos = self.all(user_id).await?;
+            todos.sort_by_key(|todo| Reverse((todo.updated_at, todo.id)));
+            todos.truncate(limit.max(0) as usize);
+            Ok(todos)
+        }
+        /// 件数取得
+        async fn stats(&self) -> anyhow::Result<TodoStats> {
+            let store = self.read_store_ref();
+            let completed = store.values().filter(|todo| todo.completed).count();
+            Ok(TodoStats {
+                total: store.len() as i64,
+                completed: completed as i64,
+            })
+        }
+        /// 接続状態(TODOの件数)
+        async fn health(&self) -> anyhow::Result<Health> {
+            let store = self.read_store_ref();
+            Ok(Health {
+                ok: true,
+                migrated: None,
+                latency_ms: None,
+                idle_connections: None,
+                active_connections: None,
+                todos: Some(store.len()),
+            })
+        }
+        /// データ一式の取り込み(ラベルは付けたTODOごとに保持する)
+        async fn import_dataset(
+            &self,
+            user_id: &str,
+            dataset: Dataset,
+            replace: bool,
+        ) -> anyhow::Result<ImportCounts> {
+            let mut store = self.write_store_ref();
+            let mut labels = self.labels.write().unwrap();
+            let mut counts = ImportCounts::default();
+            // replace なら今あるTODOは消えるので、取り込む件数だけで上限を確かめる
+            let empty = TodoData::new();
+            let current = if replace { &empty } else { &*store };
+            self.check_quota(current, user_id, dataset.todos.len())?;
+            if replace {
+                let ids: Vec<TodoId> = store
+                    .values()
+                    .filter(|todo| todo.user_id == user_id)
+                    .map(|todo| todo.id)
+                    .collect();
+                for id in &ids {
+                    if let Some(todo) = store.remove(id) {
+                        self.record_change(ChangeType::Deleted, Some(todo.text.clone()), &todo);
+                    }
+                    labels.remove(id);
+                }
+                counts.replaced = ids.len() as u64;
             }
-            /// 全権取得
-            async fn all(&self) -> anyhow::Result<Vec<Todo>> {
-                let store = self.read_store_ref();
-                Ok(Vec::from_iter(store.values().map(|todo| todo.clone())))
-            }
-            /// 更新
-            async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
-                let mut store = self.write_store_ref();
-                let todo = store.get(&id).context(RepositoryError::NotFound(id))?;
-                let text = payload.text.unwrap_or(todo.text.clone());
-                let completed = payload.completed.unwrap_or(todo.completed.clone());
+
+            let todo_ids: HashMap<TodoId, TodoId> = dataset
+                .todos
+                .iter()
+                .map(|todo| (todo.id, self.ids.next()))
+                .collect();
+            for todo in dataset.todos {
+                let id = todo_ids[&todo.id];
+                let parent_id = todo.parent_id.map(|parent_id| todo_ids[&parent_id]);
                 let todo = Todo {
                     id,
-                    text,
-                    completed,
+                    user_id: user_id.to_string(),
+                    parent_id,
+                    ..todo
                 };
-                store.insert(id, todo.clone());
-                Ok(todo)
+                self.record_change(ChangeType::Created, None, &todo);
+                store.insert(id, todo);
+            }
+            for association in dataset.associations {
+                let label = dataset
+                    .labels
+                    .iter()
+                    .find(|label| label.id == association.label_id)
+                    .cloned();
+                let todo_labels = labels.entry(todo_ids[&association.todo_id]).or_default();
+                if let Some(label) = label.filter(|label| !todo_labels.contains(label)) {
+                    todo_labels.push(label);
+                    counts.associations += 1;
+                }
+            }
+            counts.todos = todo_ids.len();
+            counts.labels = dataset.labels.len();
+
+            Ok(counts)
+        }
+    }
+
+    /// メソッドごとに事前に設定した結果
+    type MockResults = HashMap<&'static str, VecDeque<anyhow::Result<Box<dyn Any + Send>>>>;
+
+    /// 結果を事前に設定できるリポジトリ(エラー時のレスポンスのテスト用)
+    /// 設定した結果はメソッドごとに先頭から返し、設定が無ければ予期しないエラーを返す
+    #[derive(Clone, Default)]
+    pub struct MockTodoRepository {
+        results: Arc<Mutex<MockResults>>,
+        delay: Option<Duration>,
+    }
+
+    impl MockTodoRepository {
+        /// new object
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// メソッド名を指定して次に返す結果を追加する
+        pub fn push_result<R: Send + 'static>(
+            &self,
+            method: &'static str,
+            result: anyhow::Result<R>,
+        ) {
+            let result = result.map(|value| Box::new(value) as Box<dyn Any + Send>);
+            self.results
+                .lock()
+                .unwrap()
+                .entry(method)
+                .or_default()
+                .push_back(result);
+        }
+
+        /// 結果を返すまで待たせる(遅いDBの再現用)
+        pub fn with_delay(mut self, delay: Duration) -> Self {
+            self.delay = Some(delay);
+            self
+        }
+
+        /// 設定した結果を取り出す
+        async fn next<R: 'static>(&self, method: &'static str) -> anyhow::Result<R> {
+            if let Some(delay) = self.delay {
+                tokio::time::sleep(delay).await;
+            }
+            let result = self
+                .results
+                .lock()
+                .unwrap()
+                .get_mut(method)
+                .and_then(|results| results.pop_front());
+            match result {
+                Some(result) => result.map(|value| {
+                    *value
+                        .downcast::<R>()
+                        .unwrap_or_else(|_| panic!("unexpected result type for [{}]", method))
+                }),
+                None => {
+                    Err(RepositoryError::Unexpected(format!("no result for [{}]", method)).into())
+                }
+            }
+        }
+    }
+
+    #[async_trait]
+    impl TodoRepository for MockTodoRepository {
+        async fn create(&self, _: &str, _: CreateTodo) -> anyhow::Result<Todo> {
+            self.next("create").await
+        }
+        async fn create_many(&self, _: &str, _: Vec<CreateTodo>) -> anyhow::Result<Vec<Todo>> {
+            self.next("create_many").await
+        }
+        async fn create_idempotent(
+            &self,
+            _: &str,
+            _: CreateTodo,
+            _: String,
+            _: Duration,
+        ) -> anyhow::Result<(Todo, bool)> {
+            self.next("create_idempotent").await
+        }
+        async fn find(&self, _: &str, _: TodoId) -> anyhow::Result<Todo> {
+            self.next("find").await
+        }
+        async fn find_opt(&self, _: &str, _: TodoId) -> anyhow::Result<Option<Todo>> {
+            self.next("find_opt").await
+        }
+        async fn find_many(&self, _: &str, _: &[TodoId]) -> anyhow::Result<Vec<Todo>> {
+            self.next("find_many").await
+        }
+        async fn all(&self, _: &str) -> anyhow::Result<Vec<Todo>> {
+            self.next("all").await
+        }
+        async fn stream(&self, _: &str) -> anyhow::Result<TodoStream> {
+            self.next("stream").await
+        }
+        async fn all_with_labels(&self, _: &str) -> anyhow::Result<Vec<TodoWithLabels>> {
+            self.next("all_with_labels").await
+        }
+        async fn filter(&self, _: &str, _: &TodoFilter) -> anyhow::Result<Vec<Todo>> {
+            self.next("filter").await
+        }
+        async fn count(&self, _: &str) -> anyhow::Result<i64> {
+            self.next("count").await
+        }
+        async fn page(&self, _: &str, _: i64, _: i64) -> anyhow::Result<Vec<Todo>> {
+            self.next("page").await
+        }
+        async fn after(&self, _: &str, _: Option<TodoId>, _: i64) -> anyhow::Result<Vec<Todo>> {
+            self.next("after").await
+        }
+        async fn update(&self, _: &str, _: TodoId, _: UpdateTodo) -> anyhow::Result<Todo> {
+            self.next("update").await
+        }
+        async fn append_text(&self, _: &str, _: TodoId, _: &str) -> anyhow::Result<Todo> {
+            self.next("append_text").await
+        }
+        async fn replace_text(
+            &self,
+            _: &str,
+            _: &str,
+            _: &str,
+        ) -> anyhow::Result<ReplaceTextResult> {
+            self.next("replace_text").await
+        }
+        async fn update_many(
+            &self,
+            _: &str,
+            _: Vec<(TodoId, UpdateTodo)>,
+        ) -> anyhow::Result<Vec<Option<Todo>>> {
+            self.next("update_many").await
+        }
+        async fn set_completed_many(
+            &self,
+            _: &str,
+            _: &[TodoId],
+            _: bool,
+        ) -> anyhow::Result<Vec<(Todo, Todo)>> {
+            self.next("set_completed_many").await
+        }
+        async fn delete(&self, _: &str, _: TodoId) -> anyhow::Result<()> {
+            self.next("delete").await
+        }
+        async fn restore(&self, _: &str, _: TodoId) -> anyhow::Result<Todo> {
+            self.next("restore").await
+        }
+        async fn move_to(&self, _: &str, _: TodoId, _: i32) -> anyhow::Result<Todo> {
+            self.next("move_to").await
+        }
+        async fn children(&self, _: &str, _: TodoId) -> anyhow::Result<Vec<Todo>> {
+            self.next("children").await
+        }
+        async fn history(&self, _: &str, _: TodoId) -> anyhow::Result<Vec<TodoChange>> {
+            self.next("history").await
+        }
+        async fn due_between(
+            &self,
+            _: &str,
+            _: DateTime<Utc>,
+            _: DateTime<Utc>,
+        ) -> anyhow::Result<Vec<Todo>> {
+            self.next("due_between").await
+        }
+        async fn recent(&self, _: &str, _: i64) -> anyhow::Result<Vec<Todo>> {
+            self.next("recent").await
+        }
+        async fn stats(&self) -> anyhow::Result<TodoStats> {
+            self.next("stats").await
+        }
+        async fn health(&self) -> anyhow::Result<Health> {
+            self.next("health").await
+        }
+        async fn import_dataset(
+            &self,
+            _: &str,
+            _: Dataset,
+            _: bool,
+        ) -> anyhow::Result<ImportCounts> {
+            self.next("import_dataset").await
+        }
+    }
+
+    mod test {
+        use super::*;
+        use std::vec;
+
+        /// 期限はオフセット付きならUTCに直し、オフセットの無い日時・日付は指定のタイムゾーンで読む
+        #[test]
+        fn parse_due_date_formats() {
+            let utc = |value: &str| value.parse::<DateTime<Utc>>().unwrap();
+            let tokyo: Tz = "Asia/Tokyo".parse().unwrap();
+            for (value, tz, expected) in [
+                ("2025-01-31T09:00:00+09:00", Tz::UTC, "2025-01-31T00:00:00Z"),
+                (
+                    "2025-01-31T09:00:00.5-05:00",
+                    tokyo,
+                    "2025-01-31T14:00:00.5Z",
+                ),
+                ("2025-01-31 09:00:00Z", tokyo, "2025-01-31T09:00:00Z"),
+                ("2025-01-31T09:00:00", Tz::UTC, "2025-01-31T09:00:00Z"),
+                ("2025-01-31T09:00", tokyo, "2025-01-31T00:00:00Z"),
+                ("2025-01-31", Tz::UTC, "2025-01-31T00:00:00Z"),
+                ("2025-01-31", tokyo, "2025-01-30T15:00:00Z"),
+            ] {
+                assert_eq!(parse_due_date(value, tz), Some(utc(expected)), "{}", value);
             }
-            /// 削除
-            async fn delete(&self, id: i32) -> anyhow::Result<()> {
-                let mut store = self.write_store_ref();
-                store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
-                Ok(())
+            for value in [
+                "",
+                "tomorrow",
+                "2025-13-01",
+                "2025-01-31T25:00:00Z",
+                "31/01/2025",
+            ] {
+                assert_eq!(parse_due_date(value, Tz::UTC), None, "{}", value);
             }
+            // 夏時間の切り替えで存在しない時刻
+            let new_york: Tz = "America/New_York".parse().unwrap();
+            assert_eq!(parse_due_date("2025-03-09T02:30:00", new_york), None);
         }
 
         #[tokio::test]
         async fn todo_crud_scenario() {
             let text = "todo text".to_string();
-            let id = 1;
-            let expected = Todo::new(id, text.clone());
 
             // create
             let repository = TodoRepositoryForMemory::new();
             let todo = repository
-                .create(CreateTodo { text })
+                .create(
+                    DEFAULT_USER,
+                    CreateTodo {
+                        text: text.clone(),
+                        completed: None,
+                        parent_id: None,
+                        due_date: None,
+                        priority: None,
+                        recurrence: None,
+                        tags: Vec::new(),
+                    },
+                )
                 .await
                 .expect("failed create todo");
+            let created_at = todo.updated_at;
+            let id = todo.id;
+            let expected = Todo::new(id, text).with_updated_at(created_at);
             assert_eq!(expected, todo);
 
             // find
-            let todo = repository.find(todo.id).await.unwrap();
+            let todo = repository.find(DEFAULT_USER, todo.id).await.unwrap();
             assert_eq!(expected, todo);
+            // 他のユーザーからは見えない
+            assert!(repository.find("other", todo.id).await.is_err());
 
             // all
-            let todo = repository.all().await.unwrap();
+            let todo = repository.all(DEFAULT_USER).await.unwrap();
+            assert_eq!(vec![expected.clone()], todo);
+            assert!(repository.all("other").await.unwrap().is_empty());
+
+            // after
+            let todo = repository.after(DEFAULT_USER, None, 10).await.unwrap();
             assert_eq!(vec![expected], todo);
+            let todo = repository.after(DEFAULT_USER, Some(id), 10).await.unwrap();
+            assert!(todo.is_empty());
 
             // update
             let text = "update todo text".to_string();
             let todo = repository
                 .update(
-                    1,
+                    DEFAULT_USER,
+                    id,
                     UpdateTodo {
                         text: Some(text.clone()),
                         completed: Some(true),
+                        due_date: None,
+                        priority: None,
+                        recurrence: None,
+                        tags: None,
                     },
                 )
                 .await
@@ -435,15 +5538,292 @@ pub mod test_utils {
             assert_eq!(
                 Todo {
                     id,
+                    user_id: DEFAULT_USER.to_string(),
                     text,
-                    completed: true
+                    completed: true,
+                    parent_id: None,
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    position: 0,
+                    updated_at: todo.updated_at,
+                    tags: Tags::default(),
+                    completed_at: todo.completed_at,
                 },
                 todo
             );
+            assert!(todo.updated_at >= created_at);
+            assert!(todo
+                .completed_at
+                .is_some_and(|completed_at| completed_at >= created_at));
+
+            // stats
+            let stats = repository.stats().await.unwrap();
+            assert_eq!(
+                TodoStats {
+                    total: 1,
+                    completed: 1
+                },
+                stats
+            );
 
             // delete
-            let res = repository.delete(id).await;
+            assert!(repository.delete("other", id).await.is_err());
+            let res = repository.delete(DEFAULT_USER, id).await;
             assert!(res.is_ok());
         }
+
+        #[tokio::test]
+        async fn snapshot_and_restore() {
+            let repository = TodoRepositoryForMemory::new();
+            let todo = repository
+                .create(DEFAULT_USER, CreateTodo::new("kept".to_string()))
+                .await
+                .unwrap();
+            let snapshot = repository.snapshot();
+
+            repository
+                .create(DEFAULT_USER, CreateTodo::new("rolled back".to_string()))
+                .await
+                .unwrap();
+            repository.delete(DEFAULT_USER, todo.id).await.unwrap();
+            repository.restore(snapshot.clone());
+            assert_eq!(repository.all(DEFAULT_USER).await.unwrap(), vec![todo]);
+
+            repository.clear();
+            assert!(repository.all(DEFAULT_USER).await.unwrap().is_empty());
+            repository.restore(snapshot);
+            assert_eq!(repository.all(DEFAULT_USER).await.unwrap().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn completed_at_follows_completed() {
+            let repository = TodoRepositoryForMemory::new();
+            let todo = repository
+                .create(DEFAULT_USER, CreateTodo::new("todo".to_string()))
+                .await
+                .unwrap();
+            assert_eq!(todo.completed_at, None);
+            let update = |completed| UpdateTodo {
+                text: None,
+                completed: Some(completed),
+                due_date: None,
+                priority: None,
+                recurrence: None,
+                tags: None,
+            };
+
+            let completed = repository
+                .update(DEFAULT_USER, todo.id, update(true))
+                .await
+                .unwrap();
+            assert!(completed.completed_at.is_some());
+            // 完了のまま更新しても完了日時は変えない
+            let todo = repository
+                .update(DEFAULT_USER, todo.id, update(true))
+                .await
+                .unwrap();
+            assert_eq!(todo.completed_at, completed.completed_at);
+            let todo = repository
+                .update(DEFAULT_USER, todo.id, update(false))
+                .await
+                .unwrap();
+            assert_eq!(todo.completed_at, None);
+        }
+
+        #[cfg(not(feature = "uuid-id"))]
+        #[tokio::test]
+        async fn id_sequence() {
+            let repository = TodoRepositoryForMemory::new().with_id_sequence(100, 10);
+            let mut ids = Vec::new();
+            for text in ["first", "second"] {
+                let todo = repository
+                    .create(DEFAULT_USER, CreateTodo::new(text.to_string()))
+                    .await
+                    .unwrap();
+                ids.push(todo.id);
+            }
+            assert_eq!(ids, vec![TodoId::from(100), TodoId::from(110)]);
+
+            // 削除したIDは使い回さない
+            repository
+                .delete(DEFAULT_USER, TodoId::from(110))
+                .await
+                .unwrap();
+            let todo = repository
+                .create(DEFAULT_USER, CreateTodo::new("third".to_string()))
+                .await
+                .unwrap();
+            assert_eq!(todo.id, TodoId::from(120));
+        }
+
+        #[cfg(feature = "uuid-id")]
+        #[tokio::test]
+        async fn uuid_id() {
+            let repository = TodoRepositoryForMemory::new();
+            let mut ids = Vec::new();
+            for text in ["first", "second"] {
+                let todo = repository
+                    .create(DEFAULT_USER, CreateTodo::new(text.to_string()))
+                    .await
+                    .unwrap();
+                ids.push(todo.id);
+            }
+            assert_ne!(ids[0], ids[1]);
+            assert!(ids.iter().all(|id| id.0.get_version_num() == 4));
+
+            // パスの文字列とは相互に変換でき、数値は受け付けない
+            assert_eq!(ids[0].to_string().parse::<TodoId>().unwrap(), ids[0]);
+            assert!("1".parse::<TodoId>().is_err());
+        }
+
+        #[tokio::test]
+        async fn sort_by_id() {
+            for sort in [TodoSort::Position, TodoSort::Id] {
+                let repository = TodoRepositoryForMemory::new().with_sort(sort);
+                let mut created = Vec::new();
+                for text in ["first", "second"] {
+                    let todo = repository
+                        .create(DEFAULT_USER, CreateTodo::new(text.to_string()))
+                        .await
+                        .unwrap();
+                    created.push(todo.id);
+                }
+                repository
+                    .move_to(DEFAULT_USER, created[1], 0)
+                    .await
+                    .unwrap();
+                let expected = match sort {
+                    TodoSort::Position => vec![created[1], created[0]],
+                    TodoSort::Id => {
+                        let mut ids = created.clone();
+                        ids.sort();
+                        ids
+                    }
+                };
+                let ids: Vec<TodoId> = repository
+                    .all(DEFAULT_USER)
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|todo| todo.id)
+                    .collect();
+                assert_eq!(ids, expected, "sort: {:?}", sort);
+                // ページも一覧と同じ並び順で切り出す
+                let page: Vec<TodoId> = repository
+                    .page(DEFAULT_USER, 1, 0)
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|todo| todo.id)
+                    .collect();
+                assert_eq!(page, expected[..1], "sort: {:?}", sort);
+            }
+        }
+
+        #[tokio::test]
+        async fn transaction_is_all_or_nothing() {
+            let repository = TodoRepositoryForMemory::new();
+            let todo = repository
+                .create(DEFAULT_USER, CreateTodo::new("kept".to_string()))
+                .await
+                .unwrap();
+
+            // エラーなら途中の変更も残さない
+            let result: anyhow::Result<()> = repository.transaction(|store| {
+                insert_todo(
+                    store,
+                    &repository.ids,
+                    DEFAULT_USER,
+                    CreateTodo::new("a".to_string()),
+                );
+                Err(anyhow::anyhow!("failed"))
+            });
+            assert!(result.is_err());
+            assert_eq!(
+                repository.all(DEFAULT_USER).await.unwrap(),
+                vec![todo.clone()]
+            );
+
+            // パニックでも変更を残さず、ロックも使い続けられる
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let _ = repository.transaction(|store| -> anyhow::Result<()> {
+                    insert_todo(
+                        store,
+                        &repository.ids,
+                        DEFAULT_USER,
+                        CreateTodo::new("b".to_string()),
+                    );
+                    panic!("boom");
+                });
+            }));
+            assert!(result.is_err());
+            assert_eq!(repository.all(DEFAULT_USER).await.unwrap(), vec![todo]);
+
+            let todos = repository
+                .create_many(
+                    DEFAULT_USER,
+                    vec![
+                        CreateTodo::new("c".to_string()),
+                        CreateTodo::new("d".to_string()),
+                    ],
+                )
+                .await
+                .unwrap();
+            assert_eq!(todos.len(), 2);
+            assert_eq!(repository.count(DEFAULT_USER).await.unwrap(), 3);
+        }
+
+        /// 一括作成・取り込みでも、登録後の件数が上限を超えるなら何も登録しない
+        #[tokio::test]
+        async fn quota_covers_bulk_creation() {
+            let repository = TodoRepositoryForMemory::new().with_quota(3);
+            repository
+                .create(DEFAULT_USER, CreateTodo::new("first".to_string()))
+                .await
+                .unwrap();
+            let payloads = |n: usize| -> Vec<CreateTodo> {
+                (0..n)
+                    .map(|i| CreateTodo::new(format!("bulk {}", i)))
+                    .collect()
+            };
+
+            let error = repository
+                .create_many(DEFAULT_USER, payloads(3))
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::QuotaExceeded(3))
+            ));
+            assert_eq!(repository.count(DEFAULT_USER).await.unwrap(), 1);
+            repository
+                .create_many(DEFAULT_USER, payloads(2))
+                .await
+                .unwrap();
+
+            let dataset = Dataset {
+                todos: repository.all(DEFAULT_USER).await.unwrap(),
+                labels: vec![],
+                associations: vec![],
+            };
+            let error = repository
+                .import_dataset(DEFAULT_USER, dataset.clone(), false)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::QuotaExceeded(3))
+            ));
+            assert_eq!(repository.count(DEFAULT_USER).await.unwrap(), 3);
+
+            // 置き換えなら今あるTODOは数えない
+            let counts = repository
+                .import_dataset(DEFAULT_USER, dataset, true)
+                .await
+                .unwrap();
+            assert_eq!(counts.todos, 3);
+            assert_eq!(counts.replaced, 3);
+        }
     }
 }
@@ -0,0 +1,105 @@
+use serde_json::json;
+
+use crate::repositories::{
+    label::{CreateLabel, LabelRepository},
+    todo::{CreateTodo, TodoRepository, DEFAULT_USER},
+};
+
+/// 見本のラベル(名前と色)
+const SAMPLE_LABELS: &[(&str, &str)] = &[
+    ("work", "#4a90d9"),
+    ("home", "#7ed321"),
+    ("urgent", "#d0021b"),
+];
+
+/// 見本のTODO(本文・優先度・付けるラベル)
+const SAMPLE_TODOS: &[(&str, i32, &[&str])] = &[
+    ("Read the README", 1, &[]),
+    ("Write the weekly report", 2, &["work"]),
+    ("Fix the login bug", 3, &["work", "urgent"]),
+    ("Buy groceries", 1, &["home"]),
+    ("Pay the electricity bill", 2, &["home", "urgent"]),
+];
+
+/// 作成した見本データの件数
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Seeded {
+    pub todos: usize,
+    pub labels: usize,
+}
+
+/// 見本のTODOとラベルを作成する(起動時の --seed 用)
+/// 既定ユーザーのTODOかラベルが既にあれば何もしないので、何度実行してもよい
+pub async fn seed_demo_data<T: TodoRepository, L: LabelRepository>(
+    todo_repository: &T,
+    label_repository: &L,
+) -> anyhow::Result<Seeded> {
+    if todo_repository.count(DEFAULT_USER).await? > 0 || !label_repository.all().await?.is_empty() {
+        return Ok(Seeded::default());
+    }
+
+    let mut labels = Vec::new();
+    for (name, color) in SAMPLE_LABELS {
+        let payload: CreateLabel = serde_json::from_value(json!({ "name": name, "color": color }))?;
+        labels.push(label_repository.create(payload).await?);
+    }
+    for (text, priority, label_names) in SAMPLE_TODOS {
+        let payload: CreateTodo =
+            serde_json::from_value(json!({ "text": text, "priority": priority }))?;
+        let todo = todo_repository.create(DEFAULT_USER, payload).await?;
+        for label in labels
+            .iter()
+            .filter(|label| label_names.contains(&label.name.as_str()))
+        {
+            label_repository.add_label(todo.id(), label.id).await?;
+        }
+    }
+
+    Ok(Seeded {
+        todos: SAMPLE_TODOS.len(),
+        labels: labels.len(),
+    })
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "uuid-id"))]
+mod test {
+    use super::*;
+    use crate::repositories::{
+        label::test_utils::LabelRepositoryForMemory,
+        todo::{test_utils::TodoRepositoryForMemory, TodoId},
+    };
+
+    /// 空なら見本を作成し、2回目は何もしない
+    #[tokio::test]
+    async fn should_seed_only_empty_store() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+
+        let seeded = seed_demo_data(&todo_repository, &label_repository)
+            .await
+            .unwrap();
+        assert_eq!(
+            seeded,
+            Seeded {
+                todos: SAMPLE_TODOS.len(),
+                labels: SAMPLE_LABELS.len(),
+            }
+        );
+        let labels = label_repository
+            .find_by_todo(TodoId::from(3))
+            .await
+            .unwrap();
+        let names: Vec<_> = labels.iter().map(|label| label.name.as_str()).collect();
+        assert_eq!(names, vec!["work", "urgent"]);
+
+        let seeded = seed_demo_data(&todo_repository, &label_repository)
+            .await
+            .unwrap();
+        assert_eq!(seeded, Seeded::default());
+        assert_eq!(
+            todo_repository.count(DEFAULT_USER).await.unwrap(),
+            SAMPLE_TODOS.len() as i64
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use axum_server::Handle;
+use std::{env, future::Future, time::Duration};
+
+/// 終了時に処理中の接続を待つ時間(環境変数 SHUTDOWN_GRACE_MS、既定10秒)
+pub fn grace_from_env() -> Duration {
+    let millis = env::var("SHUTDOWN_GRACE_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(10_000);
+    Duration::from_millis(millis)
+}
+
+/// Ctrl+C か SIGTERM を受けるまで待つ
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("fail install Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("fail install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// signal が完了したら新しい接続の受け付けを止め、処理中の接続を grace だけ待つ
+/// 猶予を過ぎても残っている接続は件数をログに出して切断する
+pub fn spawn_graceful_shutdown<F>(handle: Handle, grace: Duration, signal: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        signal.await;
+        tracing::info!(
+            "shutting down, waiting up to {:?} for {} connections",
+            grace,
+            handle.connection_count()
+        );
+        handle.graceful_shutdown(None);
+        tokio::time::sleep(grace).await;
+        let active = handle.connection_count();
+        if active > 0 {
+            tracing::warn!(
+                "shutdown grace period expired, dropping {} active connections",
+                active
+            );
+        }
+        handle.shutdown();
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{routing::get, Router};
+    use hyper::Client;
+    use tokio::sync::oneshot;
+
+    /// 猶予を過ぎても終わらない接続は切断して停止する
+    #[tokio::test]
+    async fn should_drop_connections_after_grace() {
+        let app = Router::new().route("/", get(std::future::pending::<()>));
+        let handle = Handle::new();
+        let server = tokio::spawn(
+            axum_server::bind("127.0.0.1:0".parse().unwrap())
+                .handle(handle.clone())
+                .serve(app.into_make_service()),
+        );
+        let addr = handle.listening().await.unwrap();
+
+        // 応答しないリクエストで接続を残しておく
+        let uri = format!("http://{}/", addr).parse().unwrap();
+        tokio::spawn(async move { Client::new().get(uri).await });
+        while handle.connection_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let (sender, receiver) = oneshot::channel::<()>();
+        spawn_graceful_shutdown(handle, Duration::from_millis(50), async {
+            let _ = receiver.await;
+        });
+        sender.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop")
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use hyper::{
+    body::Body, client::HttpConnector, header::CONTENT_TYPE, Client, Method, Request, Uri,
+};
+use std::{env, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::{TodoEvent, TodoEvents};
+
+/// 送信の試行回数(初回を含む)
+const MAX_ATTEMPTS: u32 = 3;
+/// 再送までの待ち時間(試行ごとに倍にする)
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// TODO変更イベントの送信先
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: Uri,
+    client: Client<HttpConnector>,
+}
+
+impl Webhook {
+    /// new
+    pub fn new(url: Uri) -> Self {
+        Self {
+            url,
+            client: Client::new(),
+        }
+    }
+
+    /// 環境変数 WEBHOOK_URL から生成(未設定なら無効。http のURLのみ対応)
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("WEBHOOK_URL").ok().filter(|url| !url.is_empty())?;
+        match url.parse() {
+            Ok(url) => Some(Self::new(url)),
+            Err(e) => {
+                tracing::warn!("invalid WEBHOOK_URL {}: {}", url, e);
+                None
+            }
+        }
+    }
+
+    /// イベントをJSONでPOSTする(失敗したら待ち時間を延ばしながら再送する)
+    async fn send(&self, event: &TodoEvent) {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!("fail serialize webhook event: {}", e);
+                return;
+            }
+        };
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri(self.url.clone())
+                .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(body.clone()))
+                .expect("fail build webhook request");
+            match self.client.request(req).await {
+                Ok(res) if res.status().is_success() => return,
+                Ok(res) => tracing::warn!(
+                    "webhook {} returned {} (attempt {}/{})",
+                    self.url,
+                    res.status(),
+                    attempt,
+                    MAX_ATTEMPTS
+                ),
+                Err(e) => tracing::warn!(
+                    "fail send webhook {}: {} (attempt {}/{})",
+                    self.url,
+                    e,
+                    attempt,
+                    MAX_ATTEMPTS
+                ),
+            }
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+        tracing::error!("gave up sending webhook {}", self.url);
+    }
+}
+
+/// TODOの作成・更新・削除をWebhookへ送るタスクを起動する
+/// 送信はイベントごとに別タスクで行い、APIのレスポンスを待たせない
+pub fn spawn_webhook(webhook: Webhook, events: TodoEvents) {
+    let mut receiver = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("webhook skipped {} events", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let webhook = webhook.clone();
+            tokio::spawn(async move { webhook.send(&event).await });
+        }
+    });
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "uuid-id"))]
+mod test {
+    use super::*;
+    use crate::repositories::todo::Todo;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Response, Server,
+    };
+    use std::{convert::Infallible, net::SocketAddr};
+    use tokio::sync::mpsc;
+
+    /// イベントの種類とTODOを送る
+    #[tokio::test]
+    async fn should_post_event() {
+        let (sender, mut received) = mpsc::unbounded_channel();
+        let make_service = make_service_fn(move |_| {
+            let sender = sender.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let sender = sender.clone();
+                    async move {
+                        let bytes = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        sender.send(bytes).unwrap();
+                        Ok::<_, Infallible>(Response::new(Body::empty()))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let url = format!("http://{}/hook", server.local_addr())
+            .parse()
+            .unwrap();
+        tokio::spawn(server);
+
+        let events = TodoEvents::new();
+        spawn_webhook(Webhook::new(url), events.clone());
+        let todo = Todo::new(1, "webhook".to_string());
+        events.publish(TodoEvent::Created { todo });
+
+        let bytes = received.recv().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["type"], "created");
+        assert_eq!(body["todo"]["text"], "webhook");
+    }
+}